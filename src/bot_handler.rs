@@ -1,23 +1,56 @@
-use crate::store;
+use crate::config;
+use crate::dialogue_storage::SqliteDialogueStorage;
+use crate::i18n::{self, t, tf, Key, Lang, TemplateError};
+use crate::notifier::{self, Notifier};
+use crate::scheduler;
+use crate::store::{self, NotifyMode, NotifySlot};
 use crate::waste::WasteType;
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime};
+use futures::stream::StreamExt;
+use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use teloxide::{
-    dispatching::dialogue::InMemStorage,
+    net::Download,
     prelude::*,
-    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, InputFile, MaybeInaccessibleMessage},
     utils::command::BotCommands,
 };
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, info_span, instrument, warn, Instrument};
 
-type MyDialogue = Dialogue<State, InMemStorage<State>>;
+type MyDialogue = Dialogue<State, SqliteDialogueStorage>;
 type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
 
-#[derive(Clone, Default)]
+/// Longest vacation a user can set in one go.
+const MAX_MUTE_DAYS: i64 = 365;
+/// Largest /import file accepted; real exports are a few kilobytes.
+const MAX_IMPORT_BYTES: u32 = 64 * 1024;
+/// Lead time of a /remindbefore reminder when none is given, counted from `PICKUP_HOUR`.
+const DEFAULT_REMIND_BEFORE_HOURS: i64 = 12;
+/// How many upcoming pickups /remindbefore offers.
+const REMIND_BEFORE_CHOICES: usize = 5;
+/// Minimum time between two /refresh calls from the same chat, to spare the city's API.
+const REFRESH_COOLDOWN: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+/// Minimum time between two location IDs entered by the same chat during setup.
+const LOCATION_COOLDOWN: std::time::Duration = std::time::Duration::from_secs(5);
+/// Chats a cooldown remembers at most, so distinct chat IDs can't grow it without limit.
+const MAX_COOLDOWN_CHATS: usize = 10_000;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub enum State {
     #[default]
     Start,
     AwaitingLocationId,
     AwaitingLocationAlias(String), // Stores location_id while waiting for alias
+    AwaitingMuteDays,
+    AwaitingLeadHours(i64), // Stores the user_location id being edited
+    AwaitingFeedback,
+    AwaitingImport,
+    AwaitingTemplate,
 }
 
 #[derive(BotCommands, Clone)]
@@ -25,21 +58,71 @@ pub enum State {
 pub enum Command {
     #[command(description = "Start the bot and setup location.")]
     Start,
+    #[command(description = "Show this help text.")]
+    Help,
     #[command(description = "Add a new location.")]
     AddLocation,
+    #[command(description = "Add a location in one message: /setlocation <ID> [name].")]
+    SetLocation(String),
     #[command(description = "List your locations.")]
     Locations,
     #[command(description = "Manage your subscriptions.")]
     Settings,
+    #[command(description = "Subscribe all your locations to a waste type, e.g. /subscribe Bio.")]
+    Subscribe(String),
+    #[command(description = "Unsubscribe all your locations from a waste type.")]
+    Unsubscribe(String),
+    #[command(description = "Show a summary of your configuration.")]
+    Status,
+    #[command(description = "Show the next reminder you would receive.")]
+    Preview,
+    #[command(description = "Get an extra reminder before a pickup: /remindbefore [hours].")]
+    RemindBefore(String),
+    #[command(description = "Show how many reminders you have received.")]
+    MyStats,
+    #[command(description = "List the waste types and what goes in each bin.")]
+    Types,
+    #[command(description = "Send feedback or report a problem to the operator.")]
+    Feedback,
+    #[command(description = "Download all data stored about you as JSON.")]
+    Export,
+    #[command(description = "Restore your settings from an /export file.")]
+    Import,
     #[command(description = "Unsubscribe from all notifications and delete data.")]
     Stop,
+    #[command(description = "Cancel the current action.")]
+    Cancel,
+    #[command(description = "Fetch the latest pickup dates for your locations now.")]
+    Refresh,
+    #[command(description = "Show when the pickup dates for your locations were last updated.")]
+    LastUpdate,
+    #[command(hide)]
+    Broadcast(String),
+    #[command(hide)]
+    Feeds,
+    #[command(hide)]
+    Stats,
+    #[command(hide)]
+    Inspect(String),
+    #[command(hide)]
+    TestNotify(String),
+    #[command(hide)]
+    Renormalize,
+    #[command(hide)]
+    RawFeed(String),
 }
 
-pub async fn run_bot(bot: Bot, pool: SqlitePool) {
+/// Runs the dispatcher until `shutdown` is cancelled, then lets in-flight updates finish.
+pub async fn run_bot(
+    bot: Bot,
+    pool: SqlitePool,
+    notifier: Arc<Notifier>,
+    shutdown: CancellationToken,
+) {
     let pool = Arc::new(pool);
 
     let handler = Update::filter_message()
-        .enter_dialogue::<Message, InMemStorage<State>, State>()
+        .enter_dialogue::<Message, SqliteDialogueStorage, State>()
         .branch(
             dptree::entry()
                 .filter_command::<Command>()
@@ -50,353 +133,1782 @@ pub async fn run_bot(bot: Bot, pool: SqlitePool) {
             dptree::case![State::AwaitingLocationAlias(location_id)]
                 .endpoint(receive_alias_handler),
         )
+        .branch(dptree::case![State::AwaitingMuteDays].endpoint(receive_mute_days_handler))
+        .branch(
+            dptree::case![State::AwaitingLeadHours(loc_id)].endpoint(receive_lead_hours_handler),
+        )
+        .branch(dptree::case![State::AwaitingFeedback].endpoint(receive_feedback_handler))
+        .branch(dptree::case![State::AwaitingImport].endpoint(receive_import_handler))
+        .branch(dptree::case![State::AwaitingTemplate].endpoint(receive_template_handler))
         .branch(dptree::case![State::Start].endpoint(invalid_state_handler));
 
-    let callback_handler = Update::filter_callback_query().endpoint(callback_query_handler);
+    let callback_handler = Update::filter_callback_query()
+        .enter_dialogue::<CallbackQuery, SqliteDialogueStorage, State>()
+        .endpoint(callback_query_handler);
 
-    Dispatcher::builder(
+    let mut dispatcher = Dispatcher::builder(
         bot,
         dptree::entry().branch(handler).branch(callback_handler),
     )
-    .dependencies(dptree::deps![InMemStorage::<State>::new(), pool])
-    .enable_ctrlc_handler()
-    .build()
-    .dispatch()
-    .await;
+    .dependencies(dptree::deps![
+        SqliteDialogueStorage::new((*pool).clone()),
+        pool,
+        notifier,
+        Cooldowns::default()
+    ])
+    .build();
+
+    let shutdown_token = dispatcher.shutdown_token();
+    tokio::spawn(async move {
+        shutdown.cancelled().await;
+        // Fails only if the dispatcher isn't running (yet), in which case there's nothing to stop.
+        if let Ok(stopped) = shutdown_token.shutdown() {
+            stopped.await;
+        }
+    });
+
+    dispatcher.dispatch().await;
 }
 
+#[instrument(skip_all, fields(chat_id = msg.chat.id.0))]
 async fn command_handler(
     bot: Bot,
     dialogue: MyDialogue,
     msg: Message,
     cmd: Command,
     pool: Arc<SqlitePool>,
+    notifier: Arc<Notifier>,
+    cooldowns: Cooldowns,
 ) -> HandlerResult {
+    let lang = store::get_language(&pool, msg.chat.id.0).await?;
     match cmd {
         Command::Start | Command::AddLocation => {
-            bot.send_message(msg.chat.id, "Please enter your Location ID (Standort-ID). You can find it on the Dresden waste management website.")
+            bot.send_message(msg.chat.id, t(Key::EnterLocationId, lang))
                 .await?;
             dialogue.update(State::AwaitingLocationId).await?;
         }
+        Command::SetLocation(args) => {
+            let Some((location_id, alias)) = split_set_location_args(&args) else {
+                bot.send_message(msg.chat.id, t(Key::EnterLocationId, lang))
+                    .await?;
+                dialogue.update(State::AwaitingLocationId).await?;
+                return Ok(());
+            };
+            let location_id = match crate::waste::extract_location_id(location_id) {
+                Ok(location_id) => location_id,
+                Err(e) => {
+                    bot.send_message(msg.chat.id, t(location_id_problem(&e), lang))
+                        .await?;
+                    return Ok(());
+                }
+            };
+            if cooldowns
+                .location
+                .try_acquire(msg.chat.id.0, Instant::now())
+                .is_err()
+            {
+                bot.send_message(msg.chat.id, t(Key::PleaseWait, lang))
+                    .await?;
+                return Ok(());
+            }
+            if let Some(problem) = alias.and_then(check_alias) {
+                bot.send_message(msg.chat.id, t(problem, lang)).await?;
+                return Ok(());
+            }
+            finish_add_location(bot, msg.chat.id, &pool, &location_id, alias).await?;
+            dialogue.exit().await?;
+        }
+        Command::Help => {
+            bot.send_message(
+                msg.chat.id,
+                format!("{}\n\n{}", Command::descriptions(), t(Key::HelpText, lang)),
+            )
+            .await?;
+        }
         Command::Locations => {
             list_locations_handler(bot, &msg.chat.id, &pool).await?;
         }
         Command::Settings => {
             list_locations_handler(bot, &msg.chat.id, &pool).await?;
         }
-        Command::Stop => {
-            store::delete_user(&pool, msg.chat.id.0).await?;
-            bot.send_message(
+        Command::Subscribe(args) => {
+            subscribe_handler(bot, msg.chat.id, &pool, &args, true).await?;
+        }
+        Command::Unsubscribe(args) => {
+            subscribe_handler(bot, msg.chat.id, &pool, &args, false).await?;
+        }
+        Command::Status => {
+            status_handler(bot, msg.chat.id, &pool).await?;
+        }
+        Command::LastUpdate => {
+            last_update_handler(bot, msg.chat.id, &pool, lang).await?;
+        }
+        Command::Export => {
+            let Some(export) = store::export_user(&pool, msg.chat.id.0).await? else {
+                bot.send_message(msg.chat.id, t(Key::ExportNothing, lang))
+                    .await?;
+                return Ok(());
+            };
+            let json = serde_json::to_vec_pretty(&export)?;
+            bot.send_document(
                 msg.chat.id,
-                "You have been unsubscribed and your data deleted.",
+                InputFile::memory(json).file_name("dumpdate-export.json"),
             )
+            .caption(t(Key::ExportCaption, lang))
             .await?;
         }
-    }
-    Ok(())
-}
-
-async fn receive_location_id_handler(
-    bot: Bot,
-    dialogue: MyDialogue,
-    msg: Message,
-) -> HandlerResult {
-    if let Some(text) = msg.text() {
-        let location_id = text.trim().to_string();
-        if !crate::waste::is_valid_location_id(&location_id) {
+        Command::Import => {
+            bot.send_message(msg.chat.id, t(Key::ImportPrompt, lang))
+                .await?;
+            dialogue.update(State::AwaitingImport).await?;
+        }
+        Command::Preview => {
+            let now = Local::now().naive_local();
+            let text = match scheduler::preview_notification(&pool, msg.chat.id.0, now).await? {
+                Some((slot, message)) => tf(
+                    Key::PreviewHeader,
+                    lang,
+                    &[
+                        &lang.format_date(slot.date()),
+                        &slot.format("%H:%M"),
+                        &message,
+                    ],
+                ),
+                None => tf(Key::PreviewNone, lang, &[&scheduler::PREVIEW_HOURS]),
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::RemindBefore(args) => {
+            let Some(hours) = parse_remind_before_hours(&args) else {
+                bot.send_message(
+                    msg.chat.id,
+                    tf(Key::RemindBeforeUsage, lang, &[&store::MAX_LEAD_HOURS]),
+                )
+                .await?;
+                return Ok(());
+            };
+            remind_before_handler(bot, msg.chat.id, &pool, hours, lang).await?;
+        }
+        Command::MyStats => {
+            let stats = store::notification_stats(&pool, msg.chat.id.0).await?;
+            let text = match stats.last_sent {
+                Some(last) => tf(
+                    Key::MyStats,
+                    lang,
+                    &[
+                        &stats.reminders,
+                        &lang.format_date(last.date()),
+                        &last.format("%H:%M"),
+                    ],
+                ),
+                None => t(Key::MyStatsNone, lang).to_string(),
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::Types => {
+            bot.send_message(msg.chat.id, types_text(lang)).await?;
+        }
+        Command::Feedback => {
+            if config::admin_chat_id().is_none() {
+                bot.send_message(msg.chat.id, t(Key::FeedbackNotConfigured, lang))
+                    .await?;
+                return Ok(());
+            }
+            bot.send_message(msg.chat.id, t(Key::FeedbackPrompt, lang))
+                .await?;
+            dialogue.update(State::AwaitingFeedback).await?;
+        }
+        Command::Stop => {
+            // Deleting is irreversible, so ask first; the actual delete happens in `confirm_stop`.
+            let keyboard = InlineKeyboardMarkup::new(vec![vec![
+                InlineKeyboardButton::callback(t(Key::StopConfirmButton, lang), "confirm_stop"),
+                InlineKeyboardButton::callback(t(Key::CancelButton, lang), "cancel_stop"),
+            ]]);
+            bot.send_message(msg.chat.id, t(Key::StopConfirm, lang))
+                .reply_markup(keyboard)
+                .await?;
+        }
+        Command::Refresh => {
+            if let Err(remaining) = cooldowns.refresh.try_acquire(msg.chat.id.0, Instant::now()) {
+                let minutes = remaining.as_secs().div_ceil(60);
+                bot.send_message(msg.chat.id, tf(Key::RefreshCooldown, lang, &[&minutes]))
+                    .await?;
+                return Ok(());
+            }
+            refresh_handler(bot, msg.chat.id, &pool, lang).await?;
+        }
+        Command::Cancel => {
+            // Commands are matched before the dialogue states, so this also works mid-dialogue.
+            // Exiting fails if nothing is stored, e.g. when there's no dialogue to cancel.
+            if dialogue.get().await?.is_some() {
+                dialogue.exit().await?;
+            }
+            bot.send_message(msg.chat.id, t(Key::Cancelled, lang))
+                .await?;
+        }
+        Command::Broadcast(text) => {
+            if config::admin_chat_id() != Some(msg.chat.id) {
+                bot.send_message(msg.chat.id, t(Key::AdminOnly, lang))
+                    .await?;
+                return Ok(());
+            }
+            let text = text.trim();
+            if text.is_empty() {
+                bot.send_message(msg.chat.id, t(Key::BroadcastUsage, lang))
+                    .await?;
+                return Ok(());
+            }
+            let report = broadcast(&notifier, &pool, text, lang).await?;
+            bot.send_message(msg.chat.id, report).await?;
+        }
+        Command::Feeds => {
+            if config::admin_chat_id() != Some(msg.chat.id) {
+                bot.send_message(msg.chat.id, t(Key::AdminOnly, lang))
+                    .await?;
+                return Ok(());
+            }
+            feeds_handler(bot, msg.chat.id, &pool, lang).await?;
+        }
+        Command::Stats => {
+            if config::admin_chat_id() != Some(msg.chat.id) {
+                bot.send_message(msg.chat.id, t(Key::AdminOnly, lang))
+                    .await?;
+                return Ok(());
+            }
+            stats_handler(bot, msg.chat.id, &pool, lang).await?;
+        }
+        Command::Inspect(args) => {
+            if config::admin_chat_id() != Some(msg.chat.id) {
+                bot.send_message(msg.chat.id, t(Key::AdminOnly, lang))
+                    .await?;
+                return Ok(());
+            }
+            let Ok(target) = args.trim().parse::<i64>() else {
+                bot.send_message(msg.chat.id, t(Key::InspectUsage, lang))
+                    .await?;
+                return Ok(());
+            };
+            inspect_handler(bot, msg.chat.id, &pool, target, lang).await?;
+        }
+        Command::TestNotify(args) => {
+            if config::admin_chat_id() != Some(msg.chat.id) {
+                bot.send_message(msg.chat.id, t(Key::AdminOnly, lang))
+                    .await?;
+                return Ok(());
+            }
+            let Some(slot) = scheduler::parse_slot(&args) else {
+                bot.send_message(msg.chat.id, t(Key::TestNotifyUsage, lang))
+                    .await?;
+                return Ok(());
+            };
+            let sent = scheduler::test_dispatch(&notifier, &pool, &slot, msg.chat.id, lang).await?;
+            bot.send_message(msg.chat.id, tf(Key::TestNotifyDone, lang, &[&slot, &sent]))
+                .await?;
+        }
+        Command::Renormalize => {
+            if config::admin_chat_id() != Some(msg.chat.id) {
+                bot.send_message(msg.chat.id, t(Key::AdminOnly, lang))
+                    .await?;
+                return Ok(());
+            }
+            let counts = store::renormalize_waste_types(&pool).await?;
+            info!("Renormalized waste types: {:?}", counts);
             bot.send_message(
                 msg.chat.id,
-                "Invalid Location ID. It must be alphanumeric and max 20 characters.",
+                tf(
+                    Key::RenormalizeDone,
+                    lang,
+                    &[
+                        &counts.subscriptions,
+                        &counts.events,
+                        &counts.notified,
+                        &counts.retries,
+                        &counts.snoozes,
+                    ],
+                ),
             )
             .await?;
-            return Ok(());
         }
-
-        bot.send_message(
-            msg.chat.id,
-            "Please give this location a short alias (e.g., 'Home', 'Office').",
-        )
-        .await?;
-
-        dialogue
-            .update(State::AwaitingLocationAlias(location_id))
+        Command::RawFeed(args) => {
+            if config::admin_chat_id() != Some(msg.chat.id) {
+                bot.send_message(msg.chat.id, t(Key::AdminOnly, lang))
+                    .await?;
+                return Ok(());
+            }
+            let location_id = args.trim();
+            if !crate::waste::is_valid_location_id(location_id) {
+                bot.send_message(msg.chat.id, t(Key::RawFeedUsage, lang))
+                    .await?;
+                return Ok(());
+            }
+            let Some((body, fetched_at)) = store::get_raw_ical(&pool, location_id).await? else {
+                bot.send_message(msg.chat.id, tf(Key::RawFeedNone, lang, &[&location_id]))
+                    .await?;
+                return Ok(());
+            };
+            let fetched_at = fetched_at.and_utc().with_timezone(&Local);
+            bot.send_document(
+                msg.chat.id,
+                InputFile::memory(body).file_name(format!("{}.ics", location_id)),
+            )
+            .caption(tf(
+                Key::RawFeedCaption,
+                lang,
+                &[&location_id, &fetched_at.format("%d.%m.%Y %H:%M")],
+            ))
             .await?;
+        }
     }
     Ok(())
 }
 
-async fn receive_alias_handler(
-    bot: Bot,
-    dialogue: MyDialogue,
-    msg: Message,
-    pool: Arc<SqlitePool>,
-    location_id: String,
-) -> HandlerResult {
-    if let Some(alias) = msg.text() {
-        let alias = alias.trim();
+/// Sends `text` to every user through the shared rate limiter and returns a report
+/// for the admin. Users who blocked the bot are removed, as in `dispatch_notifications`.
+async fn broadcast(
+    notifier: &Notifier,
+    pool: &SqlitePool,
+    text: &str,
+    lang: Lang,
+) -> anyhow::Result<String> {
+    let chat_ids = store::get_all_chat_ids(pool).await?;
+    let total = chat_ids.len();
+    let reached = AtomicUsize::new(0);
+    let removed = AtomicUsize::new(0);
+    let budget = &notifier::BackoffBudget::new(notifier::MAX_BATCH_BACKOFF);
 
-        if alias.len() > 50 {
-            bot.send_message(
-                msg.chat.id,
-                "Alias is too long. Please keep it under 50 characters.",
-            )
-            .await?;
-            return Ok(());
-        }
+    futures::stream::iter(chat_ids)
+        .for_each_concurrent(15, |chat| {
+            let (reached, removed) = (&reached, &removed);
+            let span = info_span!("broadcast", chat_id = chat);
+            async move {
+                match notifier
+                    .send_within(ChatId(chat), text.to_string(), None, budget)
+                    .await
+                {
+                    Ok(_) => {
+                        reached.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) if notifier::is_unreachable(&e) => {
+                        info!("User {} blocked bot or is deactivated. Removing...", chat);
+                        let _ = store::delete_user(pool, chat).await;
+                        removed.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => error!("Failed to broadcast to {}: {:?}", chat, e),
+                }
+            }
+            .instrument(span)
+        })
+        .await;
 
-        if alias.chars().any(|c| c.is_control()) {
-            bot.send_message(
-                msg.chat.id,
-                "Alias contains invalid characters. Please use standard text.",
-            )
-            .await?;
-            return Ok(());
+    Ok(tf(
+        Key::BroadcastReport,
+        lang,
+        &[&reached.into_inner(), &total, &removed.into_inner()],
+    ))
+}
+
+fn types_text(lang: Lang) -> String {
+    let mut text = String::from(t(Key::TypesHeader, lang));
+    for waste in WasteType::supported_types() {
+        text.push_str(&format!("\n{}: {}", waste.label(), waste.description()));
+    }
+    text
+}
+
+/// Per-chat throttles for the requests that end up calling the city's API.
+#[derive(Clone)]
+struct Cooldowns {
+    refresh: Cooldown,
+    location: Cooldown,
+}
+
+impl Default for Cooldowns {
+    fn default() -> Self {
+        Cooldowns {
+            refresh: Cooldown::new(REFRESH_COOLDOWN),
+            location: Cooldown::new(LOCATION_COOLDOWN),
         }
+    }
+}
 
-        match store::add_user_location(&pool, msg.chat.id.0, &location_id, Some(alias)).await {
-            Ok(user_loc_id) => {
-                for waste in WasteType::default_subscriptions() {
-                    store::add_subscription(&pool, user_loc_id, waste.as_str()).await?;
-                }
+/// Remembers when each chat last did something that may only happen once per `period`.
+#[derive(Clone)]
+struct Cooldown {
+    period: std::time::Duration,
+    last: Arc<Mutex<HashMap<i64, Instant>>>,
+}
 
-                bot.send_message(
-                    msg.chat.id,
-                    format!(
-                        "Location '{}' ({}) added with default subscriptions.",
-                        alias, location_id
-                    ),
-                )
-                .await?;
+impl Cooldown {
+    fn new(period: std::time::Duration) -> Self {
+        Cooldown {
+            period,
+            last: Arc::default(),
+        }
+    }
 
-                list_locations_handler(bot, &msg.chat.id, &pool).await?;
-                dialogue.exit().await?;
+    /// Records an attempt at `now`, or returns how long the chat still has to wait.
+    fn try_acquire(&self, chat_id: i64, now: Instant) -> Result<(), std::time::Duration> {
+        let mut last = self.last.lock().unwrap();
+        if let Some(previous) = last.get(&chat_id) {
+            let elapsed = now.saturating_duration_since(*previous);
+            if elapsed < self.period {
+                return Err(self.period - elapsed);
             }
-            Err(e) => {
-                bot.send_message(msg.chat.id, format!("Error adding location: {}", e))
-                    .await?;
-                dialogue.exit().await?;
+        }
+        if last.len() >= MAX_COOLDOWN_CHATS {
+            // Chats whose cooldown has run out can be forgotten; if every one is still
+            // cooling down, give up the oldest rather than grow.
+            last.retain(|_, at| now.saturating_duration_since(*at) < self.period);
+            if last.len() >= MAX_COOLDOWN_CHATS {
+                let oldest = last
+                    .iter()
+                    .min_by_key(|(_, at)| **at)
+                    .map(|(chat, _)| *chat);
+                if let Some(oldest) = oldest {
+                    last.remove(&oldest);
+                }
             }
         }
+        last.insert(chat_id, now);
+        Ok(())
+    }
+}
+
+/// Admin overview of every configured location's feed freshness.
+async fn feeds_handler(bot: Bot, chat_id: ChatId, pool: &SqlitePool, lang: Lang) -> HandlerResult {
+    let today = Local::now().date_naive();
+    let mut text = String::from(t(Key::FeedsHeader, lang));
+    for loc_id in store::get_active_location_ids(pool).await? {
+        let last_update = match store::get_last_update(pool, &loc_id).await? {
+            Some(at) => at.format("%Y-%m-%d %H:%M UTC").to_string(),
+            None => t(Key::Never, lang).to_string(),
+        };
+        let upcoming = store::count_upcoming_events(pool, &loc_id, today).await?;
+        text.push('\n');
+        text.push_str(&tf(
+            Key::FeedsLine,
+            lang,
+            &[&loc_id, &last_update, &upcoming],
+        ));
+    }
+
+    for chunk in notifier::split_message(&text, notifier::MAX_MESSAGE_CHARS) {
+        bot.send_message(chat_id, chunk).await?;
     }
     Ok(())
 }
 
-async fn invalid_state_handler(bot: Bot, msg: Message) -> HandlerResult {
-    bot.send_message(msg.chat.id, "Please use /start or /addlocation to begin.")
-        .await?;
+/// How many locations /stats lists.
+const STATS_TOP_LOCATIONS: usize = 10;
+
+/// Admin overview of users and the locations they use most.
+async fn stats_handler(bot: Bot, chat_id: ChatId, pool: &SqlitePool, lang: Lang) -> HandlerResult {
+    let users = store::count_users(pool).await?;
+    let per_location = store::users_per_location(pool).await?;
+    let mut text = tf(Key::StatsHeader, lang, &[&users, &per_location.len()]);
+    for (loc_id, count) in per_location.iter().take(STATS_TOP_LOCATIONS) {
+        text.push('\n');
+        text.push_str(&tf(Key::StatsLine, lang, &[loc_id, count]));
+    }
+
+    bot.send_message(chat_id, text).await?;
     Ok(())
 }
 
-async fn list_locations_handler(bot: Bot, chat_id: &ChatId, pool: &SqlitePool) -> HandlerResult {
-    let locations = store::get_user_locations(pool, chat_id.0).await?;
-    if locations.is_empty() {
-        bot.send_message(*chat_id, "You have no locations set up. Use /addlocation.")
+/// Admin view of another chat's settings, locations and next reminder, for support.
+async fn inspect_handler(
+    bot: Bot,
+    chat_id: ChatId,
+    pool: &SqlitePool,
+    target: i64,
+    lang: Lang,
+) -> HandlerResult {
+    let Some(user) = store::get_user(pool, target).await? else {
+        bot.send_message(chat_id, tf(Key::InspectUnknown, lang, &[&target]))
             .await?;
         return Ok(());
+    };
+    let since = user.created_at.map_or_else(
+        || "-".to_string(),
+        |created| lang.format_date(created.date()),
+    );
+    let mute = user
+        .mute_until
+        .map_or_else(|| "-".to_string(), |until| lang.format_date(until));
+    let mut text = tf(
+        Key::InspectUser,
+        lang,
+        &[
+            &user.id,
+            &since,
+            &user.language.code(),
+            &user.notify_mode.code(),
+            &mute,
+        ],
+    );
+
+    let now = Local::now().naive_local();
+    for loc in store::get_user_locations(pool, target).await? {
+        let subs = store::get_subscriptions(pool, loc.id).await?;
+        let (notify_time, notify_offset) = loc.effective_slot();
+        text.push_str(&tf(
+            Key::StatusLocation,
+            lang,
+            &[
+                &loc.alias.as_deref().unwrap_or(&loc.location_id),
+                &loc.describe(),
+                &notify_time,
+                &t(day_key(notify_offset), lang),
+                &subscriptions_label(&subs, lang),
+            ],
+        ));
+        text.push_str(
+            &match store::get_next_pickup(pool, loc.id, now.date()).await? {
+                Some((date, types)) => tf(
+                    Key::InspectNextPickup,
+                    lang,
+                    &[&lang.format_date(date), &subscriptions_label(&types, lang)],
+                ),
+                None => t(Key::InspectNoPickup, lang).to_string(),
+            },
+        );
     }
 
-    bot.send_message(*chat_id, "Your Locations:")
-        .reply_markup(build_locations_keyboard(&locations))
-        .await?;
+    text.push_str("\n\n");
+    text.push_str(
+        &match scheduler::preview_notification(pool, target, now).await? {
+            Some((slot, message)) => tf(
+                Key::PreviewHeader,
+                lang,
+                &[
+                    &lang.format_date(slot.date()),
+                    &slot.format("%H:%M"),
+                    &message,
+                ],
+            ),
+            None => tf(Key::PreviewNone, lang, &[&scheduler::PREVIEW_HOURS]),
+        },
+    );
 
+    for chunk in notifier::split_message(&text, notifier::MAX_MESSAGE_CHARS) {
+        bot.send_message(chat_id, chunk).await?;
+    }
     Ok(())
 }
 
-async fn show_location_settings(
-    bot: &Bot,
+async fn refresh_handler(
+    bot: Bot,
     chat_id: ChatId,
-    message_id: Option<teloxide::types::MessageId>,
     pool: &SqlitePool,
-    loc_id: i64,
+    lang: Lang,
 ) -> HandlerResult {
     let locations = store::get_user_locations(pool, chat_id.0).await?;
-    let loc = locations.iter().find(|l| l.id == loc_id);
+    if locations.is_empty() {
+        bot.send_message(chat_id, t(Key::NoLocationsStatus, lang))
+            .await?;
+        return Ok(());
+    }
 
-    if let Some(loc) = loc {
-        let subs = store::get_subscriptions(pool, loc_id).await?;
-        let keyboard = build_settings_keyboard(loc_id, &subs, &loc.notify_time, loc.notify_offset);
+    let client = scheduler::build_http_client()?;
+    let mut lines = Vec::new();
+    for loc in &locations {
+        let label = loc.alias.as_deref().unwrap_or(&loc.location_id);
+        let line = match scheduler::update_location_ical(pool, &client, &loc.location_id).await {
+            Ok(count) => tf(Key::RefreshLoaded, lang, &[&label, &count]),
+            Err(e) if matches!(e.downcast_ref(), Some(scheduler::FeedError::HtmlPage(_))) => {
+                warn!(
+                    "Manual refresh of {}: the ID seems invalid",
+                    loc.location_id
+                );
+                tf(
+                    Key::RefreshUnknownLocation,
+                    lang,
+                    &[&label, &loc.location_id],
+                )
+            }
+            Err(e) => {
+                error!("Manual refresh of {} failed: {:?}", loc.location_id, e);
+                tf(Key::RefreshFailed, lang, &[&label])
+            }
+        };
+        lines.push(line);
+    }
 
-        let text = format!(
-            "Settings for {}:",
-            loc.alias.as_deref().unwrap_or(&loc.location_id)
-        );
+    bot.send_message(chat_id, lines.join("\n")).await?;
+    Ok(())
+}
 
-        if let Some(mid) = message_id {
-            bot.edit_message_text(chat_id, mid, text)
-                .reply_markup(keyboard)
-                .await?;
-        } else {
-            bot.send_message(chat_id, text)
-                .reply_markup(keyboard)
+async fn receive_location_id_handler(
+    bot: Bot,
+    dialogue: MyDialogue,
+    msg: Message,
+    pool: Arc<SqlitePool>,
+    cooldowns: Cooldowns,
+) -> HandlerResult {
+    if let Some(text) = msg.text() {
+        let lang = store::get_language(&pool, msg.chat.id.0).await?;
+        let location_id = match crate::waste::extract_location_id(text) {
+            Ok(location_id) => location_id,
+            Err(e) => {
+                bot.send_message(msg.chat.id, t(location_id_problem(&e), lang))
+                    .await?;
+                return Ok(());
+            }
+        };
+        if cooldowns
+            .location
+            .try_acquire(msg.chat.id.0, Instant::now())
+            .is_err()
+        {
+            bot.send_message(msg.chat.id, t(Key::PleaseWait, lang))
                 .await?;
+            return Ok(());
         }
-    } else if let Some(mid) = message_id {
-        bot.edit_message_text(chat_id, mid, "Location not found.")
+
+        bot.send_message(msg.chat.id, t(Key::AliasPrompt, lang))
+            .await?;
+
+        dialogue
+            .update(State::AwaitingLocationAlias(location_id))
             .await?;
     }
     Ok(())
 }
 
-async fn callback_query_handler(
+async fn receive_alias_handler(
     bot: Bot,
-    q: CallbackQuery,
+    dialogue: MyDialogue,
+    msg: Message,
     pool: Arc<SqlitePool>,
+    location_id: String,
 ) -> HandlerResult {
-    if let Some(data) = q.data.clone() {
-        let parts: Vec<&str> = data.split(':').collect();
-        let action = parts[0];
-        let chat_id = q.message.as_ref().map(|m| m.chat().id).unwrap_or(ChatId(0));
+    if let Some(alias) = msg.text() {
+        let lang = store::get_language(&pool, msg.chat.id.0).await?;
+        let alias = alias.trim();
 
-        if chat_id.0 == 0 {
+        if let Some(problem) = check_alias(alias) {
+            bot.send_message(msg.chat.id, t(problem, lang)).await?;
             return Ok(());
         }
 
-        match action {
-            "edit" => {
-                if parts.len() > 1 {
-                    if let Ok(loc_id) = parts[1].parse::<i64>() {
-                        show_location_settings(
-                            &bot,
-                            chat_id,
-                            q.message.as_ref().map(|m| m.id()),
-                            &pool,
-                            loc_id,
-                        )
-                        .await?;
-                        bot.answer_callback_query(q.id).await?;
-                    }
-                }
-            }
-            "back" => {
-                let locations = store::get_user_locations(&pool, chat_id.0).await?;
-                if let Some(message) = q.message {
-                    bot.edit_message_text(chat_id, message.id(), "Your Locations:")
-                        .reply_markup(build_locations_keyboard(&locations))
-                        .await?;
-                }
-                bot.answer_callback_query(q.id).await?;
-            }
-            "sub" => {
-                if parts.len() > 2 {
-                    let loc_id = parts[1].parse::<i64>()?;
-                    store::add_subscription(&pool, loc_id, parts[2]).await?;
-                    refresh_settings(&bot, &q, chat_id, &pool, loc_id, "Subscribed!").await?;
-                }
-            }
-            "unsub" => {
-                if parts.len() > 2 {
-                    let loc_id = parts[1].parse::<i64>()?;
-                    store::remove_subscription(&pool, loc_id, parts[2]).await?;
-                    refresh_settings(&bot, &q, chat_id, &pool, loc_id, "Unsubscribed!").await?;
-                }
-            }
-            "time" => {
-                if parts.len() > 2 {
-                    let loc_id = parts[1].parse::<i64>()?;
-                    let current_time = parts[2];
-                    let next_time = increment_time(current_time);
+        finish_add_location(bot, msg.chat.id, &pool, &location_id, Some(alias)).await?;
+        dialogue.exit().await?;
+    }
+    Ok(())
+}
 
-                    let locations = store::get_user_locations(&pool, chat_id.0).await?;
-                    if let Some(loc) = locations.iter().find(|l| l.id == loc_id) {
-                        store::update_notify_time(&pool, chat_id.0, &loc.location_id, &next_time)
-                            .await?;
-                        refresh_settings(&bot, &q, chat_id, &pool, loc_id, "Time updated!").await?;
-                    }
-                }
-            }
-            "offset" => {
-                 if parts.len() > 2 {
-                    let loc_id = parts[1].parse::<i64>()?;
-                    let current_offset = parts[2].parse::<i64>().unwrap_or(1);
-                    // toggle offset: if 1 (Day Before) -> 0 (Same Day), and vice versa.
-                    let next_offset = if current_offset == 1 { 0 } else { 1 };
+/// What to tell the user when no single location ID could be read from their message.
+fn location_id_problem(error: &crate::waste::LocationIdError) -> Key {
+    match error {
+        crate::waste::LocationIdError::Missing => Key::InvalidLocationId,
+        crate::waste::LocationIdError::Ambiguous => Key::AmbiguousLocationId,
+    }
+}
 
-                    let locations = store::get_user_locations(&pool, chat_id.0).await?;
-                    if let Some(loc) = locations.iter().find(|l| l.id == loc_id) {
-                        store::update_notify_offset(&pool, chat_id.0, &loc.location_id, next_offset)
-                            .await?;
-                         refresh_settings(&bot, &q, chat_id, &pool, loc_id, "Day updated!").await?;
-                    }
-                }
-            }
-            "delloc" => {
-                if parts.len() > 1 {
-                    if let Ok(loc_id) = parts[1].parse::<i64>() {
-                        let locations = store::get_user_locations(&pool, chat_id.0).await?;
-                        if let Some(loc) = locations.iter().find(|l| l.id == loc_id) {
-                            store::delete_user_location(&pool, chat_id.0, &loc.location_id).await?;
-
-                            let locations = store::get_user_locations(&pool, chat_id.0).await?;
-                            if let Some(message) = q.message {
-                                if locations.is_empty() {
-                                    bot.edit_message_text(
-                                        chat_id,
-                                        message.id(),
-                                        "No locations left.",
-                                    )
-                                    .reply_markup(InlineKeyboardMarkup::default())
-                                    .await?;
-                                } else {
-                                    bot.edit_message_text(
-                                        chat_id,
-                                        message.id(),
-                                        "Your Locations:",
-                                    )
-                                    .reply_markup(build_locations_keyboard(&locations))
-                                    .await?;
-                                }
-                            }
-                            bot.answer_callback_query(q.id)
-                                .text("Location deleted.")
-                                .await?;
-                        }
-                    }
-                }
-            }
-            _ => {}
-        }
+/// Why `alias` can't be used as a location name, if it can't.
+fn check_alias(alias: &str) -> Option<Key> {
+    if alias.len() > 50 {
+        Some(Key::AliasTooLong)
+    } else if alias.chars().any(|c| c.is_control()) {
+        Some(Key::AliasInvalid)
+    } else {
+        None
     }
-    Ok(())
 }
 
-fn increment_time(time: &str) -> String {
-    let parts: Vec<&str> = time.split(':').collect();
-    if parts.len() != 2 {
-        return "18:00".to_string();
+/// Splits the argument of `/setlocation` into the location ID and an optional name.
+/// `None` if no ID was given.
+fn split_set_location_args(args: &str) -> Option<(&str, Option<&str>)> {
+    let args = args.trim();
+    if args.is_empty() {
+        return None;
     }
-    let mut hour: u8 = parts[0].parse().unwrap_or(18);
-    hour += 1;
-    if hour >= 24 {
-        hour = 0;
+    match args.split_once(char::is_whitespace) {
+        Some((location_id, alias)) => Some((location_id, Some(alias.trim()))),
+        None => Some((args, None)),
     }
-    format!("{:02}:00", hour)
 }
 
-async fn refresh_settings(
-    bot: &Bot,
-    q: &CallbackQuery,
+/// Adds the location with default subscriptions and shows the updated location list.
+async fn finish_add_location(
+    bot: Bot,
     chat_id: ChatId,
     pool: &SqlitePool,
+    location_id: &str,
+    alias: Option<&str>,
+) -> HandlerResult {
+    let lang = store::get_language(pool, chat_id.0).await?;
+    match store::add_user_location_with_defaults(pool, chat_id.0, location_id, alias).await {
+        Ok(_) => {
+            let label = alias.unwrap_or(location_id);
+            bot.send_message(
+                chat_id,
+                tf(Key::LocationAdded, lang, &[&label, &location_id]),
+            )
+            .await?;
+            list_locations_handler(bot, &chat_id, pool).await?;
+        }
+        Err(e) => {
+            bot.send_message(chat_id, tf(Key::LocationAddError, lang, &[&e]))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn receive_mute_days_handler(
+    bot: Bot,
+    dialogue: MyDialogue,
+    msg: Message,
+    pool: Arc<SqlitePool>,
+) -> HandlerResult {
+    if let Some(text) = msg.text() {
+        let lang = store::get_language(&pool, msg.chat.id.0).await?;
+        let days = match text.trim().parse::<i64>() {
+            Ok(days) if (1..=MAX_MUTE_DAYS).contains(&days) => days,
+            _ => {
+                bot.send_message(
+                    msg.chat.id,
+                    tf(Key::MuteDaysInvalid, lang, &[&MAX_MUTE_DAYS]),
+                )
+                .await?;
+                return Ok(());
+            }
+        };
+
+        // Today counts as the first muted day.
+        let until = Local::now().date_naive() + Duration::days(days - 1);
+        store::set_mute_until(&pool, msg.chat.id.0, Some(until)).await?;
+
+        bot.send_message(
+            msg.chat.id,
+            tf(Key::MutedUntil, lang, &[&lang.format_date(until)]),
+        )
+        .await?;
+        dialogue.exit().await?;
+    }
+    Ok(())
+}
+
+async fn receive_template_handler(
+    bot: Bot,
+    dialogue: MyDialogue,
+    msg: Message,
+    pool: Arc<SqlitePool>,
+) -> HandlerResult {
+    if let Some(text) = msg.text() {
+        let lang = store::get_language(&pool, msg.chat.id.0).await?;
+        let template = text.trim();
+        if template == "-" {
+            store::set_message_template(&pool, msg.chat.id.0, None).await?;
+            bot.send_message(msg.chat.id, t(Key::TemplateReset, lang))
+                .await?;
+            dialogue.exit().await?;
+            return Ok(());
+        }
+
+        if let Err(e) = i18n::check_template(template) {
+            let reply = match e {
+                TemplateError::TooLong => {
+                    tf(Key::TemplateTooLong, lang, &[&i18n::MAX_TEMPLATE_CHARS])
+                }
+                TemplateError::UnknownPlaceholder(name) => tf(
+                    Key::TemplateUnknownPlaceholder,
+                    lang,
+                    &[&name, &i18n::TEMPLATE_PLACEHOLDERS.join("}, {")],
+                ),
+                TemplateError::Unclosed => t(Key::TemplateUnclosed, lang).to_string(),
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+            return Ok(());
+        }
+
+        store::set_message_template(&pool, msg.chat.id.0, Some(template)).await?;
+        let tomorrow = Local::now().date_naive() + Duration::days(1);
+        let example = i18n::fill_template(
+            template,
+            &[
+                ("type", &WasteType::Bio.label()),
+                ("date", &lang.format_day(tomorrow)),
+                ("when", t(Key::Tomorrow, lang)),
+                ("location", t(Key::TemplateExampleLocation, lang)),
+            ],
+        );
+        bot.send_message(msg.chat.id, tf(Key::TemplateSaved, lang, &[&example]))
+            .await?;
+        dialogue.exit().await?;
+    }
+    Ok(())
+}
+
+async fn receive_lead_hours_handler(
+    bot: Bot,
+    dialogue: MyDialogue,
+    msg: Message,
+    pool: Arc<SqlitePool>,
     loc_id: i64,
-    text: &str,
 ) -> HandlerResult {
-    bot.answer_callback_query(q.id.clone()).text(text).await?;
+    if let Some(text) = msg.text() {
+        let lang = store::get_language(&pool, msg.chat.id.0).await?;
+        let text = text.trim();
+        let hours = if text.eq_ignore_ascii_case(t(Key::LeadTimeOff, lang)) {
+            None
+        } else {
+            match text.parse::<i64>() {
+                Ok(hours) if (0..=store::MAX_LEAD_HOURS).contains(&hours) => Some(hours),
+                _ => {
+                    bot.send_message(
+                        msg.chat.id,
+                        tf(Key::LeadTimeInvalid, lang, &[&store::MAX_LEAD_HOURS]),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            }
+        };
+
+        let locations = store::get_user_locations(&pool, msg.chat.id.0).await?;
+        if let Some(loc) = locations.iter().find(|l| l.id == loc_id) {
+            store::update_notify_offset_hours(&pool, msg.chat.id.0, &loc.location_id, hours)
+                .await?;
+            let reply = match hours {
+                Some(hours) => {
+                    let (time, offset) = store::lead_time_slot(hours);
+                    tf(
+                        Key::LeadTimeSet,
+                        lang,
+                        &[&hours, &time, &t(day_key(offset), lang)],
+                    )
+                }
+                None => t(Key::LeadTimeCleared, lang).to_string(),
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+            show_location_settings(&bot, msg.chat.id, None, &pool, loc_id).await?;
+        } else {
+            bot.send_message(msg.chat.id, t(Key::LocationNotFound, lang))
+                .await?;
+        }
+        dialogue.exit().await?;
+    }
+    Ok(())
+}
+
+async fn receive_feedback_handler(
+    bot: Bot,
+    dialogue: MyDialogue,
+    msg: Message,
+    pool: Arc<SqlitePool>,
+) -> HandlerResult {
+    let lang = store::get_language(&pool, msg.chat.id.0).await?;
+    let Some(admin) = config::admin_chat_id() else {
+        bot.send_message(msg.chat.id, t(Key::FeedbackNotConfigured, lang))
+            .await?;
+        dialogue.exit().await?;
+        return Ok(());
+    };
+
+    let sender = match msg.from.as_ref().and_then(|u| u.username.as_deref()) {
+        Some(username) => format!("@{}", username),
+        None => msg
+            .from
+            .as_ref()
+            .map(|u| u.full_name())
+            .unwrap_or_else(|| "unknown".to_string()),
+    };
+
+    // Header first so the forwarded message (which keeps any media) is clearly attributed.
+    let admin_lang = store::get_language(&pool, admin.0).await?;
+    let header = tf(Key::FeedbackHeader, admin_lang, &[&msg.chat.id, &sender]);
+    bot.send_message(admin, header).await?;
+    bot.forward_message(admin, msg.chat.id, msg.id).await?;
+
+    bot.send_message(msg.chat.id, t(Key::FeedbackThanks, lang))
+        .await?;
+    dialogue.exit().await?;
+    Ok(())
+}
+
+async fn receive_import_handler(
+    bot: Bot,
+    dialogue: MyDialogue,
+    msg: Message,
+    pool: Arc<SqlitePool>,
+) -> HandlerResult {
+    let lang = store::get_language(&pool, msg.chat.id.0).await?;
 
+    let raw = if let Some(doc) = msg.document() {
+        if doc.file.size > MAX_IMPORT_BYTES {
+            bot.send_message(msg.chat.id, t(Key::ImportTooLarge, lang))
+                .await?;
+            return Ok(());
+        }
+        let file = bot.get_file(doc.file.id.clone()).await?;
+        let mut raw = Vec::new();
+        bot.download_file(&file.path, &mut raw).await?;
+        raw
+    } else if let Some(text) = msg.text() {
+        text.as_bytes().to_vec()
+    } else {
+        bot.send_message(msg.chat.id, t(Key::ImportPrompt, lang))
+            .await?;
+        return Ok(());
+    };
+
+    let result = match serde_json::from_slice::<store::UserExport>(&raw) {
+        Ok(data) => store::import_user(&pool, msg.chat.id.0, &data)
+            .await
+            .map(|_| data.locations.len()),
+        Err(e) => Err(e.into()),
+    };
+    match result {
+        Ok(count) => {
+            // The import may have switched the language
+            let lang = store::get_language(&pool, msg.chat.id.0).await?;
+            bot.send_message(msg.chat.id, tf(Key::ImportDone, lang, &[&count]))
+                .await?;
+            dialogue.exit().await?;
+        }
+        // Stay in the import state so the user can send a corrected file
+        Err(e) => {
+            bot.send_message(msg.chat.id, tf(Key::ImportInvalid, lang, &[&e]))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn invalid_state_handler(bot: Bot, msg: Message, pool: Arc<SqlitePool>) -> HandlerResult {
+    let lang = store::get_language(&pool, msg.chat.id.0).await?;
+    bot.send_message(msg.chat.id, t(Key::InvalidState, lang))
+        .await?;
+    Ok(())
+}
+
+async fn list_locations_handler(bot: Bot, chat_id: &ChatId, pool: &SqlitePool) -> HandlerResult {
+    let lang = store::get_language(pool, chat_id.0).await?;
     let locations = store::get_user_locations(pool, chat_id.0).await?;
-    if let Some(loc) = locations.iter().find(|l| l.id == loc_id) {
-        let subs = store::get_subscriptions(pool, loc_id).await?;
-        let keyboard = build_settings_keyboard(loc_id, &subs, &loc.notify_time, loc.notify_offset);
+    if locations.is_empty() {
+        // Still offer the language switch before anything is set up
+        bot.send_message(*chat_id, t(Key::NoLocations, lang))
+            .reply_markup(build_language_keyboard(lang))
+            .await?;
+        return Ok(());
+    }
+
+    let user = current_user(pool, chat_id.0).await?;
+    bot.send_message(*chat_id, t(Key::YourLocations, lang))
+        .reply_markup(build_locations_keyboard(&locations, &user, today()))
+        .await?;
 
-        if let Some(msg) = &q.message {
-            bot.edit_message_reply_markup(chat_id, msg.id())
+    Ok(())
+}
+
+async fn status_handler(bot: Bot, chat_id: ChatId, pool: &SqlitePool) -> HandlerResult {
+    let lang = store::get_language(pool, chat_id.0).await?;
+    let locations = store::get_user_locations(pool, chat_id.0).await?;
+    if locations.is_empty() {
+        bot.send_message(chat_id, t(Key::NoLocationsStatus, lang))
+            .await?;
+        return Ok(());
+    }
+
+    let mut text = String::from(t(Key::YourConfiguration, lang));
+    for loc in &locations {
+        let subs = store::get_subscriptions(pool, loc.id).await?;
+        let subs_label = subscriptions_label(&subs, lang);
+        let (notify_time, notify_offset) = loc.effective_slot();
+        let day_label = t(day_key(notify_offset), lang);
+
+        text.push_str(&tf(
+            Key::StatusLocation,
+            lang,
+            &[
+                &loc.alias.as_deref().unwrap_or(&loc.location_id),
+                &loc.describe(),
+                &notify_time,
+                &day_label,
+                &subs_label,
+            ],
+        ));
+    }
+
+    for chunk in notifier::split_message(&text, notifier::MAX_MESSAGE_CHARS) {
+        bot.send_message(chat_id, chunk).await?;
+    }
+    Ok(())
+}
+
+/// Reads the optional lead time of /remindbefore, in hours before `PICKUP_HOUR`.
+fn parse_remind_before_hours(args: &str) -> Option<i64> {
+    let args = args.trim();
+    if args.is_empty() {
+        return Some(DEFAULT_REMIND_BEFORE_HOURS);
+    }
+    args.parse()
+        .ok()
+        .filter(|hours| (1..=store::MAX_LEAD_HOURS).contains(hours))
+}
+
+/// When a one-off reminder `hours` before the pickup on `date` goes out.
+fn remind_before_at(date: NaiveDate, hours: i64) -> NaiveDateTime {
+    let pickup = date
+        .and_hms_opt(store::PICKUP_HOUR as u32, 0, 0)
+        .expect("valid pickup hour");
+    pickup - Duration::hours(hours)
+}
+
+/// Offers the user's next pickups as buttons; pressing one schedules a one-off reminder.
+async fn remind_before_handler(
+    bot: Bot,
+    chat_id: ChatId,
+    pool: &SqlitePool,
+    hours: i64,
+    lang: Lang,
+) -> HandlerResult {
+    let now = Local::now().naive_local();
+    let pickups = store::get_user_pickups(pool, chat_id.0, now.date(), REMIND_BEFORE_CHOICES)
+        .await?
+        .into_iter()
+        .filter(|(date, _)| remind_before_at(*date, hours) > now);
+    let rows: Vec<_> = pickups
+        .map(|(date, types)| {
+            let label = format!(
+                "{}: {}",
+                lang.format_day(date),
+                subscriptions_label(&types, lang)
+            );
+            vec![InlineKeyboardButton::callback(
+                label,
+                format!("remind:{}:{}", date, hours),
+            )]
+        })
+        .collect();
+    if rows.is_empty() {
+        bot.send_message(chat_id, t(Key::RemindBeforeNone, lang))
+            .await?;
+        return Ok(());
+    }
+    bot.send_message(chat_id, tf(Key::RemindBeforePrompt, lang, &[&hours]))
+        .reply_markup(InlineKeyboardMarkup::new(rows))
+        .await?;
+    Ok(())
+}
+
+/// When each of the user's locations last got fresh data from the city.
+async fn last_update_handler(
+    bot: Bot,
+    chat_id: ChatId,
+    pool: &SqlitePool,
+    lang: Lang,
+) -> HandlerResult {
+    let locations = store::get_user_locations(pool, chat_id.0).await?;
+    if locations.is_empty() {
+        bot.send_message(chat_id, t(Key::NoLocationsStatus, lang))
+            .await?;
+        return Ok(());
+    }
+
+    let today = Local::now().date_naive();
+    let mut lines = Vec::new();
+    for loc in &locations {
+        let label = loc.alias.as_deref().unwrap_or(&loc.location_id);
+        // Stored in UTC; users think in local dates
+        let line = match store::get_last_update(pool, &loc.location_id).await? {
+            Some(at) => {
+                let date = at.and_utc().with_timezone(&Local).date_naive();
+                tf(
+                    Key::LastUpdateLine,
+                    lang,
+                    &[
+                        &label,
+                        &age_label(date, today, lang),
+                        &lang.format_date(date),
+                    ],
+                )
+            }
+            None => tf(Key::LastUpdateNever, lang, &[&label]),
+        };
+        lines.push(line);
+    }
+
+    bot.send_message(chat_id, lines.join("\n")).await?;
+    Ok(())
+}
+
+/// "today", "yesterday" or "3 days ago", counting from `today`.
+fn age_label(date: NaiveDate, today: NaiveDate, lang: Lang) -> String {
+    match (today - date).num_days() {
+        ..=0 => t(Key::AgeToday, lang).to_string(),
+        1 => t(Key::AgeYesterday, lang).to_string(),
+        days => tf(Key::AgeDaysAgo, lang, &[&days]),
+    }
+}
+
+/// Subscribed waste types with their emoji, or a note that there are none.
+fn subscriptions_label(subs: &[String], lang: Lang) -> String {
+    if subs.is_empty() {
+        return t(Key::NoSubscriptions, lang).to_string();
+    }
+    subs.iter()
+        .map(|s| {
+            s.parse::<WasteType>()
+                .expect("WasteType parsing is infallible")
+                .label()
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The supported waste type named by `name`, in any spelling `WasteType::from_str` knows.
+fn known_waste_type(name: &str) -> Option<WasteType> {
+    let waste_type: WasteType = name.parse().expect("WasteType parsing is infallible");
+    WasteType::supported_types()
+        .contains(&waste_type)
+        .then_some(waste_type)
+}
+
+/// `/subscribe <type>` and `/unsubscribe <type>`: changes the type for every location of
+/// the user and replies with the resulting subscriptions.
+async fn subscribe_handler(
+    bot: Bot,
+    chat_id: ChatId,
+    pool: &SqlitePool,
+    args: &str,
+    subscribe: bool,
+) -> HandlerResult {
+    let lang = store::get_language(pool, chat_id.0).await?;
+    let available = WasteType::supported_types()
+        .iter()
+        .map(|w| w.as_str().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let name = args.trim();
+    if name.is_empty() {
+        bot.send_message(chat_id, tf(Key::SubscribeUsage, lang, &[&available]))
+            .await?;
+        return Ok(());
+    }
+    let Some(waste_type) = known_waste_type(name) else {
+        bot.send_message(
+            chat_id,
+            tf(Key::UnknownWasteType, lang, &[&name, &available]),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let locations = store::get_user_locations(pool, chat_id.0).await?;
+    if locations.is_empty() {
+        bot.send_message(chat_id, t(Key::NoLocations, lang)).await?;
+        return Ok(());
+    }
+
+    let key = if subscribe {
+        Key::SubscribedTo
+    } else {
+        Key::UnsubscribedFrom
+    };
+    let mut text = tf(key, lang, &[&waste_type.label()]);
+    for loc in &locations {
+        if subscribe {
+            store::add_subscription(pool, loc.id, waste_type.as_str()).await?;
+        } else {
+            store::remove_subscription(pool, loc.id, waste_type.as_str()).await?;
+        }
+        let subs = store::get_subscriptions(pool, loc.id).await?;
+        text.push_str(&tf(
+            Key::SubscriptionsLine,
+            lang,
+            &[
+                &loc.alias.as_deref().unwrap_or(&loc.location_id),
+                &subscriptions_label(&subs, lang),
+            ],
+        ));
+    }
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+async fn show_location_settings(
+    bot: &Bot,
+    chat_id: ChatId,
+    message_id: Option<teloxide::types::MessageId>,
+    pool: &SqlitePool,
+    loc_id: i64,
+) -> HandlerResult {
+    let lang = store::get_language(pool, chat_id.0).await?;
+
+    if let Some((loc, keyboard)) = load_settings(pool, chat_id, loc_id, lang).await? {
+        let text = tf(
+            Key::SettingsFor,
+            lang,
+            &[
+                &loc.alias.as_deref().unwrap_or(&loc.location_id),
+                &loc.describe(),
+            ],
+        );
+
+        if let Some(mid) = message_id {
+            bot.edit_message_text(chat_id, mid, text)
+                .reply_markup(keyboard)
+                .await?;
+        } else {
+            bot.send_message(chat_id, text)
                 .reply_markup(keyboard)
                 .await?;
         }
+    } else if let Some(mid) = message_id {
+        bot.edit_message_text(chat_id, mid, t(Key::LocationNotFound, lang))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Button payloads produced by the inline keyboards, e.g. `sub:3:Bio`.
+#[derive(Debug, PartialEq)]
+pub(crate) enum CallbackAction {
+    Edit(i64),
+    Back,
+    Subscribe(i64, String),
+    Unsubscribe(i64, String),
+    SubscribeAll(i64),
+    UnsubscribeAll(i64),
+    Time(i64, String),
+    Offset(i64, i64),
+    AddNotifyTime(i64, String, i64),
+    RemoveNotifyTime(i64, String, i64),
+    EveningAndMorning(i64),
+    LeadTime(i64),
+    DeleteLocation(i64),
+    Mute,
+    Unmute,
+    ToggleNotifyMode,
+    ToggleQuietWeek,
+    EditTemplate,
+    ToggleLanguage,
+    ConfirmStop,
+    CancelStop,
+    Snooze(NaiveDate, Vec<String>),
+    RemindBefore(NaiveDate, i64),
+}
+
+impl CallbackAction {
+    /// Returns `None` for unknown actions or values of the wrong shape, since callback
+    /// data comes from the client and can't be trusted.
+    pub(crate) fn parse(data: &str) -> Option<Self> {
+        let parts: Vec<&str> = data.split(':').collect();
+        let action = match parts.as_slice() {
+            ["edit", id] => CallbackAction::Edit(id.parse().ok()?),
+            ["back"] => CallbackAction::Back,
+            ["sub", id, waste] if !waste.is_empty() => {
+                CallbackAction::Subscribe(id.parse().ok()?, waste.to_string())
+            }
+            ["unsub", id, waste] if !waste.is_empty() => {
+                CallbackAction::Unsubscribe(id.parse().ok()?, waste.to_string())
+            }
+            ["suball", id] => CallbackAction::SubscribeAll(id.parse().ok()?),
+            ["unsuball", id] => CallbackAction::UnsubscribeAll(id.parse().ok()?),
+            // Times look like "18:00", so they span two parts
+            ["time", id, hour, minute] => {
+                CallbackAction::Time(id.parse().ok()?, format!("{}:{}", hour, minute))
+            }
+            ["offset", id, offset] => {
+                CallbackAction::Offset(id.parse().ok()?, offset.parse().ok()?)
+            }
+            ["xadd", id, hour, minute, offset] => CallbackAction::AddNotifyTime(
+                id.parse().ok()?,
+                format!("{}:{}", hour, minute),
+                offset.parse().ok()?,
+            ),
+            ["xdel", id, hour, minute, offset] => CallbackAction::RemoveNotifyTime(
+                id.parse().ok()?,
+                format!("{}:{}", hour, minute),
+                offset.parse().ok()?,
+            ),
+            ["both", id] => CallbackAction::EveningAndMorning(id.parse().ok()?),
+            ["lead", id] => CallbackAction::LeadTime(id.parse().ok()?),
+            ["delloc", id] => CallbackAction::DeleteLocation(id.parse().ok()?),
+            ["mute"] => CallbackAction::Mute,
+            ["unmute"] => CallbackAction::Unmute,
+            ["mode"] => CallbackAction::ToggleNotifyMode,
+            ["quiet"] => CallbackAction::ToggleQuietWeek,
+            ["template"] => CallbackAction::EditTemplate,
+            ["lang"] => CallbackAction::ToggleLanguage,
+            ["confirm_stop"] => CallbackAction::ConfirmStop,
+            ["cancel_stop"] => CallbackAction::CancelStop,
+            ["snooze", date, types] if !types.is_empty() => CallbackAction::Snooze(
+                date.parse().ok()?,
+                types.split(',').map(str::to_string).collect(),
+            ),
+            ["remind", date, hours] => {
+                CallbackAction::RemindBefore(date.parse().ok()?, hours.parse().ok()?)
+            }
+            _ => return None,
+        };
+        Some(action)
+    }
+}
+
+/// The store change a settings button asks for. Split out of `callback_query_handler`
+/// so the decision and its effect can be tested without Telegram.
+#[derive(Debug, PartialEq)]
+pub(crate) enum SettingsChange {
+    Subscribe(i64, String),
+    Unsubscribe(i64, String),
+    SetAllSubscriptions(i64, bool),
+    NotifyTime(i64, String),
+    NotifyOffset(i64, i64),
+    AddNotifyTime(i64, String, i64),
+    RemoveNotifyTime(i64, String, i64),
+    /// Main reminder the evening before plus an extra one on the morning of the pickup.
+    EveningAndMorning(i64),
+}
+
+impl SettingsChange {
+    /// `None` for actions that don't change a location's settings.
+    pub(crate) fn from_action(action: &CallbackAction) -> Option<Self> {
+        let change = match action {
+            CallbackAction::Subscribe(id, waste) => SettingsChange::Subscribe(*id, waste.clone()),
+            CallbackAction::Unsubscribe(id, waste) => {
+                SettingsChange::Unsubscribe(*id, waste.clone())
+            }
+            CallbackAction::SubscribeAll(id) => SettingsChange::SetAllSubscriptions(*id, true),
+            CallbackAction::UnsubscribeAll(id) => SettingsChange::SetAllSubscriptions(*id, false),
+            // The buttons carry the current value; each press moves to the next one
+            CallbackAction::Time(id, current) => {
+                SettingsChange::NotifyTime(*id, increment_time(current))
+            }
+            CallbackAction::Offset(id, current) => {
+                // toggle offset: if 1 (Day Before) -> 0 (Same Day), and vice versa.
+                SettingsChange::NotifyOffset(*id, if *current == 1 { 0 } else { 1 })
+            }
+            CallbackAction::AddNotifyTime(id, time, offset) => {
+                SettingsChange::AddNotifyTime(*id, time.clone(), *offset)
+            }
+            CallbackAction::RemoveNotifyTime(id, time, offset) => {
+                SettingsChange::RemoveNotifyTime(*id, time.clone(), *offset)
+            }
+            CallbackAction::EveningAndMorning(id) => SettingsChange::EveningAndMorning(*id),
+            _ => return None,
+        };
+        Some(change)
+    }
+
+    pub(crate) fn loc_id(&self) -> i64 {
+        match self {
+            SettingsChange::Subscribe(id, _)
+            | SettingsChange::Unsubscribe(id, _)
+            | SettingsChange::SetAllSubscriptions(id, _)
+            | SettingsChange::NotifyTime(id, _)
+            | SettingsChange::NotifyOffset(id, _)
+            | SettingsChange::AddNotifyTime(id, ..)
+            | SettingsChange::RemoveNotifyTime(id, ..)
+            | SettingsChange::EveningAndMorning(id) => *id,
+        }
+    }
+
+    fn toast(&self) -> Key {
+        match self {
+            SettingsChange::Subscribe(..) | SettingsChange::SetAllSubscriptions(_, true) => {
+                Key::Subscribed
+            }
+            SettingsChange::Unsubscribe(..) | SettingsChange::SetAllSubscriptions(_, false) => {
+                Key::Unsubscribed
+            }
+            SettingsChange::NotifyTime(..) => Key::TimeUpdated,
+            SettingsChange::NotifyOffset(..) => Key::DayUpdated,
+            SettingsChange::AddNotifyTime(..) => Key::ReminderAdded,
+            SettingsChange::RemoveNotifyTime(..) => Key::ReminderRemoved,
+            SettingsChange::EveningAndMorning(..) => Key::EveningAndMorningSet,
+        }
+    }
+
+    /// Applies the change if the location belongs to `chat_id`; returns false (and
+    /// changes nothing) otherwise, since callback data can't be trusted.
+    pub(crate) async fn apply(&self, pool: &SqlitePool, chat_id: i64) -> anyhow::Result<bool> {
+        let locations = store::get_user_locations(pool, chat_id).await?;
+        let Some(loc) = locations.iter().find(|l| l.id == self.loc_id()) else {
+            return Ok(false);
+        };
+        match self {
+            SettingsChange::Subscribe(id, waste) => {
+                store::add_subscription(pool, *id, waste).await?;
+            }
+            SettingsChange::Unsubscribe(id, waste) => {
+                store::remove_subscription(pool, *id, waste).await?;
+            }
+            SettingsChange::SetAllSubscriptions(id, subscribe) => {
+                store::set_all_subscriptions(pool, *id, *subscribe).await?;
+            }
+            SettingsChange::NotifyTime(_, time) => {
+                store::update_notify_time(pool, chat_id, &loc.location_id, time).await?;
+            }
+            SettingsChange::NotifyOffset(_, offset) => {
+                store::update_notify_offset(pool, chat_id, &loc.location_id, *offset).await?;
+            }
+            SettingsChange::AddNotifyTime(id, time, offset) => {
+                store::add_notify_time(pool, *id, time, *offset).await?;
+            }
+            SettingsChange::RemoveNotifyTime(id, time, offset) => {
+                store::remove_notify_time(pool, *id, time, *offset).await?;
+            }
+            SettingsChange::EveningAndMorning(id) => {
+                store::set_reminder_pair(pool, *id, EVENING_REMINDER, MORNING_REMINDER).await?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Where a button press came from, which decides whether it can be acted on.
+#[derive(Debug, PartialEq)]
+enum CallbackOrigin {
+    Chat(ChatId),
+    /// Telegram no longer lets us edit messages older than 48 hours (or deleted ones).
+    Expired(ChatId),
+    /// Only inline-mode messages come without one, and the bot doesn't use inline mode.
+    Unknown,
+}
+
+fn callback_origin(message: Option<&MaybeInaccessibleMessage>) -> CallbackOrigin {
+    match message {
+        Some(MaybeInaccessibleMessage::Regular(message)) => CallbackOrigin::Chat(message.chat.id),
+        Some(MaybeInaccessibleMessage::Inaccessible(message)) => {
+            CallbackOrigin::Expired(message.chat.id)
+        }
+        None => CallbackOrigin::Unknown,
+    }
+}
+
+#[instrument(skip_all, fields(user_id = q.from.id.0))]
+async fn callback_query_handler(
+    bot: Bot,
+    dialogue: MyDialogue,
+    q: CallbackQuery,
+    pool: Arc<SqlitePool>,
+) -> HandlerResult {
+    let Some(action) = q.data.as_deref().and_then(CallbackAction::parse) else {
+        warn!("Ignoring malformed callback data: {:?}", q.data);
+        bot.answer_callback_query(q.id).await?;
+        return Ok(());
+    };
+
+    let chat_id = match callback_origin(q.message.as_ref()) {
+        CallbackOrigin::Chat(chat_id) => chat_id,
+        CallbackOrigin::Expired(chat_id) => {
+            let lang = store::get_language(&pool, chat_id.0).await?;
+            bot.answer_callback_query(q.id)
+                .text(t(Key::MenuExpired, lang))
+                .await?;
+            return Ok(());
+        }
+        CallbackOrigin::Unknown => {
+            warn!("Callback query {} has no message to act on", q.id);
+            // The presser's own chat is the best guess for their language
+            let lang = store::get_language(&pool, q.from.id.0 as i64).await?;
+            bot.answer_callback_query(q.id)
+                .text(t(Key::ButtonUnavailable, lang))
+                .await?;
+            return Ok(());
+        }
+    };
+    let lang = store::get_language(&pool, chat_id.0).await?;
+
+    match action {
+        CallbackAction::Edit(loc_id) => {
+            show_location_settings(
+                &bot,
+                chat_id,
+                q.message.as_ref().map(|m| m.id()),
+                &pool,
+                loc_id,
+            )
+            .await?;
+            bot.answer_callback_query(q.id).await?;
+        }
+        CallbackAction::Back => {
+            let locations = store::get_user_locations(&pool, chat_id.0).await?;
+            let user = current_user(&pool, chat_id.0).await?;
+            if let Some(message) = q.message {
+                bot.edit_message_text(chat_id, message.id(), t(Key::YourLocations, lang))
+                    .reply_markup(build_locations_keyboard(&locations, &user, today()))
+                    .await?;
+            }
+            bot.answer_callback_query(q.id).await?;
+        }
+        CallbackAction::Subscribe(..)
+        | CallbackAction::Unsubscribe(..)
+        | CallbackAction::SubscribeAll(_)
+        | CallbackAction::UnsubscribeAll(_)
+        | CallbackAction::Time(..)
+        | CallbackAction::Offset(..)
+        | CallbackAction::AddNotifyTime(..)
+        | CallbackAction::RemoveNotifyTime(..)
+        | CallbackAction::EveningAndMorning(_) => {
+            let change = SettingsChange::from_action(&action)
+                .expect("settings buttons always map to a change");
+            if change.apply(&pool, chat_id.0).await? {
+                refresh_settings(&bot, &q, chat_id, &pool, change.loc_id(), change.toast()).await?;
+            } else {
+                bot.answer_callback_query(q.id)
+                    .text(t(Key::LocationNotFound, lang))
+                    .await?;
+            }
+        }
+        CallbackAction::LeadTime(loc_id) => {
+            bot.send_message(
+                chat_id,
+                tf(
+                    Key::LeadTimePrompt,
+                    lang,
+                    &[&store::PICKUP_HOUR, &store::MAX_LEAD_HOURS],
+                ),
+            )
+            .await?;
+            dialogue.update(State::AwaitingLeadHours(loc_id)).await?;
+            bot.answer_callback_query(q.id).await?;
+        }
+        CallbackAction::DeleteLocation(loc_id) => {
+            let locations = store::get_user_locations(&pool, chat_id.0).await?;
+            if let Some(loc) = locations.iter().find(|l| l.id == loc_id) {
+                store::delete_user_location(&pool, chat_id.0, &loc.location_id).await?;
+
+                let locations = store::get_user_locations(&pool, chat_id.0).await?;
+                if let Some(message) = q.message {
+                    if locations.is_empty() {
+                        bot.edit_message_text(chat_id, message.id(), t(Key::NoLocationsLeft, lang))
+                            .reply_markup(InlineKeyboardMarkup::default())
+                            .await?;
+                    } else {
+                        let user = current_user(&pool, chat_id.0).await?;
+                        bot.edit_message_text(chat_id, message.id(), t(Key::YourLocations, lang))
+                            .reply_markup(build_locations_keyboard(&locations, &user, today()))
+                            .await?;
+                    }
+                }
+                bot.answer_callback_query(q.id)
+                    .text(t(Key::LocationDeleted, lang))
+                    .await?;
+            }
+        }
+        CallbackAction::Snooze(event_date, waste_types) => {
+            let now = Local::now().naive_local();
+            let fire_at = now
+                .date()
+                .and_hms_opt(scheduler::SNOOZE_HOUR, 0, 0)
+                .expect("valid snooze hour");
+            if now >= fire_at || event_date < now.date() {
+                bot.answer_callback_query(q.id)
+                    .text(t(Key::SnoozeTooLate, lang))
+                    .await?;
+                return Ok(());
+            }
+            store::add_snooze(&pool, chat_id.0, fire_at, event_date, &waste_types).await?;
+            if let Some(message) = q.message {
+                bot.edit_message_reply_markup(chat_id, message.id())
+                    .reply_markup(InlineKeyboardMarkup::default())
+                    .await?;
+            }
+            bot.answer_callback_query(q.id)
+                .text(tf(Key::Snoozed, lang, &[&scheduler::SNOOZE_HOUR]))
+                .await?;
+        }
+        CallbackAction::RemindBefore(event_date, hours) => {
+            let now = Local::now().naive_local();
+            let fire_at = remind_before_at(event_date, hours.clamp(1, store::MAX_LEAD_HOURS));
+            let pickup = store::get_user_pickups(&pool, chat_id.0, event_date, 1)
+                .await?
+                .into_iter()
+                .find(|(date, _)| *date == event_date);
+            let Some((_, waste_types)) = pickup.filter(|_| fire_at > now) else {
+                bot.answer_callback_query(q.id)
+                    .text(t(Key::RemindBeforeTooLate, lang))
+                    .await?;
+                return Ok(());
+            };
+            store::add_snooze(&pool, chat_id.0, fire_at, event_date, &waste_types).await?;
+            if let Some(message) = q.message {
+                bot.edit_message_reply_markup(chat_id, message.id())
+                    .reply_markup(InlineKeyboardMarkup::default())
+                    .await?;
+            }
+            let when = [
+                lang.format_day(fire_at.date()),
+                fire_at.format("%H:%M").to_string(),
+            ];
+            bot.answer_callback_query(q.id)
+                .text(tf(Key::RemindBeforeSet, lang, &[&when[0], &when[1]]))
+                .await?;
+        }
+        CallbackAction::Mute => {
+            bot.send_message(chat_id, tf(Key::MutePrompt, lang, &[&MAX_MUTE_DAYS]))
+                .await?;
+            dialogue.update(State::AwaitingMuteDays).await?;
+            bot.answer_callback_query(q.id).await?;
+        }
+        CallbackAction::EditTemplate => {
+            let current = current_user(&pool, chat_id.0)
+                .await?
+                .message_template
+                .unwrap_or_else(|| t(Key::TemplateDefault, lang).to_string());
+            bot.send_message(chat_id, tf(Key::TemplatePrompt, lang, &[&current]))
+                .await?;
+            dialogue.update(State::AwaitingTemplate).await?;
+            bot.answer_callback_query(q.id).await?;
+        }
+        CallbackAction::Unmute => {
+            store::set_mute_until(&pool, chat_id.0, None).await?;
+            let locations = store::get_user_locations(&pool, chat_id.0).await?;
+            let user = current_user(&pool, chat_id.0).await?;
+            if let Some(message) = q.message {
+                bot.edit_message_reply_markup(chat_id, message.id())
+                    .reply_markup(build_locations_keyboard(&locations, &user, today()))
+                    .await?;
+            }
+            bot.answer_callback_query(q.id)
+                .text(t(Key::NotificationsResumed, lang))
+                .await?;
+        }
+        CallbackAction::ToggleNotifyMode => {
+            let mode = store::get_notify_mode(&pool, chat_id.0).await?.toggled();
+            store::set_notify_mode(&pool, chat_id.0, mode).await?;
+            let locations = store::get_user_locations(&pool, chat_id.0).await?;
+            let user = current_user(&pool, chat_id.0).await?;
+            if let Some(message) = q.message {
+                bot.edit_message_reply_markup(chat_id, message.id())
+                    .reply_markup(build_locations_keyboard(&locations, &user, today()))
+                    .await?;
+            }
+            let toast = match mode {
+                NotifyMode::PerEvent => Key::DigestDisabled,
+                NotifyMode::WeeklyDigest => Key::DigestEnabled,
+            };
+            bot.answer_callback_query(q.id).text(t(toast, lang)).await?;
+        }
+        CallbackAction::ToggleQuietWeek => {
+            let enabled = !current_user(&pool, chat_id.0).await?.quiet_week_notice;
+            store::set_quiet_week_notice(&pool, chat_id.0, enabled).await?;
+            let locations = store::get_user_locations(&pool, chat_id.0).await?;
+            let user = current_user(&pool, chat_id.0).await?;
+            if let Some(message) = q.message {
+                bot.edit_message_reply_markup(chat_id, message.id())
+                    .reply_markup(build_locations_keyboard(&locations, &user, today()))
+                    .await?;
+            }
+            let toast = if enabled {
+                Key::QuietWeekEnabled
+            } else {
+                Key::QuietWeekDisabled
+            };
+            bot.answer_callback_query(q.id).text(t(toast, lang)).await?;
+        }
+        CallbackAction::ToggleLanguage => {
+            let lang = lang.toggled();
+            store::set_language(&pool, chat_id.0, lang).await?;
+            let locations = store::get_user_locations(&pool, chat_id.0).await?;
+            if let Some(message) = q.message {
+                if locations.is_empty() {
+                    bot.edit_message_text(chat_id, message.id(), t(Key::NoLocations, lang))
+                        .reply_markup(build_language_keyboard(lang))
+                        .await?;
+                } else {
+                    let user = current_user(&pool, chat_id.0).await?;
+                    bot.edit_message_text(chat_id, message.id(), t(Key::YourLocations, lang))
+                        .reply_markup(build_locations_keyboard(&locations, &user, today()))
+                        .await?;
+                }
+            }
+            bot.answer_callback_query(q.id)
+                .text(t(Key::LanguageChanged, lang))
+                .await?;
+        }
+        CallbackAction::ConfirmStop => {
+            store::delete_user(&pool, chat_id.0).await?;
+            if let Some(message) = q.message {
+                bot.edit_message_text(chat_id, message.id(), t(Key::StopDone, lang))
+                    .reply_markup(InlineKeyboardMarkup::default())
+                    .await?;
+            }
+            bot.answer_callback_query(q.id).await?;
+        }
+        CallbackAction::CancelStop => {
+            if let Some(message) = q.message {
+                bot.delete_message(chat_id, message.id()).await?;
+            }
+            bot.answer_callback_query(q.id)
+                .text(t(Key::Cancelled, lang))
+                .await?;
+        }
     }
     Ok(())
 }
 
-fn build_locations_keyboard(locations: &[store::UserLocation]) -> InlineKeyboardMarkup {
+fn increment_time(time: &str) -> String {
+    NotifySlot::from_db(time)
+        .unwrap_or(NotifySlot::DEFAULT)
+        .next_hour()
+        .to_db()
+}
+
+async fn refresh_settings(
+    bot: &Bot,
+    q: &CallbackQuery,
+    chat_id: ChatId,
+    pool: &SqlitePool,
+    loc_id: i64,
+    toast: Key,
+) -> HandlerResult {
+    let lang = store::get_language(pool, chat_id.0).await?;
+    bot.answer_callback_query(q.id.clone())
+        .text(t(toast, lang))
+        .await?;
+
+    if let (Some((_, keyboard)), Some(msg)) = (
+        load_settings(pool, chat_id, loc_id, lang).await?,
+        &q.message,
+    ) {
+        bot.edit_message_reply_markup(chat_id, msg.id())
+            .reply_markup(keyboard)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Looks up one of the chat's locations and builds its settings keyboard. Shared by
+/// the initial menu and the in-place refresh so the two can't drift apart.
+async fn load_settings(
+    pool: &SqlitePool,
+    chat_id: ChatId,
+    loc_id: i64,
+    lang: Lang,
+) -> anyhow::Result<Option<(store::UserLocation, InlineKeyboardMarkup)>> {
+    let locations = store::get_user_locations(pool, chat_id.0).await?;
+    let Some(loc) = locations.into_iter().find(|l| l.id == loc_id) else {
+        return Ok(None);
+    };
+    let subs = store::get_subscriptions(pool, loc_id).await?;
+    let extra = store::get_location_notify_times(pool, loc_id).await?;
+    let keyboard = build_settings_keyboard(&loc, &subs, &extra, lang);
+    Ok(Some((loc, keyboard)))
+}
+
+/// The chat's stored settings, or the defaults if it has none yet.
+async fn current_user(pool: &SqlitePool, chat_id: i64) -> anyhow::Result<store::User> {
+    Ok(store::get_user(pool, chat_id)
+        .await?
+        .unwrap_or_else(|| store::User::new(chat_id)))
+}
+
+fn today() -> NaiveDate {
+    Local::now().date_naive()
+}
+
+fn build_locations_keyboard(
+    locations: &[store::UserLocation],
+    user: &store::User,
+    today: NaiveDate,
+) -> InlineKeyboardMarkup {
+    let lang = user.language;
     let mut keyboard = Vec::new();
     for loc in locations {
         let label = loc.alias.as_deref().unwrap_or(&loc.location_id);
@@ -405,48 +1917,496 @@ fn build_locations_keyboard(locations: &[store::UserLocation]) -> InlineKeyboard
             format!("edit:{}", loc.id),
         )]);
     }
+
+    // Vacation mode applies to all locations
+    let mute_button = match user.active_mute(today) {
+        Some(until) => InlineKeyboardButton::callback(
+            tf(Key::UnmuteButton, lang, &[&lang.format_date(until)]),
+            "unmute",
+        ),
+        None => InlineKeyboardButton::callback(t(Key::PauseButton, lang), "mute"),
+    };
+    keyboard.push(vec![mute_button]);
+    let mode_key = match user.notify_mode {
+        NotifyMode::PerEvent => Key::ModePerEvent,
+        NotifyMode::WeeklyDigest => Key::ModeWeeklyDigest,
+    };
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        tf(Key::NotifyModeButton, lang, &[&t(mode_key, lang)]),
+        "mode",
+    )]);
+    let quiet_value = t(
+        if user.quiet_week_notice {
+            Key::On
+        } else {
+            Key::Off
+        },
+        lang,
+    );
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        tf(Key::QuietWeekButton, lang, &[&quiet_value]),
+        "quiet",
+    )]);
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        t(Key::TemplateButton, lang),
+        "template",
+    )]);
+    keyboard.push(language_row(lang));
+
     InlineKeyboardMarkup::new(keyboard)
 }
 
+/// Keyboard with only the language switch, for users without locations.
+fn build_language_keyboard(lang: Lang) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![language_row(lang)])
+}
+
+fn language_row(lang: Lang) -> Vec<InlineKeyboardButton> {
+    vec![InlineKeyboardButton::callback(
+        t(Key::LanguageButton, lang),
+        "lang",
+    )]
+}
+
+fn day_key(notify_offset: i64) -> Key {
+    if notify_offset == 1 {
+        Key::DayBefore
+    } else {
+        Key::SameDay
+    }
+}
+
+const TYPE_BUTTONS_PER_ROW: usize = 2;
+
+/// 06:00 on the day of the pickup.
+const MORNING_REMINDER: (NotifySlot, i64) = (NotifySlot::on_the_hour(6), 0);
+/// 18:00 the day before the pickup.
+const EVENING_REMINDER: (NotifySlot, i64) = (NotifySlot::on_the_hour(18), 1);
+
+/// Extra reminders offered as toggles in a location's settings, on top of its main one:
+/// the morning of the pickup and the evening before.
+const EXTRA_REMINDERS: [(NotifySlot, i64, Key); 2] = [
+    (
+        MORNING_REMINDER.0,
+        MORNING_REMINDER.1,
+        Key::ExtraMorningButton,
+    ),
+    (
+        EVENING_REMINDER.0,
+        EVENING_REMINDER.1,
+        Key::ExtraEveningButton,
+    ),
+];
+
 fn build_settings_keyboard(
-    loc_id: i64,
+    loc: &store::UserLocation,
     subs: &[String],
-    notify_time: &str,
-    notify_offset: i64,
+    extra: &[store::NotifyTime],
+    lang: Lang,
 ) -> InlineKeyboardMarkup {
+    let loc_id = loc.id;
+    let (notify_time, notify_offset) = (loc.notify_time.as_str(), loc.notify_offset);
     let mut keyboard = Vec::new();
 
-    // Toggle buttons for Waste Types
-    for w_type in WasteType::supported_types() {
-        let w_str = w_type.as_str();
-        let is_subbed = subs.contains(&w_str.to_string());
-        let label = format!("{} {}", if is_subbed { "✅" } else { "❌" }, w_str);
-        let action = if is_subbed { "unsub" } else { "sub" };
-        let data = format!("{}:{}:{}", action, loc_id, w_str);
-        keyboard.push(vec![InlineKeyboardButton::callback(label, data)]);
+    // Toggle buttons for Waste Types, two per row to keep the menu short
+    let type_buttons: Vec<_> = WasteType::supported_types()
+        .iter()
+        .map(|w_type| {
+            let w_str = w_type.as_str();
+            let is_subbed = subs.contains(&w_str.to_string());
+            let label = format!("{} {}", if is_subbed { "✅" } else { "❌" }, w_str);
+            let action = if is_subbed { "unsub" } else { "sub" };
+            let data = format!("{}:{}:{}", action, loc_id, w_str);
+            InlineKeyboardButton::callback(label, data)
+        })
+        .collect();
+    for row in type_buttons.chunks(TYPE_BUTTONS_PER_ROW) {
+        keyboard.push(row.to_vec());
     }
 
+    // One tap for every type: unsubscribe once all are on, subscribe otherwise
+    let all_subbed = WasteType::supported_types()
+        .iter()
+        .all(|w| subs.iter().any(|s| s == w.as_str()));
+    let (all_key, all_action) = if all_subbed {
+        (Key::UnsubscribeAllButton, "unsuball")
+    } else {
+        (Key::SubscribeAllButton, "suball")
+    };
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        t(all_key, lang),
+        format!("{}:{}", all_action, loc_id),
+    )]);
+
     // Time toggle
-    let time_label = format!("Notify Time: {}", notify_time);
+    let time_label = tf(Key::NotifyTimeButton, lang, &[&notify_time]);
     let time_data = format!("time:{}:{}", loc_id, notify_time);
     keyboard.push(vec![InlineKeyboardButton::callback(time_label, time_data)]);
 
     // Offset toggle
-    let offset_label = if notify_offset == 1 { "Day: Day Before" } else { "Day: Same Day" };
+    let offset_label = tf(Key::DayButton, lang, &[&t(day_key(notify_offset), lang)]);
     let offset_data = format!("offset:{}:{}", loc_id, notify_offset);
-    keyboard.push(vec![InlineKeyboardButton::callback(offset_label, offset_data)]);
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        offset_label,
+        offset_data,
+    )]);
+
+    // Custom lead time, overriding the two presets above while set
+    let lead_value = match loc.notify_offset_hours {
+        Some(hours) => format!("{} h", hours),
+        None => t(Key::LeadTimeOff, lang).to_string(),
+    };
+    let lead_label = tf(Key::LeadTimeButton, lang, &[&lead_value]);
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        lead_label,
+        format!("lead:{}", loc_id),
+    )]);
+
+    // Extra reminders, each toggled on its own
+    let extra_buttons = EXTRA_REMINDERS
+        .iter()
+        .map(|&(time, offset, key)| {
+            let active = extra.iter().any(|e| {
+                NotifySlot::from_db(&e.notify_time) == Some(time) && e.notify_offset == offset
+            });
+            let label = format!(
+                "{} {}",
+                if active { "✅" } else { "❌" },
+                tf(key, lang, &[&time])
+            );
+            let action = if active { "xdel" } else { "xadd" };
+            let data = format!("{}:{}:{}:{}", action, loc_id, time, offset);
+            InlineKeyboardButton::callback(label, data)
+        })
+        .collect();
+    keyboard.push(extra_buttons);
+
+    // Both at once: the evening before as the main reminder, the morning as an extra
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        t(Key::EveningAndMorningButton, lang),
+        format!("both:{}", loc_id),
+    )]);
 
     // Delete Location
     keyboard.push(vec![InlineKeyboardButton::callback(
-        "🗑️ Delete Location",
+        t(Key::DeleteLocationButton, lang),
         format!("delloc:{}", loc_id),
     )]);
 
     // Back button
     keyboard.push(vec![InlineKeyboardButton::callback(
-        "🔙 Back to Locations",
+        t(Key::BackButton, lang),
         "back",
     )]);
 
     InlineKeyboardMarkup::new(keyboard)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use teloxide::types::InlineKeyboardButtonKind;
+
+    #[test]
+    fn test_parse_callback_action() {
+        assert_eq!(
+            CallbackAction::parse("edit:3"),
+            Some(CallbackAction::Edit(3))
+        );
+        assert_eq!(CallbackAction::parse("back"), Some(CallbackAction::Back));
+        assert_eq!(
+            CallbackAction::parse("lang"),
+            Some(CallbackAction::ToggleLanguage)
+        );
+        assert_eq!(
+            CallbackAction::parse("template"),
+            Some(CallbackAction::EditTemplate)
+        );
+        assert_eq!(
+            CallbackAction::parse("sub:3:Sperrmüll"),
+            Some(CallbackAction::Subscribe(3, "Sperrmüll".to_string()))
+        );
+        assert_eq!(
+            CallbackAction::parse("time:3:18:00"),
+            Some(CallbackAction::Time(3, "18:00".to_string()))
+        );
+        assert_eq!(
+            CallbackAction::parse("offset:3:1"),
+            Some(CallbackAction::Offset(3, 1))
+        );
+        assert_eq!(
+            CallbackAction::parse("unsuball:3"),
+            Some(CallbackAction::UnsubscribeAll(3))
+        );
+        assert_eq!(
+            CallbackAction::parse("lead:3"),
+            Some(CallbackAction::LeadTime(3))
+        );
+        assert_eq!(
+            CallbackAction::parse("confirm_stop"),
+            Some(CallbackAction::ConfirmStop)
+        );
+        assert_eq!(
+            CallbackAction::parse("snooze:2024-06-07:Bio,Rest"),
+            Some(CallbackAction::Snooze(
+                NaiveDate::from_ymd_opt(2024, 6, 7).unwrap(),
+                vec!["Bio".to_string(), "Rest".to_string()]
+            ))
+        );
+        assert_eq!(
+            CallbackAction::parse("remind:2024-06-07:12"),
+            Some(CallbackAction::RemindBefore(
+                NaiveDate::from_ymd_opt(2024, 6, 7).unwrap(),
+                12
+            ))
+        );
+    }
+
+    #[test]
+    fn test_settings_keyboard_rows() {
+        let loc = store::UserLocation {
+            id: 3,
+            location_id: "54321".to_string(),
+            notify_time: "18:00".to_string(),
+            notify_offset: 1,
+            notify_offset_hours: None,
+            alias: None,
+            location_name: None,
+        };
+        let types = WasteType::supported_types().len();
+        let type_rows = types.div_ceil(TYPE_BUTTONS_PER_ROW);
+        // Type rows, then all-toggle, time, day, lead time, extra reminders, the
+        // evening-and-morning preset, delete and back
+        let keyboard = build_settings_keyboard(&loc, &[], &[], Lang::En);
+        assert_eq!(keyboard.inline_keyboard.len(), type_rows + 8);
+        assert_eq!(
+            keyboard.inline_keyboard[type_rows][0].text,
+            "✅ Subscribe to all"
+        );
+
+        let subs: Vec<String> = WasteType::supported_types()
+            .iter()
+            .map(|w| w.as_str().to_string())
+            .collect();
+        let morning = store::NotifyTime {
+            notify_time: "06:00".to_string(),
+            notify_offset: 0,
+        };
+        let keyboard = build_settings_keyboard(&loc, &subs, &[morning], Lang::En);
+        assert_eq!(keyboard.inline_keyboard.len(), type_rows + 8);
+        assert_eq!(
+            keyboard.inline_keyboard[type_rows][0].text,
+            "❌ Unsubscribe from all"
+        );
+        let extra_row = &keyboard.inline_keyboard[type_rows + 4];
+        assert_eq!(extra_row[0].text, "✅ 🌅 Also the morning of, 06:00");
+        assert_eq!(extra_row[1].text, "❌ 🌙 Also the evening before, 18:00");
+        let extra_actions: Vec<_> = extra_row
+            .iter()
+            .map(|button| match &button.kind {
+                InlineKeyboardButtonKind::CallbackData(data) => CallbackAction::parse(data),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            extra_actions,
+            vec![
+                Some(CallbackAction::RemoveNotifyTime(3, "06:00".into(), 0)),
+                Some(CallbackAction::AddNotifyTime(3, "18:00".into(), 1)),
+            ]
+        );
+
+        // Every type button still parses back to its own type
+        let actions: Vec<_> = keyboard.inline_keyboard[..type_rows]
+            .iter()
+            .flatten()
+            .map(|button| match &button.kind {
+                InlineKeyboardButtonKind::CallbackData(data) => CallbackAction::parse(data),
+                _ => None,
+            })
+            .collect();
+        let expected: Vec<_> = subs
+            .iter()
+            .map(|w| Some(CallbackAction::Unsubscribe(3, w.clone())))
+            .collect();
+        assert_eq!(actions, expected);
+    }
+
+    #[test]
+    fn test_settings_change_from_action() {
+        let change = |data| SettingsChange::from_action(&CallbackAction::parse(data).unwrap());
+        assert_eq!(
+            change("time:3:18:00"),
+            Some(SettingsChange::NotifyTime(3, "19:00".into()))
+        );
+        // Wraps around midnight
+        assert_eq!(
+            change("time:3:23:00"),
+            Some(SettingsChange::NotifyTime(3, "00:00".into()))
+        );
+        assert_eq!(
+            change("offset:3:1"),
+            Some(SettingsChange::NotifyOffset(3, 0))
+        );
+        assert_eq!(
+            change("offset:3:0"),
+            Some(SettingsChange::NotifyOffset(3, 1))
+        );
+        assert_eq!(
+            change("suball:3"),
+            Some(SettingsChange::SetAllSubscriptions(3, true))
+        );
+        assert_eq!(
+            change("unsub:3:Bio"),
+            Some(SettingsChange::Unsubscribe(3, "Bio".into()))
+        );
+        assert_eq!(
+            change("xadd:3:06:00:0"),
+            Some(SettingsChange::AddNotifyTime(3, "06:00".into(), 0))
+        );
+        assert_eq!(
+            change("xdel:3:18:00:1"),
+            Some(SettingsChange::RemoveNotifyTime(3, "18:00".into(), 1))
+        );
+        assert_eq!(change("both:3"), Some(SettingsChange::EveningAndMorning(3)));
+        assert_eq!(change("edit:3"), None);
+        assert_eq!(change("mute"), None);
+    }
+
+    #[test]
+    fn test_callback_origin() {
+        assert_eq!(callback_origin(None), CallbackOrigin::Unknown);
+
+        // Telegram marks messages it won't let us edit anymore with date 0
+        let expired: MaybeInaccessibleMessage = serde_json::from_str(
+            r#"{"chat": {"id": 42, "type": "private", "first_name": "A"}, "message_id": 7, "date": 0}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            callback_origin(Some(&expired)),
+            CallbackOrigin::Expired(ChatId(42))
+        );
+    }
+
+    #[test]
+    fn test_age_label() {
+        let today = NaiveDate::from_ymd_opt(2024, 6, 7).unwrap();
+        let ago = |days| age_label(today - Duration::days(days), today, Lang::En);
+        assert_eq!(ago(0), "today");
+        assert_eq!(ago(1), "yesterday");
+        assert_eq!(ago(3), "3 days ago");
+        // A clock running slightly behind the last update still reads as today
+        assert_eq!(ago(-1), "today");
+        assert_eq!(
+            age_label(today - Duration::days(3), today, Lang::De),
+            "vor 3 Tagen"
+        );
+    }
+
+    #[test]
+    fn test_remind_before() {
+        assert_eq!(
+            parse_remind_before_hours(""),
+            Some(DEFAULT_REMIND_BEFORE_HOURS)
+        );
+        assert_eq!(parse_remind_before_hours(" 30 "), Some(30));
+        assert_eq!(parse_remind_before_hours("0"), None);
+        assert_eq!(parse_remind_before_hours("soon"), None);
+        assert_eq!(
+            parse_remind_before_hours(&(store::MAX_LEAD_HOURS + 1).to_string()),
+            None
+        );
+
+        let date = NaiveDate::from_ymd_opt(2024, 6, 7).unwrap();
+        let at = |hours| {
+            remind_before_at(date, hours)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        };
+        assert_eq!(
+            at(1),
+            format!("2024-06-07 {:02}:00", store::PICKUP_HOUR - 1)
+        );
+        // Lead times past midnight land on the evening before
+        assert_eq!(at(store::PICKUP_HOUR + 6), "2024-06-06 18:00");
+    }
+
+    #[test]
+    fn test_refresh_cooldown() {
+        let cooldowns = Cooldowns::default().refresh;
+        let start = Instant::now();
+        assert!(cooldowns.try_acquire(1, start).is_ok());
+        // Other chats aren't affected
+        assert!(cooldowns.try_acquire(2, start).is_ok());
+
+        let soon = start + std::time::Duration::from_secs(60);
+        assert_eq!(
+            cooldowns.try_acquire(1, soon),
+            Err(REFRESH_COOLDOWN - std::time::Duration::from_secs(60))
+        );
+        assert!(cooldowns.try_acquire(1, start + REFRESH_COOLDOWN).is_ok());
+    }
+
+    #[test]
+    fn test_cooldown_bounded() {
+        let cooldown = Cooldown::new(LOCATION_COOLDOWN);
+        let start = Instant::now();
+        for chat_id in 0..MAX_COOLDOWN_CHATS as i64 {
+            assert!(cooldown.try_acquire(chat_id, start).is_ok());
+        }
+        // Full, and everyone still cooling down: the oldest entry makes room
+        let later = start + std::time::Duration::from_secs(1);
+        assert!(cooldown.try_acquire(-1, later).is_ok());
+        assert_eq!(cooldown.last.lock().unwrap().len(), MAX_COOLDOWN_CHATS);
+        assert!(cooldown.try_acquire(-1, later).is_err());
+
+        // Once their cooldown has run out, old chats are dropped together
+        let expired = start + LOCATION_COOLDOWN;
+        assert!(cooldown.try_acquire(-2, expired).is_ok());
+        assert_eq!(cooldown.last.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_known_waste_type() {
+        assert_eq!(known_waste_type("Bio"), Some(WasteType::Bio));
+        assert_eq!(known_waste_type(" restmüll "), Some(WasteType::Rest));
+        assert_eq!(known_waste_type("GELBE TONNE"), Some(WasteType::Yellow));
+        assert_eq!(known_waste_type("Sperrmuell"), Some(WasteType::Bulky));
+        assert_eq!(known_waste_type("Glas"), None);
+        assert_eq!(known_waste_type(""), None);
+    }
+
+    #[test]
+    fn test_split_set_location_args() {
+        assert_eq!(split_set_location_args(""), None);
+        assert_eq!(split_set_location_args("  "), None);
+        assert_eq!(split_set_location_args("12345"), Some(("12345", None)));
+        assert_eq!(
+            split_set_location_args(" 12345  Zu Hause "),
+            Some(("12345", Some("Zu Hause")))
+        );
+    }
+
+    #[test]
+    fn test_parse_callback_action_rejects_malformed() {
+        for data in [
+            "",
+            ":",
+            "edit",
+            "edit:",
+            "edit:abc",
+            "edit:3:extra",
+            "sub:3",
+            "sub:3:",
+            "unsub:x:Bio",
+            "time:3",
+            "offset:3:yes",
+            "mute:now",
+            "snooze:2024-06-07:",
+            "snooze:tomorrow:Bio",
+            "unknown:1",
+        ] {
+            assert_eq!(CallbackAction::parse(data), None, "{:?}", data);
+        }
+    }
+}
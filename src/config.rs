@@ -0,0 +1,196 @@
+use chrono::Weekday;
+use std::env;
+use std::fmt::Display;
+use std::str::FromStr;
+use teloxide::types::ChatId;
+use tracing::warn;
+
+/// Reads a positive number from the environment variable `name`.
+///
+/// Falls back to `default` when the variable is unset. Values that don't parse or
+/// aren't positive are ignored with a warning so a typo doesn't take the bot down.
+pub fn positive_from_env<T>(name: &str, default: T) -> T
+where
+    T: FromStr + PartialOrd + Default + Display + Copy,
+{
+    match env::var(name) {
+        Ok(raw) => match raw.trim().parse::<T>() {
+            Ok(value) if value > T::default() => value,
+            _ => {
+                warn!(
+                    "Invalid value {:?} for {}; expected a positive integer. Using default {}.",
+                    raw, name, default
+                );
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+/// Like `positive_from_env`, but also accepts 0, for settings where it turns something off.
+pub fn non_negative_from_env<T>(name: &str, default: T) -> T
+where
+    T: FromStr + PartialOrd + Default + Display + Copy,
+{
+    match env::var(name) {
+        Ok(raw) => match raw.trim().parse::<T>() {
+            Ok(value) if value >= T::default() => value,
+            _ => {
+                warn!(
+                    "Invalid value {:?} for {}; expected 0 or more. Using default {}.",
+                    raw, name, default
+                );
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+/// Operator chat for feedback and admin-only commands (`ADMIN_CHAT_ID`).
+/// Group chat IDs are negative, so any integer is accepted.
+pub fn admin_chat_id() -> Option<ChatId> {
+    let raw = env::var("ADMIN_CHAT_ID").ok()?;
+    match raw.trim().parse::<i64>() {
+        Ok(id) => Some(ChatId(id)),
+        Err(_) => {
+            warn!(
+                "Invalid ADMIN_CHAT_ID {:?}; admin features are disabled.",
+                raw
+            );
+            None
+        }
+    }
+}
+
+/// Port for the Prometheus `/metrics` and the `/healthz` endpoint (`METRICS_PORT`);
+/// unset disables both.
+pub fn metrics_port() -> Option<u16> {
+    let raw = env::var("METRICS_PORT").ok()?;
+    match raw.trim().parse::<u16>() {
+        Ok(port) if port > 0 => Some(port),
+        _ => {
+            warn!(
+                "Invalid METRICS_PORT {:?}; metrics endpoint is disabled.",
+                raw
+            );
+            None
+        }
+    }
+}
+
+/// Minimum gap between repeated empty-feed reminders, so they stay gentle.
+const MIN_EMPTY_FEED_REMINDER_DAYS: i64 = 7;
+
+/// Days between reminders while a location's feed stays empty
+/// (`EMPTY_FEED_REMINDER_DAYS`). Unset means users are only warned once.
+pub fn empty_feed_reminder_days() -> Option<i64> {
+    let raw = env::var("EMPTY_FEED_REMINDER_DAYS").ok()?;
+    match raw.trim().parse::<i64>() {
+        Ok(days) if days >= MIN_EMPTY_FEED_REMINDER_DAYS => Some(days),
+        Ok(days) if days > 0 => {
+            warn!(
+                "EMPTY_FEED_REMINDER_DAYS {} is below the minimum; using {}.",
+                days, MIN_EMPTY_FEED_REMINDER_DAYS
+            );
+            Some(MIN_EMPTY_FEED_REMINDER_DAYS)
+        }
+        _ => {
+            warn!(
+                "Invalid EMPTY_FEED_REMINDER_DAYS {:?}; reminders are disabled.",
+                raw
+            );
+            None
+        }
+    }
+}
+
+/// Whether the environment variable `name` is set to something truthy.
+/// Empty, `0` and `false` count as unset.
+pub fn flag_from_env(name: &str) -> bool {
+    env::var(name)
+        .map(|raw| !matches!(raw.trim().to_lowercase().as_str(), "" | "0" | "false"))
+        .unwrap_or(false)
+}
+
+/// Whether `DRY_RUN` is set, in which case messages are logged instead of sent.
+pub fn dry_run() -> bool {
+    flag_from_env("DRY_RUN")
+}
+
+/// Whether `CACHE_RAW_ICAL` is set, in which case the last fetched feed body of every
+/// location is kept for the admin's /rawfeed.
+pub fn cache_raw_ical() -> bool {
+    flag_from_env("CACHE_RAW_ICAL")
+}
+
+/// When the weekly digest goes out (`DIGEST_WEEKDAY`, `DIGEST_HOUR`): Sunday, 18:00
+/// unless configured otherwise.
+pub fn digest_schedule() -> (Weekday, u32) {
+    let weekday = match env::var("DIGEST_WEEKDAY") {
+        Ok(raw) => raw.trim().parse::<Weekday>().unwrap_or_else(|_| {
+            warn!("Invalid DIGEST_WEEKDAY {:?}; using Sunday.", raw);
+            Weekday::Sun
+        }),
+        Err(_) => Weekday::Sun,
+    };
+    let hour = match env::var("DIGEST_HOUR") {
+        Ok(raw) => match raw.trim().parse::<u32>() {
+            Ok(hour) if hour < 24 => hour,
+            _ => {
+                warn!("Invalid DIGEST_HOUR {:?}; using 18.", raw);
+                18
+            }
+        },
+        Err(_) => 18,
+    };
+    (weekday, hour)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positive_from_env() {
+        assert_eq!(positive_from_env("DWB_TEST_UNSET", 28i64), 28);
+
+        env::set_var("DWB_TEST_VALID", " 7 ");
+        assert_eq!(positive_from_env("DWB_TEST_VALID", 28i64), 7);
+
+        env::set_var("DWB_TEST_ZERO", "0");
+        assert_eq!(positive_from_env("DWB_TEST_ZERO", 28i64), 28);
+
+        env::set_var("DWB_TEST_GARBAGE", "weekly");
+        assert_eq!(positive_from_env("DWB_TEST_GARBAGE", 28i64), 28);
+
+        // Where 0 means off, it is accepted
+        assert_eq!(non_negative_from_env("DWB_TEST_ZERO", 28i64), 0);
+        env::set_var("DWB_TEST_NEGATIVE", "-1");
+        assert_eq!(non_negative_from_env("DWB_TEST_NEGATIVE", 28i64), 28);
+    }
+
+    #[test]
+    fn test_dry_run() {
+        env::set_var("DRY_RUN", "false");
+        assert!(!dry_run());
+        env::set_var("DRY_RUN", "1");
+        assert!(dry_run());
+        env::remove_var("DRY_RUN");
+        assert!(!dry_run());
+    }
+
+    #[test]
+    fn test_digest_schedule() {
+        assert_eq!(digest_schedule(), (Weekday::Sun, 18));
+        env::set_var("DIGEST_WEEKDAY", "saturday");
+        env::set_var("DIGEST_HOUR", "9");
+        assert_eq!(digest_schedule(), (Weekday::Sat, 9));
+        env::set_var("DIGEST_WEEKDAY", "someday");
+        env::set_var("DIGEST_HOUR", "24");
+        assert_eq!(digest_schedule(), (Weekday::Sun, 18));
+        env::remove_var("DIGEST_WEEKDAY");
+        env::remove_var("DIGEST_HOUR");
+    }
+}
@@ -1,12 +1,26 @@
-use anyhow::{Context, Result};
-use log::info;
+use anyhow::{bail, Context, Result};
 use sqlx::migrate::MigrateDatabase;
-use sqlx::sqlite::SqlitePool;
+use sqlx::sqlite::{SqliteJournalMode, SqlitePool};
 use std::env;
 use std::str::FromStr;
+use std::time::Duration;
+use tracing::{info, warn};
 
 pub type DbPool = SqlitePool;
 
+/// SQL condition for a well-formed `notify_time` (`HH:MM`, 00:00 to 23:59), enforced by
+/// a CHECK on user_locations. `store::is_valid_notify_time` is the Rust counterpart.
+const NOTIFY_TIME_VALID: &str =
+    "notify_time GLOB '[0-2][0-9]:[0-5][0-9]' AND notify_time < '24:00'";
+
+/// SQL condition for a custom lead time within `0..=store::MAX_LEAD_HOURS`, or none.
+fn lead_hours_valid() -> String {
+    format!(
+        "notify_offset_hours IS NULL OR notify_offset_hours BETWEEN 0 AND {}",
+        crate::store::MAX_LEAD_HOURS
+    )
+}
+
 pub async fn create_schema(pool: &DbPool) -> Result<()> {
     // Users table
     sqlx::query(
@@ -35,37 +49,58 @@ pub async fn create_schema(pool: &DbPool) -> Result<()> {
     .await
     .context("Failed to create user_locations table")?;
 
-    // Attempt to add notify_offset column if it doesn't exist.
-    // SQLite doesn't support IF NOT EXISTS for columns directly.
-    // We can just try to add it and ignore the error if it fails (duplicate column).
-    match sqlx::query("ALTER TABLE user_locations ADD COLUMN notify_offset INTEGER NOT NULL DEFAULT 1")
-        .execute(pool)
-        .await
-    {
-        Ok(_) => info!("Added notify_offset column to user_locations"),
-        Err(e) => {
-            // Check if error is due to column already existing.
-            // Sqlite error code for generic error is 1, but we can check the message.
-            let msg = e.to_string();
-            if !msg.contains("duplicate column name") {
-                 // If it's not "duplicate column", then it's a real error
-                 // However, for robustness in this simple bot, we might just log it.
-                 // But wait, if the table was just created, it doesn't have the column yet?
-                 // Ah, CREATE TABLE above does NOT have notify_offset in the SQL string anymore?
-                 // I should include it in CREATE TABLE for fresh installs, AND have ALTER TABLE for migrations.
-                 // Or keep CREATE TABLE simple (v1) and let ALTER TABLE (v2) handle it?
-                 // Best practice: CREATE TABLE should be the *latest* schema.
-                 // But if table exists (old schema), CREATE TABLE does nothing.
-                 // Then ALTER TABLE adds the column.
-                 // So I should keep CREATE TABLE with *new* schema?
-                 // If I keep CREATE TABLE with new schema, and table exists (old schema), CREATE does nothing.
-                 // Then ALTER TABLE runs and adds column.
-                 // If table exists (new schema), CREATE does nothing. ALTER TABLE fails with "duplicate column".
-                 // This seems correct.
-                 info!("Column notify_offset might already exist: {}", e);
-            }
-        }
-    }
+    // 1 = Day Before, 0 = Same Day
+    add_column(
+        pool,
+        "user_locations",
+        "notify_offset INTEGER NOT NULL DEFAULT 1",
+    )
+    .await?;
+
+    // Hours before the pickup to send the reminder. NULL uses notify_time/notify_offset.
+    add_column(pool, "user_locations", "notify_offset_hours INTEGER").await?;
+
+    // Set once the user was told their feed has no upcoming pickups
+    add_column(
+        pool,
+        "user_locations",
+        "empty_feed_warned INTEGER NOT NULL DEFAULT 0",
+    )
+    .await?;
+    // ...and when, for the optional periodic reminder
+    add_column(pool, "user_locations", "empty_feed_warned_at DATETIME").await?;
+
+    // Locations table: metadata about each Standort-ID, shared by all users of it
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS locations (
+            location_id TEXT PRIMARY KEY,
+            name TEXT
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create locations table")?;
+
+    // When the location's feed was last fetched and stored successfully
+    add_column(pool, "locations", "last_updated DATETIME").await?;
+    // Set by the monthly check while the feed has no upcoming pickups, so users of a
+    // decommissioned location are told once rather than every month
+    add_column(
+        pool,
+        "locations",
+        "needs_attention INTEGER NOT NULL DEFAULT 0",
+    )
+    .await?;
+
+    // Every configured location has its row in `locations`, referenced by user_locations
+    sqlx::query(
+        "INSERT OR IGNORE INTO locations (location_id)
+         SELECT DISTINCT location_id FROM user_locations",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to backfill locations")?;
+    rebuild_user_locations(pool).await?;
 
     sqlx::query(
         "CREATE INDEX IF NOT EXISTS idx_user_locations_user_id ON user_locations(user_id);",
@@ -82,6 +117,21 @@ pub async fn create_schema(pool: &DbPool) -> Result<()> {
     .await
     .context("Failed to create index on user_locations(notify_time)")?;
 
+    // Extra reminders of a user location, on top of its main notify_time/notify_offset
+    sqlx::query(&format!(
+        "CREATE TABLE IF NOT EXISTS notify_times (
+            user_location_id INTEGER NOT NULL,
+            notify_time TEXT NOT NULL CHECK ({}),
+            notify_offset INTEGER NOT NULL CHECK (notify_offset IN (0, 1)),
+            PRIMARY KEY (user_location_id, notify_time, notify_offset),
+            FOREIGN KEY (user_location_id) REFERENCES user_locations(id) ON DELETE CASCADE
+        );",
+        NOTIFY_TIME_VALID
+    ))
+    .execute(pool)
+    .await
+    .context("Failed to create notify_times table")?;
+
     // Subscriptions table (now linked to user_locations)
     sqlx::query(
         "CREATE TABLE IF NOT EXISTS subscriptions (
@@ -109,15 +159,396 @@ pub async fn create_schema(pool: &DbPool) -> Result<()> {
     .await
     .context("Failed to create pickup_events table")?;
 
+    // The feed's UID, so an event keeps its row when it moves to another day
+    add_column(pool, "pickup_events", "uid TEXT").await?;
+    // One event can list several waste types, each stored as its own row
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_pickup_events_uid
+         ON pickup_events(location_id, uid, waste_type) WHERE uid IS NOT NULL;",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create index on pickup_events(location_id, uid, waste_type)")?;
+
     // Index on pickup_events(date) for faster daily notifications
     sqlx::query("CREATE INDEX IF NOT EXISTS idx_pickup_events_date ON pickup_events(date);")
         .execute(pool)
         .await
         .context("Failed to create index on pickup_events(date)")?;
 
+    // Log of sent notifications, so a slot that fires twice doesn't notify twice
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS notified_log (
+            chat_id INTEGER NOT NULL,
+            location_id TEXT NOT NULL,
+            waste_type TEXT NOT NULL,
+            date DATE NOT NULL,
+            sent_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (chat_id, location_id, waste_type, date),
+            FOREIGN KEY (chat_id) REFERENCES users(id) ON DELETE CASCADE
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create notified_log table")?;
+
+    // Notifications that failed to send for a transient reason, retried on the next tick
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS failed_notifications (
+            chat_id INTEGER NOT NULL,
+            location_id TEXT NOT NULL,
+            waste_type TEXT NOT NULL,
+            date DATE NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 1,
+            last_error TEXT,
+            failed_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (chat_id, location_id, waste_type, date),
+            FOREIGN KEY (chat_id) REFERENCES users(id) ON DELETE CASCADE
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create failed_notifications table")?;
+    add_reminder_slot(pool).await?;
+
+    // Vacation mode: no notifications up to and including this date
+    add_column(pool, "users", "mute_until DATE").await?;
+
+    // Serialized dialogue state per chat, see `SqliteDialogueStorage`
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS dialogues (
+            chat_id INTEGER PRIMARY KEY,
+            state TEXT NOT NULL,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create dialogues table")?;
+
+    // Message language. Users from before translations existed keep English;
+    // `create_user` sets German for everyone new.
+    add_column(pool, "users", "language TEXT NOT NULL DEFAULT 'en'").await?;
+
+    // 'per_event' reminders or one 'weekly_digest', see `store::NotifyMode`
+    add_column(
+        pool,
+        "users",
+        "notify_mode TEXT NOT NULL DEFAULT 'per_event'",
+    )
+    .await?;
+
+    // The user's own reminder line, see `i18n::fill_template`; NULL uses the default text
+    add_column(pool, "users", "message_template TEXT").await?;
+
+    // Opted in to a note on the digest day when the coming week has no pickups
+    add_column(
+        pool,
+        "users",
+        "quiet_week_notice INTEGER NOT NULL DEFAULT 0",
+    )
+    .await?;
+
+    // One-off re-sends of a reminder, requested with the snooze button
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS snoozes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            chat_id INTEGER NOT NULL,
+            fire_at DATETIME NOT NULL,
+            event_date DATE NOT NULL,
+            waste_types TEXT NOT NULL, -- comma-separated
+            UNIQUE(chat_id, fire_at, event_date),
+            FOREIGN KEY (chat_id) REFERENCES users(id) ON DELETE CASCADE
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create snoozes table")?;
+
+    // Last fetched feed body per location, kept for debugging when CACHE_RAW_ICAL is set
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS raw_ical_cache (
+            location_id TEXT PRIMARY KEY,
+            body TEXT NOT NULL,
+            fetched_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create raw_ical_cache table")?;
+
     Ok(())
 }
 
+/// Rebuilds user_locations with a foreign key on `locations` and CHECKs on the `HH:MM`
+/// format of `notify_time` and the range of `notify_offset_hours`, none of which SQLite
+/// can add to an existing table. Runs once; afterwards all are found and nothing happens.
+async fn rebuild_user_locations(pool: &DbPool) -> Result<()> {
+    let has_key: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM pragma_foreign_key_list('user_locations')
+                        WHERE \"table\" = 'locations')",
+    )
+    .fetch_one(pool)
+    .await?;
+    let has_check: bool = sqlx::query_scalar(
+        "SELECT sql LIKE '%CHECK (notify_time%' AND sql LIKE '%CHECK (notify_offset_hours%'
+         FROM sqlite_master
+         WHERE type = 'table' AND name = 'user_locations'",
+    )
+    .fetch_one(pool)
+    .await?;
+    if has_key && has_check {
+        return Ok(());
+    }
+
+    // Rows that wouldn't pass the new CHECK fall back to the default time
+    let invalid: i64 = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM user_locations WHERE NOT ({})",
+        NOTIFY_TIME_VALID
+    ))
+    .fetch_one(pool)
+    .await?;
+    if invalid > 0 {
+        warn!("Resetting {} invalid notify_time values to 18:00", invalid);
+    }
+    let invalid_lead: i64 = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM user_locations WHERE NOT ({})",
+        lead_hours_valid()
+    ))
+    .fetch_one(pool)
+    .await?;
+    if invalid_lead > 0 {
+        warn!(
+            "Clearing {} out-of-range notify_offset_hours values",
+            invalid_lead
+        );
+    }
+
+    // Dropping the old table would cascade into subscriptions, so foreign keys are off
+    // for the copy. The pragma is per connection and can't change inside a transaction.
+    let mut conn = pool.acquire().await?;
+    sqlx::query("PRAGMA foreign_keys = OFF")
+        .execute(&mut *conn)
+        .await?;
+    let result = async {
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+        sqlx::query(&format!(
+            "CREATE TABLE user_locations_new (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                location_id TEXT NOT NULL,
+                notify_time TEXT NOT NULL DEFAULT '18:00' CHECK ({}),
+                alias TEXT,
+                notify_offset INTEGER NOT NULL DEFAULT 1,
+                notify_offset_hours INTEGER CHECK ({}),
+                empty_feed_warned INTEGER NOT NULL DEFAULT 0,
+                empty_feed_warned_at DATETIME,
+                FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+                FOREIGN KEY (location_id) REFERENCES locations(location_id),
+                UNIQUE(user_id, location_id)
+            );",
+            NOTIFY_TIME_VALID,
+            lead_hours_valid()
+        ))
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!(
+            "INSERT INTO user_locations_new
+                 (id, user_id, location_id, notify_time, alias, notify_offset,
+                  notify_offset_hours, empty_feed_warned, empty_feed_warned_at)
+             SELECT id, user_id, location_id,
+                    CASE WHEN {} THEN notify_time ELSE '18:00' END,
+                    alias, notify_offset,
+                    CASE WHEN {} THEN notify_offset_hours END,
+                    empty_feed_warned, empty_feed_warned_at
+             FROM user_locations",
+            NOTIFY_TIME_VALID,
+            lead_hours_valid()
+        ))
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DROP TABLE user_locations")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("ALTER TABLE user_locations_new RENAME TO user_locations")
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        anyhow::Ok(())
+    }
+    .await;
+    sqlx::query("PRAGMA foreign_keys = ON")
+        .execute(&mut *conn)
+        .await?;
+    result.context("Failed to rebuild user_locations")?;
+
+    info!("Rebuilt user_locations with the locations foreign key and value checks");
+    Ok(())
+}
+
+/// Rebuilds notified_log and failed_notifications with the reminder's `slot` in their
+/// primary key, so an extra reminder from notify_times isn't mistaken for the main one
+/// of the same pickup. Existing rows belong to the main reminder (''). Runs once.
+async fn add_reminder_slot(pool: &DbPool) -> Result<()> {
+    let has_slot: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info('notified_log') WHERE name = 'slot')",
+    )
+    .fetch_one(pool)
+    .await?;
+    if has_slot {
+        return Ok(());
+    }
+
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "CREATE TABLE notified_log_new (
+            chat_id INTEGER NOT NULL,
+            location_id TEXT NOT NULL,
+            waste_type TEXT NOT NULL,
+            date DATE NOT NULL,
+            sent_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            slot TEXT NOT NULL DEFAULT '',
+            PRIMARY KEY (chat_id, location_id, waste_type, date, slot),
+            FOREIGN KEY (chat_id) REFERENCES users(id) ON DELETE CASCADE
+        );",
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        "INSERT INTO notified_log_new (chat_id, location_id, waste_type, date, sent_at)
+         SELECT chat_id, location_id, waste_type, date, sent_at FROM notified_log",
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        "CREATE TABLE failed_notifications_new (
+            chat_id INTEGER NOT NULL,
+            location_id TEXT NOT NULL,
+            waste_type TEXT NOT NULL,
+            date DATE NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 1,
+            last_error TEXT,
+            failed_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            slot TEXT NOT NULL DEFAULT '',
+            PRIMARY KEY (chat_id, location_id, waste_type, date, slot),
+            FOREIGN KEY (chat_id) REFERENCES users(id) ON DELETE CASCADE
+        );",
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        "INSERT INTO failed_notifications_new
+             (chat_id, location_id, waste_type, date, attempts, last_error, failed_at)
+         SELECT chat_id, location_id, waste_type, date, attempts, last_error, failed_at
+         FROM failed_notifications",
+    )
+    .execute(&mut *tx)
+    .await?;
+    for table in ["notified_log", "failed_notifications"] {
+        sqlx::query(&format!("DROP TABLE {}", table))
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&format!("ALTER TABLE {0}_new RENAME TO {0}", table))
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit()
+        .await
+        .context("Failed to add the slot column to the notification logs")?;
+
+    info!("Added slot column to notified_log and failed_notifications");
+    Ok(())
+}
+
+/// Adds a column to an existing table.
+///
+/// SQLite has no `ADD COLUMN IF NOT EXISTS`, so the statement is simply attempted and a
+/// "duplicate column name" error is treated as success. CREATE TABLE statements above
+/// keep the original schema; columns added later are always introduced through here so
+/// fresh and upgraded databases end up identical.
+async fn add_column(pool: &DbPool, table: &str, definition: &str) -> Result<()> {
+    let column = definition.split_whitespace().next().unwrap_or(definition);
+    match sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {}", table, definition))
+        .execute(pool)
+        .await
+    {
+        Ok(_) => {
+            info!("Added {} column to {}", column, table);
+            Ok(())
+        }
+        Err(e) if e.to_string().contains("duplicate column name") => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Failed to add {} column to {}", column, table)),
+    }
+}
+
+/// Describes why an existing database can't be used with the current schema, or `None`
+/// if it can. Databases from the early single-location versions were set up by sqlx
+/// migrations with subscriptions keyed by user; `create_schema` can't upgrade those, and
+/// without this check they only fail later with confusing query errors.
+pub async fn schema_mismatch(pool: &DbPool) -> Result<Option<String>> {
+    let has_migrations: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations')",
+    )
+    .fetch_one(pool)
+    .await?;
+    if has_migrations {
+        return Ok(Some(
+            "it was created by the old migration-based schema (_sqlx_migrations table)".into(),
+        ));
+    }
+
+    let subscription_columns: Vec<String> =
+        sqlx::query_scalar("SELECT name FROM pragma_table_info('subscriptions')")
+            .fetch_all(pool)
+            .await?;
+    if !subscription_columns.is_empty()
+        && !subscription_columns.iter().any(|c| c == "user_location_id")
+    {
+        return Ok(Some(
+            "its subscriptions table has no user_location_id column".into(),
+        ));
+    }
+
+    Ok(None)
+}
+
+/// How long a connection waits for another one's write lock before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// sqlx's own pool defaults, kept so an unconfigured bot behaves as before.
+const DEFAULT_MAX_CONNECTIONS: u32 = 10;
+const DEFAULT_ACQUIRE_TIMEOUT_SECS: u64 = 30;
+
+/// Opens the pool with the same settings on every connection: WAL so the bot can read
+/// while the scheduler writes, a busy timeout instead of immediate "database is locked"
+/// errors, and foreign keys, which the cascading deletes rely on.
+///
+/// The pool size and how long a query waits for a free connection come from
+/// `DB_MAX_CONNECTIONS` and `DB_ACQUIRE_TIMEOUT_SECS`.
+pub(crate) async fn connect(database_url: &str) -> Result<DbPool> {
+    let max_connections =
+        crate::config::positive_from_env("DB_MAX_CONNECTIONS", DEFAULT_MAX_CONNECTIONS);
+    let acquire_timeout = Duration::from_secs(crate::config::positive_from_env(
+        "DB_ACQUIRE_TIMEOUT_SECS",
+        DEFAULT_ACQUIRE_TIMEOUT_SECS,
+    ));
+    info!(
+        "Database pool: up to {} connections, {:?} acquire timeout",
+        max_connections, acquire_timeout
+    );
+    sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(max_connections)
+        .acquire_timeout(acquire_timeout)
+        .connect_with(
+            sqlx::sqlite::SqliteConnectOptions::from_str(database_url)?
+                .journal_mode(SqliteJournalMode::Wal)
+                .busy_timeout(BUSY_TIMEOUT)
+                .foreign_keys(true),
+        )
+        .await
+        .context("Failed to connect to database")
+}
+
 pub async fn init_db() -> Result<DbPool> {
     let database_url =
         env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite:waste_bot.db".to_string());
@@ -134,12 +565,30 @@ pub async fn init_db() -> Result<DbPool> {
         println!("Database {} already exists", database_url);
     }
 
-    let pool = sqlx::sqlite::SqlitePoolOptions::new()
-        .connect_with(
-            sqlx::sqlite::SqliteConnectOptions::from_str(&database_url)?.foreign_keys(true),
-        )
-        .await
-        .context("Failed to connect to database")?;
+    let mut pool = connect(&database_url).await?;
+
+    if let Some(problem) = schema_mismatch(&pool).await? {
+        if !crate::config::flag_from_env("DB_RESET_ON_MISMATCH") {
+            bail!(
+                "Database {} is incompatible with this version: {}. Back it up and remove it, \
+                 or set DB_RESET_ON_MISMATCH=1 to recreate it empty (this deletes all data).",
+                database_url,
+                problem
+            );
+        }
+        warn!(
+            "Database {} is incompatible ({}); DB_RESET_ON_MISMATCH is set, recreating it.",
+            database_url, problem
+        );
+        pool.close().await;
+        sqlx::Sqlite::drop_database(&database_url)
+            .await
+            .context("Failed to remove incompatible database")?;
+        sqlx::Sqlite::create_database(&database_url)
+            .await
+            .context("Failed to create database")?;
+        pool = connect(&database_url).await?;
+    }
 
     create_schema(&pool).await?;
 
@@ -1,11 +1,49 @@
+use crate::bot_handler::{CallbackAction, SettingsChange, State};
+use crate::dialogue_storage::SqliteDialogueStorage;
+use crate::i18n::Lang;
 use crate::store::{
-    add_subscription, add_user_location, create_user, delete_user, delete_user_location,
-    get_subscriptions, get_user_locations, update_notify_time, upsert_events,
+    add_notify_time, add_snooze, add_subscription, add_user_location,
+    add_user_location_with_defaults, clear_location_stale, count_notifications_on,
+    count_upcoming_events, count_users, create_user, delete_user, delete_user_location,
+    export_user, get_active_location_ids, get_all_chat_ids, get_events_in_range,
+    get_failed_notifications, get_language, get_last_update, get_location_notify_times,
+    get_location_users, get_next_pickup, get_notify_mode, get_notify_times, get_quiet_week_users,
+    get_raw_ical, get_subscriptions, get_user, get_user_locations, get_user_pickups,
+    get_users_to_notify, import_user, is_valid_notify_time, last_feed_update, lead_time_slot,
+    mark_location_stale, mark_location_updated, notification_stats, ping, prune_old_events,
+    record_notification, release_empty_feed_warning, remove_notify_time, renormalize_waste_types,
+    reset_empty_feed_warning, save_raw_ical, set_all_subscriptions, set_language,
+    set_message_template, set_mute_until, set_notify_mode, set_quiet_week_notice, take_due_snoozes,
+    take_empty_feed_warnings, update_location_name, update_notify_offset_hours, update_notify_time,
+    upsert_events, users_per_location, NotificationStats, NotifyMode, NotifySlot, NotifyTime,
+    RenormalizeCounts, UserExport, MAX_LEAD_HOURS, MAX_RAW_ICAL_BYTES,
 };
 use crate::waste::{PickupEvent, WasteType};
+use chrono::NaiveDate;
 use sqlx::sqlite::SqlitePoolOptions;
 use std::env;
 use std::str::FromStr;
+use teloxide::dispatching::dialogue::Storage;
+use teloxide::types::ChatId;
+
+/// A fresh in-memory database with the current schema.
+async fn test_pool() -> sqlx::SqlitePool {
+    let pool = memory_pool().await;
+    crate::db::create_schema(&pool).await.unwrap();
+    pool
+}
+
+/// An empty in-memory database, for tests that set up their own schema.
+async fn memory_pool() -> sqlx::SqlitePool {
+    SqlitePoolOptions::new()
+        .connect_with(
+            sqlx::sqlite::SqliteConnectOptions::from_str("sqlite::memory:")
+                .unwrap()
+                .foreign_keys(true),
+        )
+        .await
+        .unwrap()
+}
 
 #[tokio::test]
 async fn test_db_operations() {
@@ -70,14 +108,13 @@ async fn test_db_operations() {
     // Test Events
     // Use dynamic date to ensure it's not filtered out by "today" check in upsert_events
     let today = chrono::Local::now().date_naive();
-    let today_str = today.format("%Y-%m-%d").to_string();
-    let tomorrow_str = (today + chrono::Duration::days(1))
-        .format("%Y-%m-%d")
-        .to_string();
+    let tomorrow = today + chrono::Duration::days(1);
 
     let event = PickupEvent {
         date: today,
         waste_types: vec![WasteType::Bio],
+        all_day: true,
+        uid: None,
     };
     upsert_events(&pool, "LOC1", &[event]).await.unwrap();
 
@@ -92,10 +129,8 @@ async fn test_db_operations() {
         .unwrap();
 
     let tasks = crate::store::get_users_to_notify(
-        &pool,
-        "06:00",
-        &today_str,    // today
-        &tomorrow_str, // tomorrow
+        &pool, "06:00", today,    // today
+        tomorrow, // tomorrow
     )
     .await
     .unwrap();
@@ -131,6 +166,8 @@ async fn test_batch_insert() {
         events.push(PickupEvent {
             date: today + chrono::Duration::days(i),
             waste_types: vec![WasteType::Bio],
+            all_day: true,
+            uid: None,
         });
     }
 
@@ -144,6 +181,19 @@ async fn test_batch_insert() {
             .unwrap();
 
     assert_eq!(count, 1000);
+
+    // Refreshing across several batches keeps every row and drops the ones that are gone
+    let ids = || async {
+        sqlx::query_scalar::<_, i64>("SELECT id FROM pickup_events ORDER BY date")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+    };
+    let before = ids().await;
+    upsert_events(&pool, "LOC_BATCH", &events[..600])
+        .await
+        .unwrap();
+    assert_eq!(ids().await, before[..600]);
 }
 
 #[tokio::test]
@@ -186,6 +236,10 @@ async fn test_multiple_locations() {
 
     let l2 = locations.iter().find(|l| l.location_id == "LOC2").unwrap();
     assert_eq!(l2.notify_time, "08:00");
+    assert_eq!(
+        get_notify_times(&pool).await.unwrap(),
+        vec!["08:00", "18:00"]
+    );
 
     // Test delete location by alias
     delete_user_location(&pool, chat_id, "Home").await.unwrap();
@@ -193,3 +247,2003 @@ async fn test_multiple_locations() {
     assert_eq!(locations.len(), 1);
     assert_eq!(locations[0].alias.as_deref(), Some("Office"));
 }
+
+#[tokio::test]
+async fn test_extra_notify_times() {
+    let pool = test_pool().await;
+
+    let chat_id = 42;
+    let loc_id = add_user_location(&pool, chat_id, "LOC1", None)
+        .await
+        .unwrap();
+    add_subscription(&pool, loc_id, "Bio").await.unwrap();
+    // Main reminder the evening before, extra one on the morning of the pickup
+    update_notify_time(&pool, chat_id, "LOC1", "18:00")
+        .await
+        .unwrap();
+    add_notify_time(&pool, loc_id, "06:00", 0).await.unwrap();
+    add_notify_time(&pool, loc_id, "06:00", 0).await.unwrap();
+    assert!(add_notify_time(&pool, loc_id, "6am", 0).await.is_err());
+    assert_eq!(
+        get_location_notify_times(&pool, loc_id).await.unwrap(),
+        vec![NotifyTime {
+            notify_time: "06:00".to_string(),
+            notify_offset: 0
+        }]
+    );
+    assert_eq!(
+        get_notify_times(&pool).await.unwrap(),
+        vec!["06:00", "18:00"]
+    );
+
+    let today = chrono::Local::now().date_naive();
+    let tomorrow = today + chrono::Duration::days(1);
+    let day_after = tomorrow + chrono::Duration::days(1);
+    upsert_events(
+        &pool,
+        "LOC1",
+        &[PickupEvent {
+            date: tomorrow,
+            waste_types: vec![WasteType::Bio],
+            all_day: true,
+            uid: None,
+        }],
+    )
+    .await
+    .unwrap();
+
+    // Evening before: the main reminder
+    let tasks = get_users_to_notify(&pool, "18:00", today, tomorrow)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!((tasks[0].notify_offset, tasks[0].slot.as_str()), (1, ""));
+    record_notification(&pool, chat_id, "LOC1", "Bio", tomorrow, &tasks[0].slot)
+        .await
+        .unwrap();
+    assert!(get_users_to_notify(&pool, "18:00", today, tomorrow)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Morning of: the extra one is still due, with today's wording
+    let tasks = get_users_to_notify(&pool, "06:00", tomorrow, day_after)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(
+        (tasks[0].notify_offset, tasks[0].slot.as_str()),
+        (0, "06:00/0")
+    );
+    record_notification(&pool, chat_id, "LOC1", "Bio", tomorrow, &tasks[0].slot)
+        .await
+        .unwrap();
+    assert!(get_users_to_notify(&pool, "06:00", tomorrow, day_after)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // An extra reminder repeating the main one doesn't send twice
+    add_notify_time(&pool, loc_id, "18:00", 1).await.unwrap();
+    let tasks = get_users_to_notify(&pool, "18:00", today, tomorrow)
+        .await
+        .unwrap();
+    assert!(tasks.is_empty());
+
+    // Extra reminders are exported, and go with their location
+    let export = export_user(&pool, chat_id).await.unwrap().unwrap();
+    assert_eq!(export.locations[0].extra_notify_times.len(), 2);
+    remove_notify_time(&pool, loc_id, "18:00", 1).await.unwrap();
+    assert_eq!(
+        get_location_notify_times(&pool, loc_id)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    delete_user_location(&pool, chat_id, "LOC1").await.unwrap();
+    assert_eq!(get_notify_times(&pool).await.unwrap(), Vec::<String>::new());
+}
+
+#[tokio::test]
+async fn test_notification_idempotency() {
+    let pool = test_pool().await;
+
+    let chat_id = 4242;
+    let loc_id = add_user_location(&pool, chat_id, "LOC_IDEM", Some("Home"))
+        .await
+        .unwrap();
+    add_subscription(&pool, loc_id, "Bio").await.unwrap();
+
+    let today = chrono::Local::now().date_naive();
+    let tomorrow = today + chrono::Duration::days(1);
+
+    upsert_events(
+        &pool,
+        "LOC_IDEM",
+        &[PickupEvent {
+            date: tomorrow,
+            waste_types: vec![WasteType::Bio],
+            all_day: true,
+            uid: None,
+        }],
+    )
+    .await
+    .unwrap();
+
+    // First run of the 18:00 slot finds the pending notification
+    let tasks = get_users_to_notify(&pool, "18:00", today, tomorrow)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].event_date, tomorrow);
+
+    // Simulate a successful send
+    for task in &tasks {
+        record_notification(
+            &pool,
+            task.chat_id,
+            &task.location_id,
+            &task.waste_type,
+            task.event_date,
+            &task.slot,
+        )
+        .await
+        .unwrap();
+    }
+
+    // Second run of the same slot has nothing left to send
+    let tasks = get_users_to_notify(&pool, "18:00", today, tomorrow)
+        .await
+        .unwrap();
+    assert!(tasks.is_empty());
+
+    // Recording twice is harmless
+    record_notification(&pool, chat_id, "LOC_IDEM", "Bio", tomorrow, "")
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_location_name() {
+    let pool = test_pool().await;
+
+    let chat_id = 777;
+    add_user_location(&pool, chat_id, "12345", Some("Home"))
+        .await
+        .unwrap();
+
+    // No name fetched yet: fall back to the raw ID
+    let locations = get_user_locations(&pool, chat_id).await.unwrap();
+    assert_eq!(locations[0].location_name, None);
+    assert_eq!(locations[0].describe(), "12345");
+
+    update_location_name(&pool, "12345", "Musterstraße 1")
+        .await
+        .unwrap();
+    let locations = get_user_locations(&pool, chat_id).await.unwrap();
+    assert_eq!(locations[0].describe(), "Musterstraße 1 (ID 12345)");
+
+    // Updating again overwrites the stored name
+    update_location_name(&pool, "12345", "Musterstraße 2")
+        .await
+        .unwrap();
+    let locations = get_user_locations(&pool, chat_id).await.unwrap();
+    assert_eq!(
+        locations[0].location_name.as_deref(),
+        Some("Musterstraße 2")
+    );
+}
+
+#[tokio::test]
+async fn test_date_boundaries() {
+    let pool = test_pool().await;
+
+    let chat_id = 2099;
+    let loc_id = add_user_location(&pool, chat_id, "LOC_DATES", Some("Home"))
+        .await
+        .unwrap();
+    add_subscription(&pool, loc_id, "Bio").await.unwrap();
+    add_subscription(&pool, loc_id, "Rest").await.unwrap();
+
+    let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+    let events = vec![
+        PickupEvent {
+            date: date(2099, 1, 31),
+            waste_types: vec![WasteType::Bio],
+            all_day: true,
+            uid: None,
+        },
+        PickupEvent {
+            date: date(2099, 2, 1),
+            waste_types: vec![WasteType::Rest],
+            all_day: true,
+            uid: None,
+        },
+        PickupEvent {
+            date: date(2099, 12, 31),
+            waste_types: vec![WasteType::Bio],
+            all_day: true,
+            uid: None,
+        },
+        PickupEvent {
+            date: date(2100, 1, 1),
+            waste_types: vec![WasteType::Rest],
+            all_day: true,
+            uid: None,
+        },
+    ];
+    upsert_events(&pool, "LOC_DATES", &events).await.unwrap();
+
+    // Dates round-trip through the store unchanged
+    let stored: Vec<NaiveDate> = sqlx::query_scalar(
+        "SELECT date FROM pickup_events WHERE location_id = 'LOC_DATES' ORDER BY date",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        stored,
+        vec![
+            date(2099, 1, 31),
+            date(2099, 2, 1),
+            date(2099, 12, 31),
+            date(2100, 1, 1)
+        ]
+    );
+
+    // Range comparisons across a month and a year boundary
+    let count_between = |from: NaiveDate, to: NaiveDate| {
+        let pool = pool.clone();
+        async move {
+            sqlx::query_scalar::<_, i64>(
+                "SELECT COUNT(*) FROM pickup_events
+                 WHERE location_id = 'LOC_DATES' AND date >= ? AND date <= ?",
+            )
+            .bind(from)
+            .bind(to)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+        }
+    };
+    assert_eq!(count_between(date(2099, 1, 31), date(2099, 2, 1)).await, 2);
+    assert_eq!(count_between(date(2099, 2, 1), date(2099, 2, 28)).await, 1);
+    assert_eq!(count_between(date(2099, 12, 31), date(2100, 1, 1)).await, 2);
+    assert_eq!(count_between(date(2100, 1, 1), date(2100, 12, 31)).await, 1);
+
+    // "Tomorrow" lookups across a month boundary (18:00 / Day Before is the default)
+    let tasks = get_users_to_notify(&pool, "18:00", date(2099, 1, 31), date(2099, 2, 1))
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].waste_type, "Rest");
+    assert_eq!(tasks[0].event_date, date(2099, 2, 1));
+
+    // ... and across a year boundary
+    let tasks = get_users_to_notify(&pool, "18:00", date(2099, 12, 31), date(2100, 1, 1))
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].event_date, date(2100, 1, 1));
+}
+
+#[tokio::test]
+async fn test_mute_until() {
+    let pool = test_pool().await;
+
+    let chat_id = 31337;
+    let loc_id = add_user_location(&pool, chat_id, "LOC_MUTE", Some("Home"))
+        .await
+        .unwrap();
+    add_subscription(&pool, loc_id, "Bio").await.unwrap();
+
+    let today = chrono::Local::now().date_naive();
+    let tomorrow = today + chrono::Duration::days(1);
+    upsert_events(
+        &pool,
+        "LOC_MUTE",
+        &[PickupEvent {
+            date: tomorrow,
+            waste_types: vec![WasteType::Bio],
+            all_day: true,
+            uid: None,
+        }],
+    )
+    .await
+    .unwrap();
+
+    // Muted through next week: excluded
+    set_mute_until(&pool, chat_id, Some(today + chrono::Duration::days(7)))
+        .await
+        .unwrap();
+    let tasks = get_users_to_notify(&pool, "18:00", today, tomorrow)
+        .await
+        .unwrap();
+    assert!(tasks.is_empty());
+
+    // Muted through today: still excluded
+    set_mute_until(&pool, chat_id, Some(today)).await.unwrap();
+    let tasks = get_users_to_notify(&pool, "18:00", today, tomorrow)
+        .await
+        .unwrap();
+    assert!(tasks.is_empty());
+
+    // Expired mute: included again
+    set_mute_until(&pool, chat_id, Some(today - chrono::Duration::days(1)))
+        .await
+        .unwrap();
+    let tasks = get_users_to_notify(&pool, "18:00", today, tomorrow)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 1);
+
+    // Unmuting clears the date
+    set_mute_until(&pool, chat_id, None).await.unwrap();
+    assert_eq!(
+        get_user(&pool, chat_id).await.unwrap().unwrap().mute_until,
+        None
+    );
+    assert!(get_user(&pool, 1).await.unwrap().is_none()); // unknown user
+}
+
+#[tokio::test]
+async fn test_get_user() {
+    let pool = test_pool().await;
+
+    create_user(&pool, 7).await.unwrap();
+    let user = get_user(&pool, 7).await.unwrap().unwrap();
+    assert_eq!(user.id, 7);
+    assert_eq!(user.language, Lang::default());
+    assert_eq!(user.mute_until, None);
+    assert_eq!(user.notify_mode, NotifyMode::PerEvent);
+    assert!(user.created_at.is_some());
+
+    let today = NaiveDate::from_ymd_opt(2024, 6, 10).unwrap();
+    set_language(&pool, 7, Lang::En).await.unwrap();
+    set_notify_mode(&pool, 7, NotifyMode::WeeklyDigest)
+        .await
+        .unwrap();
+    set_mute_until(&pool, 7, Some(today)).await.unwrap();
+    let user = get_user(&pool, 7).await.unwrap().unwrap();
+    assert_eq!(user.language, Lang::En);
+    assert_eq!(user.notify_mode, NotifyMode::WeeklyDigest);
+    assert_eq!(user.mute_until, Some(today));
+    // The mute includes its last day
+    assert_eq!(user.active_mute(today), Some(today));
+    assert_eq!(user.active_mute(today + chrono::Duration::days(1)), None);
+
+    assert_eq!(user.message_template, None);
+    set_message_template(&pool, 7, Some("{when}: {type}"))
+        .await
+        .unwrap();
+    let user = get_user(&pool, 7).await.unwrap().unwrap();
+    assert_eq!(user.message_template.as_deref(), Some("{when}: {type}"));
+    set_message_template(&pool, 7, None).await.unwrap();
+    let user = get_user(&pool, 7).await.unwrap().unwrap();
+    assert_eq!(user.message_template, None);
+
+    assert!(!user.quiet_week_notice);
+    set_quiet_week_notice(&pool, 7, true).await.unwrap();
+    assert!(get_user(&pool, 7).await.unwrap().unwrap().quiet_week_notice);
+
+    delete_user(&pool, 7).await.unwrap();
+    assert!(get_user(&pool, 7).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_metrics_counts() {
+    let pool = test_pool().await;
+
+    assert_eq!(count_users(&pool).await.unwrap(), 0);
+    create_user(&pool, 1).await.unwrap();
+    create_user(&pool, 2).await.unwrap();
+    create_user(&pool, 2).await.unwrap();
+    assert_eq!(count_users(&pool).await.unwrap(), 2);
+    assert_eq!(get_all_chat_ids(&pool).await.unwrap(), vec![1, 2]);
+
+    let today = chrono::Local::now().date_naive();
+    let pickup = today + chrono::Duration::days(1);
+    record_notification(&pool, 1, "LOC1", "Bio", pickup, "")
+        .await
+        .unwrap();
+    record_notification(&pool, 2, "LOC1", "Bio", pickup, "")
+        .await
+        .unwrap();
+    assert_eq!(count_notifications_on(&pool, today).await.unwrap(), 2);
+    assert_eq!(
+        count_notifications_on(&pool, today - chrono::Duration::days(1))
+            .await
+            .unwrap(),
+        0
+    );
+
+    // Health check inputs
+    ping(&pool).await.unwrap();
+    assert_eq!(last_feed_update(&pool).await.unwrap(), None);
+    add_user_location(&pool, 1, "LOC1", None).await.unwrap();
+    assert_eq!(last_feed_update(&pool).await.unwrap(), None);
+    mark_location_updated(&pool, "LOC1").await.unwrap();
+    // Locations nobody uses don't count
+    sqlx::query(
+        "INSERT INTO locations (location_id, last_updated) VALUES ('UNUSED', '2999-01-01')",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        last_feed_update(&pool).await.unwrap(),
+        get_last_update(&pool, "LOC1").await.unwrap()
+    );
+    assert!(last_feed_update(&pool).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_prune_old_events() {
+    let pool = test_pool().await;
+
+    add_user_location(&pool, 1, "LOC_KEEP", None).await.unwrap();
+    let today = chrono::Local::now().date_naive();
+    let rows = [
+        ("LOC_KEEP", today - chrono::Duration::days(60)), // too old
+        ("LOC_KEEP", today - chrono::Duration::days(5)),  // recent past: kept
+        ("LOC_KEEP", today + chrono::Duration::days(3)),  // upcoming: kept
+        ("LOC_GONE", today + chrono::Duration::days(3)),  // nobody uses this location
+    ];
+    for (loc, date) in rows {
+        sqlx::query(
+            "INSERT INTO pickup_events (location_id, date, waste_type) VALUES (?, ?, 'Bio')",
+        )
+        .bind(loc)
+        .bind(date)
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+
+    assert_eq!(prune_old_events(&pool).await.unwrap(), 2);
+
+    let remaining: Vec<(String, NaiveDate)> =
+        sqlx::query_as("SELECT location_id, date FROM pickup_events ORDER BY date")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        remaining,
+        vec![
+            ("LOC_KEEP".to_string(), today - chrono::Duration::days(5)),
+            ("LOC_KEEP".to_string(), today + chrono::Duration::days(3)),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_language() {
+    let pool = test_pool().await;
+
+    // Unknown chats and new users get German
+    assert_eq!(get_language(&pool, 7).await.unwrap(), Lang::De);
+    create_user(&pool, 7).await.unwrap();
+    assert_eq!(get_language(&pool, 7).await.unwrap(), Lang::De);
+
+    set_language(&pool, 7, Lang::En).await.unwrap();
+    assert_eq!(get_language(&pool, 7).await.unwrap(), Lang::En);
+
+    // Rows that predate the column keep English
+    sqlx::query("INSERT INTO users (id) VALUES (8)")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(get_language(&pool, 8).await.unwrap(), Lang::En);
+}
+
+#[tokio::test]
+async fn test_dialogue_storage() {
+    let pool = test_pool().await;
+
+    let storage = SqliteDialogueStorage::new(pool.clone());
+    let chat = ChatId(42);
+
+    let state: Option<State> = storage.clone().get_dialogue(chat).await.unwrap();
+    assert!(state.is_none());
+
+    storage
+        .clone()
+        .update_dialogue(chat, State::AwaitingLocationAlias("12345".to_string()))
+        .await
+        .unwrap();
+    // A fresh storage over the same database sees the state, as after a restart
+    let restarted = SqliteDialogueStorage::new(pool.clone());
+    let state: Option<State> = restarted.clone().get_dialogue(chat).await.unwrap();
+    assert!(matches!(state, Some(State::AwaitingLocationAlias(id)) if id == "12345"));
+
+    Storage::<State>::remove_dialogue(restarted.clone(), chat)
+        .await
+        .unwrap();
+    let state: Option<State> = restarted.clone().get_dialogue(chat).await.unwrap();
+    assert!(state.is_none());
+    // Removing twice is an error, as with teloxide's own storages
+    assert!(Storage::<State>::remove_dialogue(restarted, chat)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_empty_feed_warning_once() {
+    let pool = test_pool().await;
+
+    add_user_location(&pool, 1, "LOC_EMPTY", Some("Home"))
+        .await
+        .unwrap();
+    add_user_location(&pool, 2, "LOC_EMPTY", None)
+        .await
+        .unwrap();
+    let today = chrono::Local::now().date_naive();
+    assert_eq!(
+        count_upcoming_events(&pool, "LOC_EMPTY", today)
+            .await
+            .unwrap(),
+        0
+    );
+
+    let warned = take_empty_feed_warnings(&pool, "LOC_EMPTY", None)
+        .await
+        .unwrap();
+    assert_eq!(warned.len(), 2);
+    assert_eq!(warned[0].alias.as_deref(), Some("Home"));
+    assert_eq!(warned[0].language, Lang::De);
+    // Only once per empty period
+    assert!(take_empty_feed_warnings(&pool, "LOC_EMPTY", None)
+        .await
+        .unwrap()
+        .is_empty());
+    // Reminders only come once the interval has passed
+    assert!(take_empty_feed_warnings(&pool, "LOC_EMPTY", Some(7))
+        .await
+        .unwrap()
+        .is_empty());
+
+    sqlx::query(
+        "UPDATE user_locations SET empty_feed_warned_at = datetime('now', '-8 days')
+         WHERE user_id = 1",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let reminded = take_empty_feed_warnings(&pool, "LOC_EMPTY", Some(7))
+        .await
+        .unwrap();
+    assert_eq!(reminded.len(), 1);
+    assert_eq!(reminded[0].chat_id, 1);
+    assert!(take_empty_feed_warnings(&pool, "LOC_EMPTY", Some(7))
+        .await
+        .unwrap()
+        .is_empty());
+
+    // A warning that couldn't be sent is handed out again
+    release_empty_feed_warning(&pool, "LOC_EMPTY", 2)
+        .await
+        .unwrap();
+    let retried = take_empty_feed_warnings(&pool, "LOC_EMPTY", None)
+        .await
+        .unwrap();
+    assert_eq!(
+        retried.iter().map(|u| u.chat_id).collect::<Vec<_>>(),
+        vec![2]
+    );
+
+    reset_empty_feed_warning(&pool, "LOC_EMPTY").await.unwrap();
+    assert_eq!(
+        take_empty_feed_warnings(&pool, "LOC_EMPTY", None)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn test_quiet_week_users() {
+    let pool = test_pool().await;
+
+    // Past pickups aren't stored, so the week has to lie ahead
+    let from = chrono::Local::now().date_naive() + chrono::Duration::days(1);
+    let to = from + chrono::Duration::days(6);
+    let event = |date, waste_types| PickupEvent {
+        date,
+        waste_types,
+        all_day: true,
+        uid: None,
+    };
+    upsert_events(
+        &pool,
+        "LOC_BUSY",
+        &[
+            event(from + chrono::Duration::days(2), vec![WasteType::Bio]),
+            event(to + chrono::Duration::days(8), vec![WasteType::Rest]),
+        ],
+    )
+    .await
+    .unwrap();
+
+    // 1: a pickup this week; 2: only types it doesn't subscribe to; 3: not opted in;
+    // 4: no locations; 5: on vacation
+    for chat_id in 1..=5 {
+        create_user(&pool, chat_id).await.unwrap();
+        set_quiet_week_notice(&pool, chat_id, chat_id != 3)
+            .await
+            .unwrap();
+        if chat_id != 4 {
+            let loc = add_user_location(&pool, chat_id, "LOC_BUSY", None)
+                .await
+                .unwrap();
+            let waste = if chat_id == 2 { "Rest" } else { "Bio" };
+            if chat_id != 5 {
+                add_subscription(&pool, loc, waste).await.unwrap();
+            }
+        }
+    }
+    set_mute_until(&pool, 5, Some(to)).await.unwrap();
+
+    let users = get_quiet_week_users(&pool, from, to).await.unwrap();
+    assert_eq!(users, vec![(2, Lang::De)]);
+
+    // Opting out is respected too
+    set_quiet_week_notice(&pool, 2, false).await.unwrap();
+    assert!(get_quiet_week_users(&pool, from, to)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_get_user_pickups() {
+    let pool = test_pool().await;
+
+    let today = chrono::Local::now().date_naive();
+    let day = |n| today + chrono::Duration::days(n);
+    let event = |date, waste_types| PickupEvent {
+        date,
+        waste_types,
+        all_day: true,
+        uid: None,
+    };
+    upsert_events(
+        &pool,
+        "LOC_A",
+        &[
+            event(day(1), vec![WasteType::Bio, WasteType::Rest]),
+            event(day(2), vec![WasteType::Paper]),
+            event(day(3), vec![WasteType::Rest]),
+            event(day(5), vec![WasteType::Bio]),
+        ],
+    )
+    .await
+    .unwrap();
+    upsert_events(&pool, "LOC_B", &[event(day(1), vec![WasteType::Bio])])
+        .await
+        .unwrap();
+
+    create_user(&pool, 1).await.unwrap();
+    let home = add_user_location(&pool, 1, "LOC_A", None).await.unwrap();
+    let work = add_user_location(&pool, 1, "LOC_B", None).await.unwrap();
+    for waste in ["Bio", "Rest"] {
+        add_subscription(&pool, home, waste).await.unwrap();
+    }
+    add_subscription(&pool, work, "Bio").await.unwrap();
+
+    // Days with only unsubscribed types are skipped; one type at two locations shows once
+    let pickups = get_user_pickups(&pool, 1, today, 2).await.unwrap();
+    let types = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        pickups,
+        vec![
+            (day(1), types(&["Bio", "Rest"])),
+            (day(3), types(&["Rest"]))
+        ]
+    );
+    let later = get_user_pickups(&pool, 1, day(4), 5).await.unwrap();
+    assert_eq!(later, vec![(day(5), types(&["Bio"]))]);
+}
+
+#[tokio::test]
+async fn test_raw_ical_cache() {
+    let pool = test_pool().await;
+
+    assert!(get_raw_ical(&pool, "LOC_A").await.unwrap().is_none());
+    save_raw_ical(&pool, "LOC_A", "BEGIN:VCALENDAR\nfirst")
+        .await
+        .unwrap();
+    save_raw_ical(&pool, "LOC_A", "BEGIN:VCALENDAR\nsecond")
+        .await
+        .unwrap();
+    let (body, _) = get_raw_ical(&pool, "LOC_A").await.unwrap().unwrap();
+    assert_eq!(body, "BEGIN:VCALENDAR\nsecond");
+
+    // Oversized bodies are cut without splitting a character
+    let huge = "ü".repeat(MAX_RAW_ICAL_BYTES);
+    save_raw_ical(&pool, "LOC_B", &huge).await.unwrap();
+    let (body, _) = get_raw_ical(&pool, "LOC_B").await.unwrap().unwrap();
+    assert_eq!(body.len(), MAX_RAW_ICAL_BYTES);
+    assert!(huge.starts_with(&body));
+
+    // Pruning drops the feeds of locations nobody uses
+    add_user_location(&pool, 1, "LOC_A", None).await.unwrap();
+    prune_old_events(&pool).await.unwrap();
+    assert!(get_raw_ical(&pool, "LOC_A").await.unwrap().is_some());
+    assert!(get_raw_ical(&pool, "LOC_B").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_location_stale() {
+    let pool = test_pool().await;
+
+    add_user_location(&pool, 2, "LOC_OLD", None).await.unwrap();
+    add_user_location(&pool, 1, "LOC_OLD", Some("Home"))
+        .await
+        .unwrap();
+    let users = get_location_users(&pool, "LOC_OLD").await.unwrap();
+    assert_eq!(
+        users.iter().map(|u| u.chat_id).collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+    assert_eq!(users[0].alias.as_deref(), Some("Home"));
+
+    // Flagged once, however often the check runs
+    assert!(mark_location_stale(&pool, "LOC_OLD").await.unwrap());
+    assert!(!mark_location_stale(&pool, "LOC_OLD").await.unwrap());
+
+    // Cleared when pickups come back, and can be flagged again later
+    clear_location_stale(&pool, "LOC_OLD").await.unwrap();
+    assert!(mark_location_stale(&pool, "LOC_OLD").await.unwrap());
+    assert!(!mark_location_stale(&pool, "LOC_UNKNOWN").await.unwrap());
+}
+
+#[tokio::test]
+async fn test_last_update() {
+    let pool = test_pool().await;
+
+    add_user_location(&pool, 1, "LOC_B", None).await.unwrap();
+    add_user_location(&pool, 2, "LOC_A", None).await.unwrap();
+    add_user_location(&pool, 3, "LOC_A", None).await.unwrap();
+    assert_eq!(
+        get_active_location_ids(&pool).await.unwrap(),
+        vec!["LOC_A", "LOC_B"]
+    );
+
+    assert_eq!(get_last_update(&pool, "LOC_A").await.unwrap(), None);
+    update_location_name(&pool, "LOC_A", "Musterstraße 1")
+        .await
+        .unwrap();
+    assert_eq!(get_last_update(&pool, "LOC_A").await.unwrap(), None);
+
+    mark_location_updated(&pool, "LOC_A").await.unwrap();
+    let updated = get_last_update(&pool, "LOC_A").await.unwrap().unwrap();
+    assert!(
+        (chrono::Utc::now().naive_utc() - updated)
+            .num_minutes()
+            .abs()
+            < 5
+    );
+    // Marking doesn't clobber the name
+    let locations = get_user_locations(&pool, 2).await.unwrap();
+    assert_eq!(
+        locations[0].location_name.as_deref(),
+        Some("Musterstraße 1")
+    );
+}
+
+#[tokio::test]
+async fn test_lead_time() {
+    let pool = test_pool().await;
+
+    let loc_id = add_user_location(&pool, 77, "LOC_LEAD", Some("Home"))
+        .await
+        .unwrap();
+    add_subscription(&pool, loc_id, "Bio").await.unwrap();
+
+    let pickup = NaiveDate::from_ymd_opt(2099, 6, 10).unwrap();
+    let day_before = NaiveDate::from_ymd_opt(2099, 6, 9).unwrap();
+    let day_after = NaiveDate::from_ymd_opt(2099, 6, 11).unwrap();
+    upsert_events(
+        &pool,
+        "LOC_LEAD",
+        &[PickupEvent {
+            date: pickup,
+            waste_types: vec![WasteType::Bio],
+            all_day: true,
+            uid: None,
+        }],
+    )
+    .await
+    .unwrap();
+
+    // 2 hours before the 06:00 pickup start is 04:00 the same day
+    update_notify_offset_hours(&pool, 77, "LOC_LEAD", Some(2))
+        .await
+        .unwrap();
+    assert_eq!(
+        get_user_locations(&pool, 77).await.unwrap()[0].notify_offset_hours,
+        Some(2)
+    );
+    assert_eq!(lead_time_slot(2), ("04:00".to_string(), 0));
+    assert!(get_users_to_notify(&pool, "18:00", day_before, pickup)
+        .await
+        .unwrap()
+        .is_empty());
+    let tasks = get_users_to_notify(&pool, "04:00", pickup, day_after)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].notify_offset, 0);
+
+    // 9 hours before is 21:00 the evening before
+    update_notify_offset_hours(&pool, 77, "LOC_LEAD", Some(9))
+        .await
+        .unwrap();
+    assert_eq!(lead_time_slot(9), ("21:00".to_string(), 1));
+    assert!(get_users_to_notify(&pool, "04:00", pickup, day_after)
+        .await
+        .unwrap()
+        .is_empty());
+    let tasks = get_users_to_notify(&pool, "21:00", day_before, pickup)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].notify_offset, 1);
+
+    // Picking a preset time clears the custom lead time
+    update_notify_time(&pool, 77, "LOC_LEAD", "18:00")
+        .await
+        .unwrap();
+    assert_eq!(
+        get_user_locations(&pool, 77).await.unwrap()[0].notify_offset_hours,
+        None
+    );
+    let tasks = get_users_to_notify(&pool, "18:00", day_before, pickup)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 1);
+
+    // Out-of-range lead times can't be stored, and are clamped if one is computed anyway
+    let raw = sqlx::query("UPDATE user_locations SET notify_offset_hours = 99 WHERE user_id = 77")
+        .execute(&pool)
+        .await;
+    assert!(raw.is_err());
+    assert_eq!(lead_time_slot(99), lead_time_slot(MAX_LEAD_HOURS));
+    assert_eq!(lead_time_slot(-3), ("06:00".to_string(), 0));
+}
+
+#[tokio::test]
+async fn test_set_all_subscriptions() {
+    let pool = test_pool().await;
+
+    let loc_id = add_user_location(&pool, 88, "LOC_ALL", None).await.unwrap();
+    add_subscription(&pool, loc_id, "Bio").await.unwrap();
+
+    set_all_subscriptions(&pool, loc_id, true).await.unwrap();
+    let subs = get_subscriptions(&pool, loc_id).await.unwrap();
+    assert_eq!(subs.len(), WasteType::supported_types().len());
+
+    set_all_subscriptions(&pool, loc_id, false).await.unwrap();
+    assert!(get_subscriptions(&pool, loc_id).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_waste_type_names_normalized() {
+    let pool = test_pool().await;
+
+    let loc_id = add_user_location(&pool, 99, "LOC_NORM", None)
+        .await
+        .unwrap();
+    add_subscription(&pool, loc_id, "Papier").await.unwrap();
+    // Spelling variants of a subscription collapse into the canonical one
+    add_subscription(&pool, loc_id, "blaue tonne")
+        .await
+        .unwrap();
+    assert_eq!(
+        get_subscriptions(&pool, loc_id).await.unwrap(),
+        vec!["Papier"]
+    );
+
+    let pickup = NaiveDate::from_ymd_opt(2099, 3, 3).unwrap();
+    let day_before = NaiveDate::from_ymd_opt(2099, 3, 2).unwrap();
+    upsert_events(
+        &pool,
+        "LOC_NORM",
+        &[PickupEvent {
+            date: pickup,
+            // Both name the same bin; storing them must not conflict
+            waste_types: vec![
+                WasteType::Other("Blaue Tonne".to_string()),
+                WasteType::Paper,
+            ],
+            all_day: true,
+            uid: None,
+        }],
+    )
+    .await
+    .unwrap();
+
+    let tasks = get_users_to_notify(&pool, "18:00", day_before, pickup)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].waste_type, "Papier");
+}
+
+#[tokio::test]
+async fn test_export_user() {
+    let pool = test_pool().await;
+
+    assert!(export_user(&pool, 111).await.unwrap().is_none());
+
+    create_user(&pool, 111).await.unwrap();
+    let loc_id = add_user_location(&pool, 111, "LOC_EXP", Some("Home"))
+        .await
+        .unwrap();
+    add_subscription(&pool, loc_id, "Bio").await.unwrap();
+    let date = NaiveDate::from_ymd_opt(2099, 5, 5).unwrap();
+    record_notification(&pool, 111, "LOC_EXP", "Bio", date, "")
+        .await
+        .unwrap();
+
+    let export = export_user(&pool, 111).await.unwrap().unwrap();
+    assert_eq!(export.language, "de");
+    assert_eq!(export.locations.len(), 1);
+    assert_eq!(export.locations[0].alias.as_deref(), Some("Home"));
+    assert_eq!(export.locations[0].subscriptions, vec!["Bio"]);
+    assert_eq!(export.notifications_sent.len(), 1);
+    assert_eq!(export.notifications_sent[0].date, date);
+
+    let json = serde_json::to_string(&export).unwrap();
+    assert!(json.contains("\"location_id\":\"LOC_EXP\""));
+}
+
+#[tokio::test]
+async fn test_import_user() {
+    let pool = test_pool().await;
+
+    let loc_id = add_user_location(&pool, 121, "OLD", Some("Old"))
+        .await
+        .unwrap();
+    add_subscription(&pool, loc_id, "Bio").await.unwrap();
+
+    // The payload's chat_id is ignored
+    let json = r#"{
+        "chat_id": 999,
+        "language": "en",
+        "locations": [{
+            "location_id": "NEW1",
+            "alias": "Home",
+            "notify_time": "06:00",
+            "notify_offset": 0,
+            "notify_offset_hours": null,
+            "subscriptions": ["Blaue Tonne", "Gelb"]
+        }]
+    }"#;
+    let data: UserExport = serde_json::from_str(json).unwrap();
+    import_user(&pool, 121, &data).await.unwrap();
+
+    assert!(export_user(&pool, 999).await.unwrap().is_none());
+    assert_eq!(get_language(&pool, 121).await.unwrap(), Lang::En);
+    let locations = get_user_locations(&pool, 121).await.unwrap();
+    assert_eq!(locations.len(), 1);
+    assert_eq!(locations[0].location_id, "NEW1");
+    assert_eq!(locations[0].notify_time, "06:00");
+    let mut subs = get_subscriptions(&pool, locations[0].id).await.unwrap();
+    subs.sort();
+    assert_eq!(subs, vec!["Gelb", "Papier"]);
+
+    // An invalid entry rejects the whole payload and leaves the data untouched
+    let json = r#"{
+        "language": "de",
+        "locations": [
+            {"location_id": "OK1", "alias": null, "notify_time": "18:00",
+             "notify_offset": 1, "notify_offset_hours": null, "subscriptions": []},
+            {"location_id": "BAD", "alias": null, "notify_time": "25:00",
+             "notify_offset": 1, "notify_offset_hours": null, "subscriptions": []}
+        ]
+    }"#;
+    let data: UserExport = serde_json::from_str(json).unwrap();
+    assert!(import_user(&pool, 121, &data).await.is_err());
+    assert_eq!(get_language(&pool, 121).await.unwrap(), Lang::En);
+    let locations = get_user_locations(&pool, 121).await.unwrap();
+    assert_eq!(locations.len(), 1);
+    assert_eq!(locations[0].location_id, "NEW1");
+}
+
+#[tokio::test]
+async fn test_export_import_round_trip() {
+    let pool = test_pool().await;
+
+    add_user_location(&pool, 141, "RT1", Some("Home"))
+        .await
+        .unwrap();
+    assert!(update_notify_time(&pool, 141, "Home", "07:30")
+        .await
+        .unwrap());
+
+    // Minute-precision times survive a move to another chat
+    let export = export_user(&pool, 141).await.unwrap().unwrap();
+    import_user(&pool, 142, &export).await.unwrap();
+    let locations = get_user_locations(&pool, 142).await.unwrap();
+    assert_eq!(locations.len(), 1);
+    assert_eq!(locations[0].location_id, "RT1");
+    assert_eq!(locations[0].notify_time, "07:30");
+}
+
+#[tokio::test]
+async fn test_settings_changes() {
+    let pool = test_pool().await;
+
+    let loc_id = add_user_location(&pool, 131, "LOC_CB", None).await.unwrap();
+    let other_loc = add_user_location(&pool, 132, "LOC_CB", None).await.unwrap();
+
+    let apply = |data: String, chat_id: i64| {
+        let pool = pool.clone();
+        async move {
+            let action = CallbackAction::parse(&data).unwrap();
+            let change = SettingsChange::from_action(&action).unwrap();
+            change.apply(&pool, chat_id).await.unwrap()
+        }
+    };
+
+    assert!(apply(format!("sub:{}:Bio", loc_id), 131).await);
+    assert_eq!(get_subscriptions(&pool, loc_id).await.unwrap(), vec!["Bio"]);
+    assert!(apply(format!("unsub:{}:Bio", loc_id), 131).await);
+    assert!(get_subscriptions(&pool, loc_id).await.unwrap().is_empty());
+
+    assert!(apply(format!("time:{}:23:00", loc_id), 131).await);
+    assert!(apply(format!("offset:{}:1", loc_id), 131).await);
+    let loc = &get_user_locations(&pool, 131).await.unwrap()[0];
+    assert_eq!(loc.notify_time, "00:00");
+    assert_eq!(loc.notify_offset, 0);
+
+    // The combined preset reminds the evening before and again on the morning of
+    add_subscription(&pool, loc_id, "Bio").await.unwrap();
+    let today = chrono::Local::now().date_naive();
+    let tomorrow = today + chrono::Duration::days(1);
+    upsert_events(
+        &pool,
+        "LOC_CB",
+        &[PickupEvent {
+            date: tomorrow,
+            waste_types: vec![WasteType::Bio],
+            all_day: true,
+            uid: None,
+        }],
+    )
+    .await
+    .unwrap();
+    assert!(apply(format!("both:{}", loc_id), 131).await);
+    let tasks = get_users_to_notify(&pool, "18:00", today, tomorrow)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!((tasks[0].chat_id, tasks[0].notify_offset), (131, 1));
+    let day_after = tomorrow + chrono::Duration::days(1);
+    let tasks = get_users_to_notify(&pool, "06:00", tomorrow, day_after)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!((tasks[0].chat_id, tasks[0].notify_offset), (131, 0));
+
+    // Someone else's location is left alone
+    assert!(!apply(format!("sub:{}:Bio", other_loc), 131).await);
+    assert!(!apply(format!("time:{}:18:00", other_loc), 131).await);
+    assert!(get_subscriptions(&pool, other_loc)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        get_user_locations(&pool, 132).await.unwrap()[0].notify_time,
+        "18:00"
+    );
+}
+
+#[tokio::test]
+async fn test_foreign_keys_cascade() {
+    let pool = crate::db::connect("sqlite::memory:").await.unwrap();
+    crate::db::create_schema(&pool).await.unwrap();
+
+    // Every pooled connection enforces foreign keys, not just the first one
+    let mut connections = Vec::new();
+    for _ in 0..3 {
+        let mut conn = pool.acquire().await.unwrap();
+        let enabled: bool = sqlx::query_scalar("PRAGMA foreign_keys")
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        assert!(enabled);
+        connections.push(conn);
+    }
+    drop(connections);
+
+    let loc_id = add_user_location_with_defaults(&pool, 1, "LOC1", Some("Home"))
+        .await
+        .unwrap();
+    assert!(!get_subscriptions(&pool, loc_id).await.unwrap().is_empty());
+
+    // A plain DELETE, without any of the application's cleanup
+    sqlx::query("DELETE FROM users WHERE id = 1")
+        .execute(&pool)
+        .await
+        .unwrap();
+    for table in ["user_locations", "subscriptions"] {
+        let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 0, "{} not cleaned up", table);
+    }
+}
+
+#[tokio::test]
+async fn test_concurrent_writes() {
+    let path = env::temp_dir().join(format!("dumpdate-wal-test-{}.db", std::process::id()));
+    let pool = crate::db::connect(&format!("sqlite://{}?mode=rwc", path.display()))
+        .await
+        .unwrap();
+    crate::db::create_schema(&pool).await.unwrap();
+
+    let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(journal_mode, "wal");
+
+    // Two writers holding transactions at the same time wait for each other
+    let write = |chat_id: i64| {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            for i in 0..20 {
+                let mut tx = pool.begin().await?;
+                sqlx::query("INSERT INTO users (id) VALUES (?)")
+                    .bind(chat_id * 100 + i)
+                    .execute(&mut *tx)
+                    .await?;
+                tokio::task::yield_now().await;
+                tx.commit().await?;
+            }
+            Ok::<_, sqlx::Error>(())
+        })
+    };
+    let (a, b) = tokio::join!(write(1), write(2));
+    a.unwrap().unwrap();
+    b.unwrap().unwrap();
+    assert_eq!(count_users(&pool).await.unwrap(), 40);
+
+    pool.close().await;
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[tokio::test]
+async fn test_schema_mismatch() {
+    // Empty and current databases are fine
+    let pool = memory_pool().await;
+    assert!(crate::db::schema_mismatch(&pool).await.unwrap().is_none());
+    crate::db::create_schema(&pool).await.unwrap();
+    assert!(crate::db::schema_mismatch(&pool).await.unwrap().is_none());
+
+    // The original single-location layout is detected
+    let legacy = memory_pool().await;
+    sqlx::raw_sql(include_str!("../migrations/20241027000000_init.sql"))
+        .execute(&legacy)
+        .await
+        .unwrap();
+    let problem = crate::db::schema_mismatch(&legacy).await.unwrap();
+    assert!(problem.unwrap().contains("user_location_id"));
+}
+
+#[tokio::test]
+async fn test_locations_backfill() {
+    let pool = memory_pool().await;
+
+    // A database from before locations were referenced by foreign key
+    sqlx::raw_sql(
+        "CREATE TABLE users (id INTEGER PRIMARY KEY, created_at DATETIME DEFAULT CURRENT_TIMESTAMP);
+         CREATE TABLE user_locations (
+             id INTEGER PRIMARY KEY AUTOINCREMENT,
+             user_id INTEGER NOT NULL,
+             location_id TEXT NOT NULL,
+             notify_time TEXT NOT NULL DEFAULT '18:00',
+             alias TEXT,
+             FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+             UNIQUE(user_id, location_id)
+         );
+         CREATE TABLE subscriptions (
+             user_location_id INTEGER NOT NULL,
+             waste_type TEXT NOT NULL,
+             PRIMARY KEY (user_location_id, waste_type),
+             FOREIGN KEY (user_location_id) REFERENCES user_locations(id) ON DELETE CASCADE
+         );
+         INSERT INTO users (id) VALUES (1), (2);
+         INSERT INTO user_locations (id, user_id, location_id, notify_time, alias)
+         VALUES (7, 1, 'SHARED', '06:00', 'Home'), (8, 2, 'SHARED', '6pm', NULL);
+         INSERT INTO subscriptions VALUES (7, 'Bio'), (8, 'Gelb');
+         CREATE TABLE notified_log (
+             chat_id INTEGER NOT NULL,
+             location_id TEXT NOT NULL,
+             waste_type TEXT NOT NULL,
+             date DATE NOT NULL,
+             sent_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+             PRIMARY KEY (chat_id, location_id, waste_type, date),
+             FOREIGN KEY (chat_id) REFERENCES users(id) ON DELETE CASCADE
+         );
+         INSERT INTO notified_log (chat_id, location_id, waste_type, date)
+         VALUES (1, 'SHARED', 'Bio', '2024-06-07');",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    crate::db::create_schema(&pool).await.unwrap();
+    // Running it again leaves everything as is
+    crate::db::create_schema(&pool).await.unwrap();
+
+    let locations: Vec<String> = sqlx::query_scalar("SELECT location_id FROM locations")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(locations, vec!["SHARED"]);
+    assert_eq!(
+        get_active_location_ids(&pool).await.unwrap(),
+        vec!["SHARED"]
+    );
+
+    let loc = &get_user_locations(&pool, 1).await.unwrap()[0];
+    assert_eq!((loc.id, loc.notify_time.as_str()), (7, "06:00"));
+    assert_eq!(get_subscriptions(&pool, 7).await.unwrap(), vec!["Bio"]);
+    assert_eq!(get_subscriptions(&pool, 8).await.unwrap(), vec!["Gelb"]);
+    // A malformed time is reset to the default instead of failing the rebuild
+    assert_eq!(
+        get_user_locations(&pool, 2).await.unwrap()[0].notify_time,
+        "18:00"
+    );
+    // Logged notifications become the main reminder's
+    let slots: Vec<String> = sqlx::query_scalar("SELECT slot FROM notified_log")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(slots, vec![""]);
+
+    // user_locations now has to point at a known location
+    let orphan =
+        sqlx::query("INSERT INTO user_locations (user_id, location_id) VALUES (1, 'NOPE')")
+            .execute(&pool)
+            .await;
+    assert!(orphan.is_err());
+
+    // Once nobody uses it, pruning removes the location row
+    delete_user(&pool, 1).await.unwrap();
+    delete_user(&pool, 2).await.unwrap();
+    prune_old_events(&pool).await.unwrap();
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM locations")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn test_notify_slot() {
+    // Stored times round-trip unchanged
+    for text in ["00:00", "06:00", "07:30", "18:00", "23:59"] {
+        let slot = NotifySlot::from_db(text).unwrap();
+        assert_eq!(slot.to_db(), text);
+        assert_eq!(text.parse::<NotifySlot>().unwrap(), slot);
+    }
+    for invalid in [
+        "24:00", "18:60", "6:00", "18:0", "1800", "+6:00", "18:00 ", "",
+    ] {
+        assert_eq!(NotifySlot::from_db(invalid), None, "{:?}", invalid);
+    }
+
+    // Typed input is more forgiving, but still has to be a time of day
+    assert_eq!(
+        "18".parse::<NotifySlot>().unwrap(),
+        NotifySlot::on_the_hour(18)
+    );
+    assert_eq!(
+        " 7:30 ".parse::<NotifySlot>().unwrap(),
+        NotifySlot::new(7, 30)
+    );
+    for invalid in ["24", "7:5", "+7", "7:30pm", ""] {
+        assert!(invalid.parse::<NotifySlot>().is_err(), "{:?}", invalid);
+    }
+
+    // The settings toggle steps through the full hours and wraps at midnight
+    assert_eq!(
+        NotifySlot::on_the_hour(18).next_hour(),
+        NotifySlot::on_the_hour(19)
+    );
+    assert_eq!(
+        NotifySlot::new(6, 30).next_hour(),
+        NotifySlot::on_the_hour(7)
+    );
+    let mut slot = NotifySlot::DEFAULT;
+    for _ in 0..24 {
+        slot = slot.next_hour();
+        assert_eq!(slot.minute(), 0);
+    }
+    assert_eq!(slot, NotifySlot::DEFAULT);
+}
+
+#[tokio::test]
+async fn test_notify_time_check() {
+    let pool = test_pool().await;
+    create_user(&pool, 5).await.unwrap();
+    add_user_location(&pool, 5, "LOC1", None).await.unwrap();
+
+    assert!(is_valid_notify_time("00:00"));
+    assert!(is_valid_notify_time("23:59"));
+    for invalid in [
+        "24:00", "18:60", "6:00", "18:0", "1800", "ab:cd", "18:00 ", "",
+    ] {
+        assert!(!is_valid_notify_time(invalid), "{:?}", invalid);
+    }
+
+    // Rejected in Rust with a readable error, before it reaches the database
+    let err = update_notify_time(&pool, 5, "LOC1", "25:00")
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("invalid notify time"), "{}", err);
+    assert!(update_notify_time(&pool, 5, "LOC1", "06:30").await.unwrap());
+    assert_eq!(
+        get_user_locations(&pool, 5).await.unwrap()[0].notify_time,
+        "06:30"
+    );
+
+    // The CHECK catches writes that bypass store
+    let raw = sqlx::query("UPDATE user_locations SET notify_time = '7pm' WHERE user_id = 5")
+        .execute(&pool)
+        .await;
+    assert!(raw.is_err());
+    assert_eq!(
+        get_user_locations(&pool, 5).await.unwrap()[0].notify_time,
+        "06:30"
+    );
+}
+
+#[tokio::test]
+async fn test_preview_notification() {
+    let pool = test_pool().await;
+
+    let loc_id = add_user_location(&pool, 141, "LOC_PREV", Some("Home"))
+        .await
+        .unwrap();
+    add_subscription(&pool, loc_id, "Bio").await.unwrap();
+    // Someone else at the same location isn't part of the preview
+    let other = add_user_location(&pool, 142, "LOC_PREV", None)
+        .await
+        .unwrap();
+    add_subscription(&pool, other, "Bio").await.unwrap();
+    set_language(&pool, 141, Lang::En).await.unwrap();
+
+    let pickup = NaiveDate::from_ymd_opt(2099, 6, 5).unwrap();
+    upsert_events(
+        &pool,
+        "LOC_PREV",
+        &[PickupEvent {
+            date: pickup,
+            waste_types: vec![WasteType::Bio],
+            all_day: true,
+            uid: None,
+        }],
+    )
+    .await
+    .unwrap();
+
+    let now = NaiveDate::from_ymd_opt(2099, 6, 4)
+        .unwrap()
+        .and_hms_opt(9, 30, 0)
+        .unwrap();
+    let (slot, message) = crate::scheduler::preview_notification(&pool, 141, now)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        slot,
+        NaiveDate::from_ymd_opt(2099, 6, 4)
+            .unwrap()
+            .and_hms_opt(18, 0, 0)
+            .unwrap()
+    );
+    assert!(message.contains("Tomorrow"));
+    assert!(message.contains("Home"));
+
+    // Nothing within the window
+    let later = now + chrono::Duration::days(3);
+    assert!(crate::scheduler::preview_notification(&pool, 141, later)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_snoozes_fire_once() {
+    let pool = test_pool().await;
+
+    create_user(&pool, 1).await.unwrap();
+    let date = NaiveDate::from_ymd_opt(2024, 6, 7).unwrap();
+    let fire_at = date.and_hms_opt(16, 0, 0).unwrap();
+    add_snooze(&pool, 1, fire_at, date, &["bio".to_string()])
+        .await
+        .unwrap();
+    // Snoozing again replaces the types instead of adding a second reminder
+    let types = vec!["Bio".to_string(), "Papier".to_string()];
+    add_snooze(&pool, 1, fire_at, date, &types).await.unwrap();
+
+    let before = date.and_hms_opt(15, 59, 0).unwrap();
+    assert!(take_due_snoozes(&pool, before).await.unwrap().is_empty());
+
+    let due = take_due_snoozes(&pool, fire_at).await.unwrap();
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].chat_id, 1);
+    assert_eq!(due[0].event_date, date);
+    assert_eq!(due[0].waste_types, types);
+    assert_eq!(due[0].language, Lang::De);
+    // Gone once fired
+    assert!(take_due_snoozes(&pool, fire_at).await.unwrap().is_empty());
+
+    // Deleting the user takes pending snoozes with it
+    add_snooze(&pool, 1, fire_at, date, &types).await.unwrap();
+    delete_user(&pool, 1).await.unwrap();
+    assert!(take_due_snoozes(&pool, fire_at).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_weekly_digest_mode() {
+    let pool = test_pool().await;
+
+    // Past events aren't stored, so the "Sunday" of the digest is today
+    let sunday = chrono::Local::now().date_naive();
+    let monday = sunday + chrono::Duration::days(1);
+    let next_monday = sunday + chrono::Duration::days(8);
+    for chat_id in [1, 2] {
+        let loc_id = add_user_location(&pool, chat_id, "LOC1", None)
+            .await
+            .unwrap();
+        // Default reminder: 18:00 the day before
+        add_subscription(&pool, loc_id, "Bio").await.unwrap();
+    }
+    let events: Vec<_> = [monday, next_monday]
+        .into_iter()
+        .map(|date| PickupEvent {
+            date,
+            waste_types: vec![WasteType::Bio],
+            all_day: true,
+            uid: None,
+        })
+        .collect();
+    upsert_events(&pool, "LOC1", &events).await.unwrap();
+
+    assert_eq!(
+        get_notify_mode(&pool, 2).await.unwrap(),
+        NotifyMode::PerEvent
+    );
+    set_notify_mode(&pool, 2, NotifyMode::WeeklyDigest)
+        .await
+        .unwrap();
+    assert_eq!(
+        get_notify_mode(&pool, 2).await.unwrap(),
+        NotifyMode::WeeklyDigest
+    );
+
+    // Digest users no longer get the per-event reminder...
+    let tasks = get_users_to_notify(&pool, "18:00", sunday, monday)
+        .await
+        .unwrap();
+    assert_eq!(tasks.iter().map(|t| t.chat_id).collect::<Vec<_>>(), vec![1]);
+
+    // ...but the week ahead, without what lies beyond it
+    let week = get_events_in_range(
+        &pool,
+        NotifyMode::WeeklyDigest,
+        monday,
+        sunday + chrono::Duration::days(7),
+    )
+    .await
+    .unwrap();
+    assert_eq!(week.len(), 1);
+    assert_eq!(week[0].chat_id, 2);
+    assert_eq!(week[0].date, monday);
+    assert_eq!(week[0].waste_type, "Bio");
+
+    // Muted users are skipped
+    set_mute_until(&pool, 2, Some(monday)).await.unwrap();
+    let week = get_events_in_range(&pool, NotifyMode::WeeklyDigest, monday, next_monday)
+        .await
+        .unwrap();
+    assert!(week.is_empty());
+}
+
+#[tokio::test]
+async fn test_failed_notification_is_retried() {
+    use crate::notifier::Delivery;
+    use crate::scheduler::{settle_delivery, Settled, MAX_SEND_ATTEMPTS};
+    use std::sync::Arc;
+    use teloxide::RequestError;
+
+    let pool = test_pool().await;
+
+    let today = chrono::Local::now().date_naive();
+    let tomorrow = today + chrono::Duration::days(1);
+    let loc_id = add_user_location(&pool, 1, "LOC1", Some("Home"))
+        .await
+        .unwrap();
+    add_subscription(&pool, loc_id, "Bio").await.unwrap();
+    let event = PickupEvent {
+        date: tomorrow,
+        waste_types: vec![WasteType::Bio],
+        all_day: true,
+        uid: None,
+    };
+    upsert_events(&pool, "LOC1", &[event]).await.unwrap();
+
+    let transient = || {
+        Err(RequestError::Io(Arc::new(std::io::Error::new(
+            std::io::ErrorKind::ConnectionReset,
+            "connection reset",
+        ))))
+    };
+
+    // The slot's send fails on a flaky connection and is queued
+    let tasks = get_users_to_notify(&pool, "18:00", today, tomorrow)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(
+        settle_delivery(&pool, 1, &tasks, transient()).await,
+        Settled::Retrying
+    );
+
+    // The next tick picks it up and succeeds
+    let retry = get_failed_notifications(&pool, today).await.unwrap();
+    assert_eq!(retry.len(), 1);
+    assert_eq!(retry[0].location_alias.as_deref(), Some("Home"));
+    assert_eq!(retry[0].event_date, tomorrow);
+    assert_eq!(retry[0].notify_offset, 1);
+    assert_eq!(
+        settle_delivery(&pool, 1, &retry, Ok(Delivery::Sent)).await,
+        Settled::Delivered(Delivery::Sent)
+    );
+    assert!(get_failed_notifications(&pool, today)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(get_users_to_notify(&pool, "18:00", today, tomorrow)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // A send that keeps failing is dropped after the last attempt
+    sqlx::query("DELETE FROM notified_log")
+        .execute(&pool)
+        .await
+        .unwrap();
+    for _ in 1..MAX_SEND_ATTEMPTS {
+        assert_eq!(
+            settle_delivery(&pool, 1, &tasks, transient()).await,
+            Settled::Retrying
+        );
+    }
+    assert_eq!(
+        settle_delivery(&pool, 1, &tasks, transient()).await,
+        Settled::GaveUp
+    );
+    assert!(get_failed_notifications(&pool, today)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_readding_location_keeps_subscriptions() {
+    let pool = test_pool().await;
+
+    // New locations start with the defaults
+    let loc_id = add_user_location_with_defaults(&pool, 1, "LOC1", Some("Home"))
+        .await
+        .unwrap();
+    assert_eq!(get_subscriptions(&pool, loc_id).await.unwrap().len(), 4);
+
+    // The user customizes them
+    set_all_subscriptions(&pool, loc_id, false).await.unwrap();
+    add_subscription(&pool, loc_id, "Sperrmüll").await.unwrap();
+
+    // create_user is an upsert and leaves everything alone
+    create_user(&pool, 1).await.unwrap();
+    assert_eq!(
+        get_subscriptions(&pool, loc_id).await.unwrap(),
+        vec!["Sperrmüll"]
+    );
+
+    // Adding the same location again only renames it
+    let again = add_user_location_with_defaults(&pool, 1, "LOC1", Some("Flat"))
+        .await
+        .unwrap();
+    assert_eq!(again, loc_id);
+    assert_eq!(
+        get_subscriptions(&pool, loc_id).await.unwrap(),
+        vec!["Sperrmüll"]
+    );
+    let locations = get_user_locations(&pool, 1).await.unwrap();
+    assert_eq!(locations.len(), 1);
+    assert_eq!(locations[0].alias.as_deref(), Some("Flat"));
+
+    // ...and without a new alias keeps the old one
+    add_user_location_with_defaults(&pool, 1, "LOC1", None)
+        .await
+        .unwrap();
+    let locations = get_user_locations(&pool, 1).await.unwrap();
+    assert_eq!(locations[0].alias.as_deref(), Some("Flat"));
+}
+
+#[tokio::test]
+async fn test_users_per_location() {
+    let pool = test_pool().await;
+
+    assert!(users_per_location(&pool).await.unwrap().is_empty());
+
+    add_user_location(&pool, 1, "LOC1", None).await.unwrap();
+    add_user_location(&pool, 2, "LOC2", None).await.unwrap();
+    add_user_location(&pool, 3, "LOC2", None).await.unwrap();
+    // A second location of the same user counts for that location only
+    add_user_location(&pool, 1, "LOC2", None).await.unwrap();
+
+    assert_eq!(
+        users_per_location(&pool).await.unwrap(),
+        vec![("LOC2".to_string(), 3), ("LOC1".to_string(), 1)]
+    );
+}
+
+#[tokio::test]
+async fn test_notification_stats() {
+    let pool = test_pool().await;
+
+    create_user(&pool, 1).await.unwrap();
+    create_user(&pool, 2).await.unwrap();
+    assert_eq!(
+        notification_stats(&pool, 1).await.unwrap(),
+        NotificationStats {
+            reminders: 0,
+            last_sent: None
+        }
+    );
+
+    let day = NaiveDate::from_ymd_opt(2024, 6, 7).unwrap();
+    // Two types on the same day were one reminder
+    record_notification(&pool, 1, "LOC1", "Bio", day, "")
+        .await
+        .unwrap();
+    record_notification(&pool, 1, "LOC1", "Rest", day, "")
+        .await
+        .unwrap();
+    record_notification(
+        &pool,
+        1,
+        "LOC1",
+        "Bio",
+        day + chrono::Duration::days(14),
+        "",
+    )
+    .await
+    .unwrap();
+    // Other chats don't count
+    record_notification(&pool, 2, "LOC1", "Bio", day, "")
+        .await
+        .unwrap();
+
+    let stats = notification_stats(&pool, 1).await.unwrap();
+    assert_eq!(stats.reminders, 2);
+    assert!(stats.last_sent.is_some());
+    assert_eq!(notification_stats(&pool, 2).await.unwrap().reminders, 1);
+}
+
+#[tokio::test]
+async fn test_get_next_pickup() {
+    let pool = test_pool().await;
+
+    let today = chrono::Local::now().date_naive();
+    let loc_id = add_user_location(&pool, 1, "LOC1", None).await.unwrap();
+    assert_eq!(get_next_pickup(&pool, loc_id, today).await.unwrap(), None);
+
+    add_subscription(&pool, loc_id, "Bio").await.unwrap();
+    add_subscription(&pool, loc_id, "Rest").await.unwrap();
+    let events = vec![
+        // Not subscribed, so it doesn't count even though it comes first
+        PickupEvent {
+            date: today + chrono::Duration::days(1),
+            waste_types: vec![WasteType::Paper],
+            all_day: true,
+            uid: None,
+        },
+        PickupEvent {
+            date: today + chrono::Duration::days(3),
+            waste_types: vec![WasteType::Rest, WasteType::Paper, WasteType::Bio],
+            all_day: true,
+            uid: None,
+        },
+        PickupEvent {
+            date: today + chrono::Duration::days(5),
+            waste_types: vec![WasteType::Bio],
+            all_day: true,
+            uid: None,
+        },
+    ];
+    upsert_events(&pool, "LOC1", &events).await.unwrap();
+
+    assert_eq!(
+        get_next_pickup(&pool, loc_id, today).await.unwrap(),
+        Some((
+            today + chrono::Duration::days(3),
+            vec!["Bio".to_string(), "Rest".to_string()]
+        ))
+    );
+    assert_eq!(
+        get_next_pickup(&pool, loc_id, today + chrono::Duration::days(4))
+            .await
+            .unwrap(),
+        Some((today + chrono::Duration::days(5), vec!["Bio".to_string()]))
+    );
+}
+
+#[tokio::test]
+async fn test_upsert_events_keeps_uid_identity() {
+    let pool = test_pool().await;
+
+    let today = chrono::Local::now().date_naive();
+    let event = |days: i64, uid: Option<&str>, waste_types: Vec<WasteType>| PickupEvent {
+        date: today + chrono::Duration::days(days),
+        waste_types,
+        all_day: true,
+        uid: uid.map(str::to_string),
+    };
+    let rows = || async {
+        sqlx::query_as::<_, (i64, NaiveDate, String)>(
+            "SELECT id, date, waste_type FROM pickup_events WHERE location_id = 'LOC1'
+             ORDER BY date, waste_type",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap()
+    };
+
+    upsert_events(
+        &pool,
+        "LOC1",
+        &[
+            event(2, Some("a"), vec![WasteType::Bio, WasteType::Rest]),
+            event(4, None, vec![WasteType::Paper]),
+        ],
+    )
+    .await
+    .unwrap();
+    let before = rows().await;
+    assert_eq!(before.len(), 3);
+
+    // The event moves by a day; the same UID keeps its rows, nothing is duplicated
+    upsert_events(
+        &pool,
+        "LOC1",
+        &[
+            event(3, Some("a"), vec![WasteType::Bio, WasteType::Rest]),
+            event(4, None, vec![WasteType::Paper]),
+        ],
+    )
+    .await
+    .unwrap();
+    let after = rows().await;
+    assert_eq!(after.len(), 3);
+    for (old, new) in before.iter().zip(&after) {
+        assert_eq!(old.0, new.0);
+        assert_eq!(old.2, new.2);
+    }
+    assert_eq!(after[0].1, today + chrono::Duration::days(3));
+
+    // Events gone from the feed are removed
+    upsert_events(&pool, "LOC1", &[event(3, Some("a"), vec![WasteType::Bio])])
+        .await
+        .unwrap();
+    let remaining = rows().await;
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].0, before[0].0);
+}
+
+#[tokio::test]
+async fn test_empty_feed_keeps_events() {
+    let pool = test_pool().await;
+
+    let today = chrono::Local::now().date_naive();
+    let event = |days: i64| PickupEvent {
+        date: today + chrono::Duration::days(days),
+        waste_types: vec![WasteType::Bio],
+        all_day: true,
+        uid: None,
+    };
+    upsert_events(&pool, "LOC1", &[event(1), event(8)])
+        .await
+        .unwrap();
+    assert_eq!(
+        count_upcoming_events(&pool, "LOC1", today).await.unwrap(),
+        2
+    );
+
+    // An empty feed, or one with only past events, doesn't wipe the upcoming pickups
+    upsert_events(&pool, "LOC1", &[]).await.unwrap();
+    assert_eq!(
+        count_upcoming_events(&pool, "LOC1", today).await.unwrap(),
+        2
+    );
+    upsert_events(&pool, "LOC1", &[event(-3)]).await.unwrap();
+    assert_eq!(
+        count_upcoming_events(&pool, "LOC1", today).await.unwrap(),
+        2
+    );
+
+    // As soon as the feed has events again, it replaces them as usual
+    upsert_events(&pool, "LOC1", &[event(8)]).await.unwrap();
+    assert_eq!(
+        count_upcoming_events(&pool, "LOC1", today).await.unwrap(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_renormalize_waste_types() {
+    let pool = test_pool().await;
+
+    // Rows written before the parser knew these spellings
+    let loc_id = add_user_location(&pool, 1, "LOC1", None).await.unwrap();
+    for waste in ["Papier", "blaue tonne", "Biotonne (Abholung)", "Kompost"] {
+        sqlx::query("INSERT INTO subscriptions (user_location_id, waste_type) VALUES (?, ?)")
+            .bind(loc_id)
+            .bind(waste)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+    let date = NaiveDate::from_ymd_opt(2099, 3, 3).unwrap();
+    for waste in ["Bio", "Biotonne (Abholung)", "Restmüll 14-täglich"] {
+        sqlx::query("INSERT INTO pickup_events (location_id, date, waste_type) VALUES (?, ?, ?)")
+            .bind("LOC1")
+            .bind(date)
+            .bind(waste)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+    record_notification(&pool, 1, "LOC1", "Restmüll 14-täglich", date, "")
+        .await
+        .unwrap();
+    sqlx::query(
+        "INSERT INTO failed_notifications (chat_id, location_id, waste_type, date)
+         VALUES (1, 'LOC1', 'blaue tonne', ?)",
+    )
+    .bind(date)
+    .execute(&pool)
+    .await
+    .unwrap();
+    let fire_at = date.and_hms_opt(16, 0, 0).unwrap();
+    sqlx::query(
+        "INSERT INTO snoozes (chat_id, fire_at, event_date, waste_types)
+         VALUES (1, ?, ?, 'Bio,Biotonne (Abholung),Restmüll 14-täglich')",
+    )
+    .bind(fire_at)
+    .bind(date)
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let counts = renormalize_waste_types(&pool).await.unwrap();
+    assert_eq!(
+        counts,
+        RenormalizeCounts {
+            subscriptions: 2,
+            events: 2,
+            notified: 1,
+            retries: 1,
+            snoozes: 1,
+        }
+    );
+    assert_eq!(
+        get_subscriptions(&pool, loc_id).await.unwrap(),
+        vec!["Bio", "Kompost", "Papier"]
+    );
+    let events: Vec<String> =
+        sqlx::query_scalar("SELECT waste_type FROM pickup_events ORDER BY waste_type")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(events, vec!["Bio", "Rest"]);
+    let logged: Vec<String> = sqlx::query_scalar("SELECT waste_type FROM notified_log")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(logged, vec!["Rest"]);
+    let retried: Vec<String> = sqlx::query_scalar("SELECT waste_type FROM failed_notifications")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(retried, vec!["Papier"]);
+    let snoozed: String = sqlx::query_scalar("SELECT waste_types FROM snoozes")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(snoozed, "Bio,Rest");
+
+    // Nothing left to do the second time
+    assert_eq!(
+        renormalize_waste_types(&pool).await.unwrap(),
+        RenormalizeCounts::default()
+    );
+}
+
+#[tokio::test]
+async fn test_upsert_events_today_boundary() {
+    let pool = test_pool().await;
+
+    let today = chrono::Local::now().date_naive();
+    let day = |days: i64| today + chrono::Duration::days(days);
+    let event = |days: i64, waste: WasteType| PickupEvent {
+        date: day(days),
+        waste_types: vec![waste],
+        all_day: true,
+        uid: None,
+    };
+    let rows = || async {
+        sqlx::query_as::<_, (NaiveDate, String)>(
+            "SELECT date, waste_type FROM pickup_events WHERE location_id = 'LOC1'
+             ORDER BY date, waste_type",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap()
+    };
+
+    // Stored before it became history
+    sqlx::query("INSERT INTO pickup_events (location_id, date, waste_type) VALUES ('LOC1', ?, ?)")
+        .bind(day(-1))
+        .bind("Bio")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // Yesterday's event in the feed is skipped, today's counts as upcoming
+    upsert_events(
+        &pool,
+        "LOC1",
+        &[
+            event(-1, WasteType::Rest),
+            event(0, WasteType::Bio),
+            event(1, WasteType::Paper),
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        rows().await,
+        vec![
+            (day(-1), "Bio".to_string()),
+            (day(0), "Bio".to_string()),
+            (day(1), "Papier".to_string()),
+        ]
+    );
+
+    // Only rows from today on are replaced; the past one stays until pruning
+    upsert_events(
+        &pool,
+        "LOC1",
+        &[event(-1, WasteType::Rest), event(1, WasteType::Paper)],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        rows().await,
+        vec![(day(-1), "Bio".to_string()), (day(1), "Papier".to_string())]
+    );
+}
@@ -0,0 +1,64 @@
+use crate::store;
+use futures::future::BoxFuture;
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use teloxide::dispatching::dialogue::Storage;
+use teloxide::types::ChatId;
+
+/// Dialogue storage backed by the bot's own database, so a multi-step flow
+/// survives a restart. States are stored as JSON in the `dialogues` table.
+pub struct SqliteDialogueStorage {
+    pool: SqlitePool,
+}
+
+impl SqliteDialogueStorage {
+    pub fn new(pool: SqlitePool) -> Arc<Self> {
+        Arc::new(Self { pool })
+    }
+}
+
+impl<D> Storage<D> for SqliteDialogueStorage
+where
+    D: Serialize + DeserializeOwned + Send + 'static,
+{
+    type Error = anyhow::Error;
+
+    fn remove_dialogue(self: Arc<Self>, chat_id: ChatId) -> BoxFuture<'static, anyhow::Result<()>>
+    where
+        D: Send + 'static,
+    {
+        Box::pin(async move {
+            if !store::delete_dialogue_state(&self.pool, chat_id.0).await? {
+                anyhow::bail!("No dialogue stored for chat {}", chat_id);
+            }
+            Ok(())
+        })
+    }
+
+    fn update_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+        dialogue: D,
+    ) -> BoxFuture<'static, anyhow::Result<()>>
+    where
+        D: Send + 'static,
+    {
+        Box::pin(async move {
+            let state = serde_json::to_string(&dialogue)?;
+            store::set_dialogue_state(&self.pool, chat_id.0, &state).await
+        })
+    }
+
+    fn get_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+    ) -> BoxFuture<'static, anyhow::Result<Option<D>>> {
+        Box::pin(async move {
+            match store::get_dialogue_state(&self.pool, chat_id.0).await? {
+                Some(state) => Ok(Some(serde_json::from_str(&state)?)),
+                None => Ok(None),
+            }
+        })
+    }
+}
@@ -0,0 +1,811 @@
+use chrono::{Datelike, NaiveDate, Weekday};
+use std::fmt::Display;
+use strum_macros::EnumIter;
+use thiserror::Error;
+
+/// Language of a user's messages, stored as its code in `users.language`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Lang {
+    #[default]
+    De,
+    En,
+}
+
+impl Lang {
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::De => "de",
+            Lang::En => "en",
+        }
+    }
+
+    /// Unknown codes fall back to German.
+    pub fn from_code(code: &str) -> Self {
+        match code {
+            "en" => Lang::En,
+            _ => Lang::De,
+        }
+    }
+
+    pub fn toggled(self) -> Self {
+        match self {
+            Lang::De => Lang::En,
+            Lang::En => Lang::De,
+        }
+    }
+
+    pub fn format_date(self, date: NaiveDate) -> String {
+        match self {
+            Lang::De => date.format("%d.%m.%Y").to_string(),
+            Lang::En => date.format("%Y-%m-%d").to_string(),
+        }
+    }
+
+    /// Short weekday and day of month, e.g. "Fr, 07.06." or "Fri, 07.06.".
+    pub fn format_day(self, date: NaiveDate) -> String {
+        format!(
+            "{}, {}",
+            self.weekday(date.weekday()),
+            date.format("%d.%m.")
+        )
+    }
+
+    fn weekday(self, day: Weekday) -> &'static str {
+        // chrono only formats English names without its locale feature
+        let (de, en) = match day {
+            Weekday::Mon => ("Mo", "Mon"),
+            Weekday::Tue => ("Di", "Tue"),
+            Weekday::Wed => ("Mi", "Wed"),
+            Weekday::Thu => ("Do", "Thu"),
+            Weekday::Fri => ("Fr", "Fri"),
+            Weekday::Sat => ("Sa", "Sat"),
+            Weekday::Sun => ("So", "Sun"),
+        };
+        match self {
+            Lang::De => de,
+            Lang::En => en,
+        }
+    }
+}
+
+/// Every user-facing message. Texts with `{}` are filled in order by `tf`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter)]
+pub enum Key {
+    EnterLocationId,
+    HelpText,
+    TypesHeader,
+    ExportNothing,
+    ExportCaption,
+    ImportPrompt,
+    PreviewHeader,
+    PreviewNone,
+    MyStats,
+    MyStatsNone,
+    SubscribeUsage,
+    UnknownWasteType,
+    SubscribedTo,
+    UnsubscribedFrom,
+    SubscriptionsLine,
+    InspectUsage,
+    RenormalizeDone,
+    IcalOutage,
+    RawFeedUsage,
+    RawFeedNone,
+    RawFeedCaption,
+    InspectUnknown,
+    InspectUser,
+    InspectNextPickup,
+    InspectNoPickup,
+    TestNotifyUsage,
+    TestNotifyRecipient,
+    TestNotifyDone,
+    TemplateButton,
+    TemplatePrompt,
+    TemplateDefault,
+    TemplateTooLong,
+    TemplateUnknownPlaceholder,
+    TemplateUnclosed,
+    TemplateSaved,
+    TemplateReset,
+    TemplateExampleLocation,
+    ImportTooLarge,
+    ImportInvalid,
+    ImportDone,
+    FeedbackNotConfigured,
+    FeedbackPrompt,
+    FeedbackThanks,
+    FeedbackHeader,
+    StopConfirm,
+    StopConfirmButton,
+    CancelButton,
+    StopDone,
+    Cancelled,
+    AdminOnly,
+    BroadcastUsage,
+    BroadcastReport,
+    InvalidLocationId,
+    AmbiguousLocationId,
+    AliasPrompt,
+    AliasTooLong,
+    AliasInvalid,
+    LocationAdded,
+    LocationAddError,
+    MutePrompt,
+    MuteDaysInvalid,
+    LeadTimePrompt,
+    LeadTimeInvalid,
+    LeadTimeSet,
+    LeadTimeCleared,
+    MutedUntil,
+    InvalidState,
+    NoLocations,
+    NoLocationsStatus,
+    NoLocationsLeft,
+    YourLocations,
+    YourConfiguration,
+    StatusLocation,
+    NoSubscriptions,
+    DayBefore,
+    SameDay,
+    SettingsFor,
+    LocationNotFound,
+    Subscribed,
+    Unsubscribed,
+    TimeUpdated,
+    DayUpdated,
+    LocationDeleted,
+    NotificationsResumed,
+    MenuExpired,
+    ButtonUnavailable,
+    RefreshCooldown,
+    LocationStale,
+    LastUpdateLine,
+    LastUpdateNever,
+    AgeToday,
+    AgeYesterday,
+    AgeDaysAgo,
+    PleaseWait,
+    FeedsHeader,
+    StatsHeader,
+    StatsLine,
+    FeedsLine,
+    Never,
+    RefreshLoaded,
+    RefreshFailed,
+    RefreshUnknownLocation,
+    ExtraMorningButton,
+    ExtraEveningButton,
+    ReminderAdded,
+    ReminderRemoved,
+    EveningAndMorningButton,
+    EveningAndMorningSet,
+    UnmuteButton,
+    PauseButton,
+    SubscribeAllButton,
+    UnsubscribeAllButton,
+    NotifyTimeButton,
+    DayButton,
+    LeadTimeButton,
+    LeadTimeOff,
+    DeleteLocationButton,
+    BackButton,
+    LanguageButton,
+    LanguageChanged,
+    NoUpcomingPickups,
+    Tomorrow,
+    Today,
+    NotificationLine,
+    SnoozeButton,
+    Snoozed,
+    SnoozeTooLate,
+    SnoozeReminder,
+    RemindBeforeUsage,
+    RemindBeforePrompt,
+    RemindBeforeNone,
+    RemindBeforeTooLate,
+    RemindBeforeSet,
+    DigestHeader,
+    DigestLine,
+    NotifyModeButton,
+    ModePerEvent,
+    ModeWeeklyDigest,
+    DigestEnabled,
+    DigestDisabled,
+    QuietWeekButton,
+    QuietWeekEnabled,
+    QuietWeekDisabled,
+    QuietWeek,
+    On,
+    Off,
+}
+
+/// Returns the text for `key` in `lang`.
+pub fn t(key: Key, lang: Lang) -> &'static str {
+    let (de, en) = texts(key);
+    match lang {
+        Lang::De => de,
+        Lang::En => en,
+    }
+}
+
+/// Like `t`, replacing each `{}` with the next argument.
+pub fn tf(key: Key, lang: Lang, args: &[&(dyn Display + Sync)]) -> String {
+    let mut args = args.iter();
+    let mut parts = t(key, lang).split("{}");
+    let mut out = String::from(parts.next().unwrap_or_default());
+    for part in parts {
+        if let Some(arg) = args.next() {
+            out.push_str(&arg.to_string());
+        }
+        out.push_str(part);
+    }
+    out
+}
+
+/// Placeholders a user's own reminder template may use, filled per reminder line.
+pub const TEMPLATE_PLACEHOLDERS: [&str; 4] = ["type", "date", "when", "location"];
+
+/// Longest reminder template a user can save, in characters.
+pub const MAX_TEMPLATE_CHARS: usize = 200;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TemplateError {
+    #[error("template is longer than {MAX_TEMPLATE_CHARS} characters")]
+    TooLong,
+    #[error("unknown placeholder {{{0}}}")]
+    UnknownPlaceholder(String),
+    #[error("a {{ is never closed")]
+    Unclosed,
+}
+
+/// Checks that `template` only uses `TEMPLATE_PLACEHOLDERS` and isn't too long.
+pub fn check_template(template: &str) -> Result<(), TemplateError> {
+    if template.chars().count() > MAX_TEMPLATE_CHARS {
+        return Err(TemplateError::TooLong);
+    }
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            return Err(TemplateError::Unclosed);
+        };
+        let name = &rest[start + 1..start + len];
+        if !TEMPLATE_PLACEHOLDERS.contains(&name) {
+            return Err(TemplateError::UnknownPlaceholder(name.to_string()));
+        }
+        rest = &rest[start + len + 1..];
+    }
+    Ok(())
+}
+
+/// Replaces each `{name}` in `template` with its value. Placeholders without a value
+/// are left as they are.
+pub fn fill_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut out = template.to_string();
+    for (name, value) in values {
+        out = out.replace(&format!("{{{}}}", name), value);
+    }
+    out
+}
+
+/// (German, English) for each key.
+fn texts(key: Key) -> (&'static str, &'static str) {
+    match key {
+        Key::EnterLocationId => (
+            "Bitte sende deine Standort-ID. Du findest sie im Abfallkalender der Stadt Dresden. \
+             Mit /cancel brichst du ab.",
+            "Please enter your Location ID (Standort-ID). You can find it on the Dresden waste \
+             management website. Send /cancel to abort.",
+        ),
+        Key::HelpText => (
+            "Standort-ID finden:
+Suche deine Adresse im Abfallkalender auf der Website der Stadt Dresden \
+(stadtplan.dresden.de). Die Standort-ID ist die Nummer zu deiner Adresse, z. B. 12345.
+
+Benachrichtigungszeiten:
+Jeder Standort hat eine Uhrzeit (stündlich, z. B. 18:00) und einen Tag. \
+\"Am Vortag\" erinnert dich am Abend vor der Abholung, \"Am selben Tag\" am Morgen.
+
+Beispiele:
+/addlocation - dann 12345 und einen Namen wie Zuhause senden
+/setlocation 12345 Zuhause - dasselbe in einer Nachricht
+/settings - Abfallarten, Uhrzeit und Tag pro Standort umschalten",
+            "Finding your Location ID (Standort-ID):
+Look up your address in the waste calendar (Abfallkalender) on the Dresden city website \
+(stadtplan.dresden.de). The Standort-ID is the number shown for your address, e.g. 12345.
+
+Notification times:
+Each location has a notify time (hourly, e.g. 18:00) and a day setting. \
+\"Day Before\" reminds you the evening before a pickup, \"Same Day\" on the morning of it.
+
+Examples:
+/addlocation - then send 12345 and an alias like Home
+/setlocation 12345 Home - the same in one message
+/settings - toggle waste types, time and day per location",
+        ),
+        Key::TypesHeader => (
+            "♻️ Abfallarten und was hineingehört:",
+            "♻️ Waste types and what goes in each bin:",
+        ),
+        Key::ExportNothing => (
+            "Über dich sind keine Daten gespeichert.",
+            "There is no data stored about you.",
+        ),
+        Key::ExportCaption => (
+            "Alle Daten, die über dich gespeichert sind.",
+            "All data stored about you.",
+        ),
+        Key::PreviewHeader => (
+            "(Vorschau) Nächste Erinnerung am {} um {}:\n\n{}",
+            "(preview) Next reminder on {} at {}:\n\n{}",
+        ),
+        Key::PreviewNone => (
+            "(Vorschau) In den nächsten {} Stunden ist keine Erinnerung fällig.",
+            "(preview) No reminders are due in the next {} hours.",
+        ),
+        Key::MyStats => (
+            "Du hast bisher {} Erinnerungen bekommen, zuletzt am {} um {} Uhr.",
+            "You have received {} reminders so far, the last one on {} at {}.",
+        ),
+        Key::MyStatsNone => (
+            "Du hast noch keine Erinnerung bekommen. Sobald eine Abholung ansteht, melde ich mich.",
+            "You haven't received any reminders yet. I'll be in touch once a pickup is due.",
+        ),
+        Key::SubscribeUsage => (
+            "Gib eine Abfallart an, z. B. /subscribe Bio. Verfügbar: {}",
+            "Name a waste type, e.g. /subscribe Bio. Available: {}",
+        ),
+        Key::UnknownWasteType => (
+            "Unbekannte Abfallart '{}'. Verfügbar: {}",
+            "Unknown waste type '{}'. Available: {}",
+        ),
+        Key::SubscribedTo => ("✅ {} abonniert.", "✅ Subscribed to {}."),
+        Key::UnsubscribedFrom => ("❌ {} abbestellt.", "❌ Unsubscribed from {}."),
+        Key::SubscriptionsLine => ("\n📍 {}: {}", "\n📍 {}: {}"),
+        Key::InspectUsage => ("Nutzung: /inspect <Chat-ID>", "Usage: /inspect <chat ID>"),
+        Key::RenormalizeDone => (
+            "Abfallarten neu geschrieben: {} Abos, {} Abholtermine, {} Log-Einträge, \
+             {} Wiederholungen, {} Snoozes.",
+            "Waste types rewritten: {} subscriptions, {} pickup events, {} log entries, \
+             {} retries, {} snoozes.",
+        ),
+        Key::IcalOutage => (
+            "⚠️ Das iCal-Update ist für alle {} Standorte fehlgeschlagen. Stimmt die Feed-URL \
+             noch? (ICAL_URL_TEMPLATE)",
+            "⚠️ iCal update failed for all {} locations. Is the feed URL still valid? \
+             (ICAL_URL_TEMPLATE)",
+        ),
+        Key::RawFeedUsage => ("Nutzung: /rawfeed <Standort-ID>", "Usage: /rawfeed <location ID>"),
+        Key::RawFeedNone => (
+            "Für {} ist kein Feed gespeichert. Ist CACHE_RAW_ICAL gesetzt?",
+            "No feed is cached for {}. Is CACHE_RAW_ICAL set?",
+        ),
+        Key::RawFeedCaption => ("Feed von {}, abgerufen am {}.", "Feed of {}, fetched on {}."),
+        Key::InspectUnknown => ("Kein Nutzer mit der Chat-ID {}.", "No user with chat ID {}."),
+        Key::InspectUser => (
+            "👤 Chat {}, seit {}\nSprache: {}, Modus: {}, pausiert bis: {}",
+            "👤 Chat {}, since {}\nLanguage: {}, mode: {}, muted until: {}",
+        ),
+        Key::InspectNextPickup => ("\nNächste Abholung: {}: {}", "\nNext pickup: {}: {}"),
+        Key::InspectNoPickup => ("\nKeine anstehende Abholung.", "\nNo upcoming pickup."),
+        Key::TestNotifyUsage => (
+            "Nutzung: /testnotify <Stunde oder HH:MM>, z. B. /testnotify 18",
+            "Usage: /testnotify <hour or HH:MM>, e.g. /testnotify 18",
+        ),
+        Key::TestNotifyRecipient => ("🧪 Test, eigentlich für {}:", "🧪 Test, meant for {}:"),
+        Key::TestNotifyDone => (
+            "🧪 Testlauf für {}: {} Nachrichten an diesen Chat umgeleitet.",
+            "🧪 Test run for {}: {} messages redirected to this chat.",
+        ),
+        Key::TemplateButton => ("✏️ Erinnerungstext", "✏️ Reminder text"),
+        Key::TemplatePrompt => (
+            "Sende deinen eigenen Erinnerungstext. Platzhalter: {when} (Heute/Morgen), {date}, \
+             {type} (Abfallarten) und {location}. Beispiel: {when} ist {type} dran, Tonnen raus!\n\
+             Sende - für den Standardtext, /cancel bricht ab.\n\nAktuell: {}",
+            "Send your own reminder text. Placeholders: {when} (Today/Tomorrow), {date}, \
+             {type} (waste types) and {location}. Example: {when}: {type}, put the bins out!\n\
+             Send - for the default text, /cancel to abort.\n\nCurrent: {}",
+        ),
+        Key::TemplateDefault => ("Standardtext", "default text"),
+        Key::TemplateTooLong => (
+            "Der Text ist zu lang, höchstens {} Zeichen.",
+            "That text is too long, at most {} characters.",
+        ),
+        Key::TemplateUnknownPlaceholder => (
+            "Unbekannter Platzhalter {{}}. Möglich sind {{}}.",
+            "Unknown placeholder {{}}. Available are {{}}.",
+        ),
+        Key::TemplateUnclosed => (
+            "Eine { wird nicht mit } geschlossen.",
+            "A { is never closed with }.",
+        ),
+        Key::TemplateSaved => (
+            "Gespeichert. So sehen deine Erinnerungen jetzt aus:\n{}",
+            "Saved. Your reminders now look like this:\n{}",
+        ),
+        Key::TemplateReset => (
+            "Erinnerungen verwenden wieder den Standardtext.",
+            "Reminders use the default text again.",
+        ),
+        Key::TemplateExampleLocation => ("Zuhause", "Home"),
+        Key::ImportPrompt => (
+            "Sende die JSON-Datei aus /export (als Datei oder Text). Deine aktuellen Standorte und Einstellungen werden dabei ersetzt. /cancel bricht ab.",
+            "Send the JSON file from /export (as a file or as text). It replaces your current locations and settings. Use /cancel to abort.",
+        ),
+        Key::ImportTooLarge => (
+            "Die Datei ist zu groß für einen Import.",
+            "That file is too large to import.",
+        ),
+        Key::ImportInvalid => (
+            "❌ Import fehlgeschlagen, nichts wurde geändert: {}",
+            "❌ Import failed, nothing was changed: {}",
+        ),
+        Key::ImportDone => (
+            "✅ Import abgeschlossen: {} Standort(e).",
+            "✅ Import complete: {} location(s).",
+        ),
+        Key::FeedbackNotConfigured => (
+            "Feedback ist für diesen Bot nicht eingerichtet.",
+            "Feedback isn't configured for this bot.",
+        ),
+        Key::FeedbackPrompt => (
+            "Bitte sende dein Feedback in einer einzigen Nachricht.",
+            "Please send your feedback in a single message.",
+        ),
+        Key::FeedbackThanks => (
+            "Danke! Dein Feedback wurde gesendet.",
+            "Thanks! Your feedback has been sent.",
+        ),
+        Key::FeedbackHeader => (
+            "📬 Feedback aus Chat {} ({}):",
+            "📬 Feedback from chat {} ({}):",
+        ),
+        Key::StopConfirm => (
+            "Damit werden alle deine Standorte und Abos gelöscht. Bist du sicher?",
+            "This will delete all your locations and subscriptions. Are you sure?",
+        ),
+        Key::StopConfirmButton => ("🗑️ Ja, löschen", "🗑️ Yes, delete"),
+        Key::CancelButton => ("Abbrechen", "Cancel"),
+        Key::StopDone => (
+            "Du wurdest abgemeldet und deine Daten gelöscht.",
+            "You have been unsubscribed and your data deleted.",
+        ),
+        Key::Cancelled => ("Abgebrochen.", "Cancelled."),
+        Key::AdminOnly => (
+            "Dieser Befehl ist nur für den Betreiber verfügbar.",
+            "This command is only available to the operator.",
+        ),
+        Key::BroadcastUsage => (
+            "Verwendung: /broadcast <Nachricht>",
+            "Usage: /broadcast <message>",
+        ),
+        Key::BroadcastReport => (
+            "Rundnachricht an {} von {} Nutzern zugestellt ({} entfernt, da der Bot blockiert wurde).",
+            "Broadcast reached {} of {} users ({} removed after blocking the bot).",
+        ),
+        Key::InvalidLocationId => (
+            "Ungültige Standort-ID. Sie darf nur Buchstaben und Ziffern enthalten und höchstens 20 Zeichen lang sein.",
+            "Invalid Location ID. It must be alphanumeric and max 20 characters.",
+        ),
+        Key::AmbiguousLocationId => (
+            "Ich habe mehrere mögliche Standort-IDs gefunden. Bitte schick mir nur die eine Nummer.",
+            "I found more than one possible Location ID. Please send just the one number.",
+        ),
+        Key::AliasPrompt => (
+            "Bitte gib diesem Standort einen kurzen Namen (z. B. 'Zuhause', 'Büro').",
+            "Please give this location a short alias (e.g., 'Home', 'Office').",
+        ),
+        Key::AliasTooLong => (
+            "Der Name ist zu lang. Bitte höchstens 50 Zeichen.",
+            "Alias is too long. Please keep it under 50 characters.",
+        ),
+        Key::AliasInvalid => (
+            "Der Name enthält ungültige Zeichen. Bitte nur normalen Text verwenden.",
+            "Alias contains invalid characters. Please use standard text.",
+        ),
+        Key::LocationAdded => (
+            "Standort '{}' ({}) mit Standard-Abos hinzugefügt.",
+            "Location '{}' ({}) added with default subscriptions.",
+        ),
+        Key::LocationAddError => (
+            "Fehler beim Hinzufügen des Standorts: {}",
+            "Error adding location: {}",
+        ),
+        Key::MutePrompt => (
+            "Für wie viele Tage sollen die Benachrichtigungen pausieren? (1-{})",
+            "For how many days should notifications be paused? (1-{})",
+        ),
+        Key::MuteDaysInvalid => (
+            "Bitte sende eine Anzahl von Tagen zwischen 1 und {}.",
+            "Please send a number of days between 1 and {}.",
+        ),
+        Key::LeadTimePrompt => (
+            "Wie viele Stunden vor der Abholung (ab {}:00 Uhr) möchtest du erinnert werden? (0-{})\nSende \"aus\", um wieder Uhrzeit und Tag zu verwenden.",
+            "How many hours before the pickup (from {}:00) should we remind you? (0-{})\nSend \"off\" to go back to the time and day setting.",
+        ),
+        Key::LeadTimeInvalid => (
+            "Bitte sende eine Stundenzahl zwischen 0 und {} oder \"aus\".",
+            "Please send a number of hours between 0 and {}, or \"off\".",
+        ),
+        Key::LeadTimeSet => (
+            "✅ Erinnerung {} Stunden vor der Abholung: {} ({}).",
+            "✅ Reminder {} hours before the pickup: {} ({}).",
+        ),
+        Key::LeadTimeCleared => (
+            "✅ Erinnerung wieder nach Uhrzeit und Tag.",
+            "✅ Reminders follow the time and day setting again.",
+        ),
+        Key::MutedUntil => (
+            "Benachrichtigungen pausiert bis einschließlich {}. Mit /settings kannst du sie früher fortsetzen.",
+            "Notifications paused until {} (inclusive). Use /settings to unmute early.",
+        ),
+        Key::InvalidState => (
+            "Bitte nutze /start oder /addlocation, um zu beginnen.",
+            "Please use /start or /addlocation to begin.",
+        ),
+        Key::NoLocations => (
+            "Du hast keine Standorte eingerichtet. Nutze /addlocation.",
+            "You have no locations set up. Use /addlocation.",
+        ),
+        Key::NoLocationsStatus => (
+            "Du hast noch keine Standorte eingerichtet. Nutze /start, um einen hinzuzufügen.",
+            "You have no locations set up yet. Use /start to add one.",
+        ),
+        Key::NoLocationsLeft => ("Keine Standorte mehr.", "No locations left."),
+        Key::YourLocations => ("Deine Standorte:", "Your Locations:"),
+        Key::YourConfiguration => ("Deine Einstellungen:", "Your configuration:"),
+        Key::StatusLocation => (
+            "\n\n📍 {}\nStandort: {}\nBenachrichtigung: {} ({})\nAbos: {}",
+            "\n\n📍 {}\nLocation: {}\nNotify: {} ({})\nSubscriptions: {}",
+        ),
+        Key::NoSubscriptions => ("keine", "none"),
+        Key::DayBefore => ("Am Vortag", "Day Before"),
+        Key::SameDay => ("Am selben Tag", "Same Day"),
+        Key::SettingsFor => (
+            "Einstellungen für {}:\nStandort: {}",
+            "Settings for {}:\nLocation: {}",
+        ),
+        Key::LocationNotFound => ("Standort nicht gefunden.", "Location not found."),
+        Key::Subscribed => ("Abonniert!", "Subscribed!"),
+        Key::Unsubscribed => ("Abbestellt!", "Unsubscribed!"),
+        Key::TimeUpdated => ("Uhrzeit geändert!", "Time updated!"),
+        Key::DayUpdated => ("Tag geändert!", "Day updated!"),
+        Key::LocationDeleted => ("Standort gelöscht.", "Location deleted."),
+        Key::NotificationsResumed => ("Benachrichtigungen fortgesetzt.", "Notifications resumed."),
+        Key::ButtonUnavailable => (
+            "Diese Schaltfläche funktioniert hier nicht. Öffne das Menü mit /settings neu.",
+            "This button doesn't work here. Open the menu again with /settings.",
+        ),
+        Key::MenuExpired => (
+            "Dieses Menü ist abgelaufen, bitte /settings erneut aufrufen.",
+            "This menu expired, please run /settings again.",
+        ),
+        Key::FeedsHeader => ("Feeds:", "Feeds:"),
+        Key::FeedsLine => (
+            "{}: aktualisiert {}, {} anstehende Termine",
+            "{}: updated {}, {} upcoming pickups",
+        ),
+        Key::StatsHeader => (
+            "{} Nutzer, {} abgerufene Standorte. Meistgenutzt:",
+            "{} users, {} locations fetched. Most used:",
+        ),
+        Key::StatsLine => ("{}: {} Nutzer", "{}: {} users"),
+        Key::Never => ("nie", "never"),
+        Key::RefreshCooldown => (
+            "Bitte warte noch {} Minute(n), bevor du erneut aktualisierst.",
+            "Please wait {} more minute(s) before refreshing again.",
+        ),
+        Key::LocationStale => (
+            "⚠️ Für {} liefert die Stadt seit einiger Zeit keine Abholtermine mehr. Vielleicht \
+             gibt es die Standort-ID nicht mehr. Bitte prüfe sie und füge den Standort ggf. mit \
+             /addlocation neu hinzu.",
+            "⚠️ The city hasn't listed any pickups for {} for a while. The Standort-ID may no \
+             longer exist. Please verify it and re-add the location with /addlocation if needed.",
+        ),
+        Key::LastUpdateLine => (
+            "📍 {}: Abholtermine zuletzt {} aktualisiert ({}).",
+            "📍 {}: pickup dates last refreshed {} ({}).",
+        ),
+        Key::LastUpdateNever => (
+            "📍 {}: Noch keine Abholtermine geladen. Versuch es mit /refresh.",
+            "📍 {}: No pickup dates loaded yet. Try /refresh.",
+        ),
+        Key::AgeToday => ("heute", "today"),
+        Key::AgeYesterday => ("gestern", "yesterday"),
+        Key::AgeDaysAgo => ("vor {} Tagen", "{} days ago"),
+        Key::PleaseWait => (
+            "Einen Moment bitte, und versuch es dann noch einmal.",
+            "Please wait a moment, then try again.",
+        ),
+        Key::RefreshLoaded => ("📍 {}: {} Termine geladen.", "📍 {}: {} events loaded."),
+        Key::RefreshFailed => (
+            "📍 {}: Der Kalender konnte nicht abgerufen werden. Bitte versuche es später erneut.",
+            "📍 {}: Couldn't fetch the calendar. Please try again later.",
+        ),
+        Key::RefreshUnknownLocation => (
+            "📍 {}: Die Stadt kennt die Standort-ID {} nicht. Bitte prüfe sie unter /locations.",
+            "📍 {}: The city doesn't know the location ID {}. Please check it in /locations.",
+        ),
+        Key::UnmuteButton => ("🔔 Fortsetzen (pausiert bis {})", "🔔 Unmute (muted until {})"),
+        Key::PauseButton => ("🔇 Benachrichtigungen pausieren", "🔇 Pause notifications"),
+        Key::SubscribeAllButton => ("✅ Alle abonnieren", "✅ Subscribe to all"),
+        Key::UnsubscribeAllButton => ("❌ Alle abbestellen", "❌ Unsubscribe from all"),
+        Key::NotifyTimeButton => ("Uhrzeit: {}", "Notify Time: {}"),
+        Key::DayButton => ("Tag: {}", "Day: {}"),
+        Key::LeadTimeButton => ("⏱ Vorlauf: {}", "⏱ Lead Time: {}"),
+        Key::LeadTimeOff => ("aus", "off"),
+        Key::ExtraMorningButton => ("🌅 Auch am Morgen, {}", "🌅 Also the morning of, {}"),
+        Key::ExtraEveningButton => ("🌙 Auch am Vorabend, {}", "🌙 Also the evening before, {}"),
+        Key::ReminderAdded => ("Erinnerung hinzugefügt!", "Reminder added!"),
+        Key::ReminderRemoved => ("Erinnerung entfernt!", "Reminder removed!"),
+        Key::EveningAndMorningButton => (
+            "🌙+🌅 Am Vorabend und am Morgen",
+            "🌙+🌅 Evening before and morning of",
+        ),
+        Key::EveningAndMorningSet => (
+            "Erinnerung am Vorabend (18:00) und am Morgen (06:00) eingestellt!",
+            "Reminders set for the evening before (18:00) and the morning of (06:00)!",
+        ),
+        Key::DeleteLocationButton => ("🗑️ Standort löschen", "🗑️ Delete Location"),
+        Key::BackButton => ("🔙 Zurück zu den Standorten", "🔙 Back to Locations"),
+        // Labelled with the language it switches to, in that language
+        Key::LanguageButton => ("🌐 English", "🌐 Deutsch"),
+        Key::LanguageChanged => ("Sprache: Deutsch", "Language: English"),
+        Key::NoUpcomingPickups => (
+            "⚠️ Für {} konnten wir keine anstehenden Abholtermine finden. Bitte prüfe deine \
+             Standort-ID und füge den Standort ggf. mit /addlocation neu hinzu.",
+            "⚠️ We couldn't find upcoming pickups for {}. Please verify your Standort-ID and \
+             re-add the location with /addlocation if needed.",
+        ),
+        Key::Tomorrow => ("Morgen", "Tomorrow"),
+        Key::Today => ("Heute", "Today"),
+        Key::NotificationLine => (
+            "📅 {} ({}) bei {}: Abholung von {}.",
+            "📅 {} ({}) at {}: {} collection.",
+        ),
+        Key::SnoozeButton => ("⏰ Um {}:00 nochmal erinnern", "⏰ Remind me again at {}:00"),
+        Key::Snoozed => (
+            "Ich erinnere dich um {}:00 noch einmal.",
+            "I'll remind you again at {}:00.",
+        ),
+        Key::SnoozeTooLate => (
+            "Dafür ist es heute schon zu spät.",
+            "It's too late for that today.",
+        ),
+        Key::SnoozeReminder => (
+            "⏰ Erinnerung: {} ({}): Abholung von {}.",
+            "⏰ Reminder: {} ({}): {} collection.",
+        ),
+        Key::RemindBeforeUsage => (
+            "Nutzung: /remindbefore [Stunden], mit 1 bis {} Stunden vor der Abholung.",
+            "Usage: /remindbefore [hours], with 1 to {} hours before the pickup.",
+        ),
+        Key::RemindBeforePrompt => (
+            "Für welche Abholung möchtest du {} Stunden vorher eine zusätzliche Erinnerung?",
+            "Which pickup would you like an extra reminder for, {} hours before?",
+        ),
+        Key::RemindBeforeNone => (
+            "Es stehen keine Abholungen an, für die das noch klappt.",
+            "There are no upcoming pickups left to set that reminder for.",
+        ),
+        Key::RemindBeforeTooLate => (
+            "Für diese Abholung ist es dafür schon zu spät.",
+            "It's too late for that reminder for this pickup.",
+        ),
+        Key::RemindBeforeSet => ("Ich erinnere dich am {} um {}.", "I'll remind you on {} at {}."),
+        Key::DigestHeader => (
+            "🗓 Deine Abholungen in der kommenden Woche:",
+            "🗓 Your pickups in the coming week:",
+        ),
+        Key::DigestLine => ("• {} bei {}: {}", "• {} at {}: {}"),
+        Key::NotifyModeButton => ("📬 Benachrichtigung: {}", "📬 Reminders: {}"),
+        Key::ModePerEvent => ("je Abholung", "per pickup"),
+        Key::ModeWeeklyDigest => ("Wochenübersicht", "weekly summary"),
+        Key::DigestEnabled => (
+            "Du bekommst jetzt eine Wochenübersicht statt einzelner Erinnerungen.",
+            "You'll now get a weekly summary instead of single reminders.",
+        ),
+        Key::DigestDisabled => (
+            "Du bekommst jetzt wieder vor jeder Abholung eine Erinnerung.",
+            "You'll get a reminder before each pickup again.",
+        ),
+        Key::QuietWeekButton => (
+            "📭 Hinweis bei Wochen ohne Abholung: {}",
+            "📭 Note on weeks without pickups: {}",
+        ),
+        Key::QuietWeekEnabled => (
+            "Du bekommst jetzt Bescheid, wenn in der kommenden Woche nichts abgeholt wird.",
+            "You'll now be told when nothing is collected in the coming week.",
+        ),
+        Key::QuietWeekDisabled => (
+            "Kein Hinweis mehr bei Wochen ohne Abholung.",
+            "No more notes on weeks without pickups.",
+        ),
+        Key::QuietWeek => (
+            "📭 In der kommenden Woche sind für deine Standorte keine Abholungen geplant.",
+            "📭 No pickups scheduled for your locations in the coming week.",
+        ),
+        Key::On => ("an", "on"),
+        Key::Off => ("aus", "off"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    #[test]
+    fn test_every_key_has_both_languages() {
+        for key in Key::iter() {
+            let (de, en) = texts(key);
+            assert!(!de.is_empty(), "{:?} has no German text", key);
+            assert!(!en.is_empty(), "{:?} has no English text", key);
+            assert_eq!(
+                de.matches("{}").count(),
+                en.matches("{}").count(),
+                "{:?} has different placeholders",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn test_tf_fills_placeholders() {
+        assert_eq!(
+            tf(Key::LocationAdded, Lang::En, &[&"Home", &12345]),
+            "Location 'Home' (12345) added with default subscriptions."
+        );
+        assert_eq!(
+            tf(Key::MutePrompt, Lang::De, &[&365])
+                .matches("365")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_check_template() {
+        assert_eq!(
+            check_template("{when}: {type} in {location} ({date}). Tonnen raus!"),
+            Ok(())
+        );
+        assert_eq!(check_template("No placeholders at all"), Ok(()));
+        assert_eq!(
+            check_template("{when}: {typ}"),
+            Err(TemplateError::UnknownPlaceholder("typ".to_string()))
+        );
+        assert_eq!(check_template("{type"), Err(TemplateError::Unclosed));
+        assert_eq!(
+            check_template(&"x".repeat(MAX_TEMPLATE_CHARS + 1)),
+            Err(TemplateError::TooLong)
+        );
+    }
+
+    #[test]
+    fn test_fill_template() {
+        assert_eq!(
+            fill_template(
+                "{when}: {type}, {type}!",
+                &[("when", "Tomorrow"), ("type", "Bio")]
+            ),
+            "Tomorrow: Bio, Bio!"
+        );
+    }
+
+    #[test]
+    fn test_lang_codes() {
+        assert_eq!(Lang::from_code(Lang::En.code()), Lang::En);
+        assert_eq!(Lang::from_code("de"), Lang::De);
+        assert_eq!(Lang::from_code("fr"), Lang::De);
+        assert_eq!(Lang::default().toggled(), Lang::En);
+    }
+
+    #[test]
+    fn test_format_day() {
+        let friday = NaiveDate::from_ymd_opt(2024, 6, 7).unwrap();
+        assert_eq!(Lang::De.format_day(friday), "Fr, 07.06.");
+        assert_eq!(Lang::En.format_day(friday), "Fri, 07.06.");
+        let sunday = NaiveDate::from_ymd_opt(2024, 6, 9).unwrap();
+        assert_eq!(Lang::De.format_day(sunday), "So, 09.06.");
+    }
+}
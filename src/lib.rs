@@ -0,0 +1,18 @@
+//! Telegram bot that reminds users of waste pickups in Dresden.
+//!
+//! The binary in `main.rs` wires these modules together. `waste` (feed parsing),
+//! `store` (database access) and `scheduler::update_location_ical` can also be used on
+//! their own, e.g. by the binary's `dump <id> [--json]` subcommand.
+
+pub mod bot_handler;
+pub mod config;
+pub mod db;
+#[cfg(test)]
+mod db_tests;
+mod dialogue_storage;
+pub mod i18n;
+pub mod metrics;
+pub mod notifier;
+pub mod scheduler;
+pub mod store;
+pub mod waste;
@@ -1,24 +1,71 @@
-mod bot_handler;
-mod db;
-#[cfg(test)]
-mod db_tests;
-mod scheduler;
-mod store;
-mod waste;
-
-use bot_handler::run_bot;
-use db::init_db;
 use dotenvy::dotenv;
-use log::{error, info};
-use scheduler::run_scheduler;
+use dresden_waste_bot::bot_handler::run_bot;
+use dresden_waste_bot::db::init_db;
+use dresden_waste_bot::notifier::Notifier;
+use dresden_waste_bot::scheduler::run_scheduler;
+use dresden_waste_bot::waste::{self, PickupEvent};
+use dresden_waste_bot::{config, metrics};
 use std::env;
 use std::error::Error;
+use std::sync::Arc;
 use teloxide::prelude::*;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+/// What the binary was asked to do on the command line.
+#[derive(Debug, PartialEq)]
+enum Cli {
+    /// No arguments: run the bot.
+    Bot,
+    /// `dump <location ID> [--json]`: print a location's upcoming pickups and exit.
+    Dump { location_id: String, json: bool },
+}
+
+const USAGE: &str = "Usage: dresden_waste_bot [dump <location ID> [--json]]";
+
+impl Cli {
+    fn parse(args: &[String]) -> Result<Cli, String> {
+        let Some((command, rest)) = args.split_first() else {
+            return Ok(Cli::Bot);
+        };
+        if command != "dump" {
+            return Err(format!("Unknown command {:?}\n{}", command, USAGE));
+        }
+        let mut location_id = None;
+        let mut json = false;
+        for arg in rest {
+            match arg.as_str() {
+                "--json" => json = true,
+                _ if location_id.is_none() && !arg.starts_with('-') => {
+                    location_id = Some(arg.clone())
+                }
+                _ => return Err(format!("Unexpected argument {:?}\n{}", arg, USAGE)),
+            }
+        }
+        match location_id {
+            Some(location_id) => Ok(Cli::Dump { location_id, json }),
+            None => Err(USAGE.to_string()),
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     dotenv().ok();
-    env_logger::init();
+    // RUST_LOG works as before (e.g. RUST_LOG=info); unset shows errors only.
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    match Cli::parse(&args) {
+        Ok(Cli::Bot) => {}
+        Ok(Cli::Dump { location_id, json }) => return dump(&location_id, json).await,
+        Err(usage) => {
+            eprintln!("{}", usage);
+            std::process::exit(2);
+        }
+    }
 
     info!("Starting Dresden Waste Bot...");
 
@@ -33,15 +80,143 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let bot = Bot::new(token);
 
+    let shutdown = CancellationToken::new();
+    tokio::spawn(shutdown_on_signal(shutdown.clone()));
+
+    let metrics = config::metrics_port()
+        .map(|port| tokio::spawn(metrics::serve(pool.clone(), port, shutdown.clone())));
+
     // Start Scheduler
-    let bot_clone = bot.clone();
-    let pool_clone = pool.clone();
-    tokio::spawn(async move {
-        run_scheduler(bot_clone, pool_clone).await;
-    });
+    let dry_run = config::dry_run();
+    if dry_run {
+        info!("DRY_RUN is set: messages are logged instead of sent.");
+    }
+    let notifier = Arc::new(Notifier::new(bot.clone()).dry_run(dry_run));
+    let scheduler = tokio::spawn(run_scheduler(
+        notifier.clone(),
+        pool.clone(),
+        shutdown.clone(),
+    ));
 
     // Run the bot
-    run_bot(bot, pool).await;
+    run_bot(bot, pool.clone(), notifier, shutdown.clone()).await;
+
+    // The dispatcher may also stop on its own; make sure the scheduler follows.
+    shutdown.cancel();
+    if let Err(e) = scheduler.await {
+        error!("Scheduler task failed: {:?}", e);
+    }
+    if let Some(metrics) = metrics {
+        if let Err(e) = metrics.await {
+            error!("Metrics server task failed: {:?}", e);
+        }
+    }
+
+    // Nothing uses the database any more. Closing waits for every connection to be
+    // returned and closes it, so SQLite finishes pending writes and checkpoints its journal.
+    pool.close().await;
+    info!("Database pool closed.");
+    info!("Shutdown complete.");
+
+    Ok(())
+}
+
+/// Fetches and parses a location's feed like the bot does and prints its upcoming
+/// pickups, without Telegram or the database. Useful to check a Standort-ID.
+async fn dump(location_id: &str, json: bool) -> Result<(), Box<dyn Error>> {
+    if !waste::is_valid_location_id(location_id) {
+        return Err(format!("Invalid location ID {:?}", location_id).into());
+    }
+    let client = dresden_waste_bot::scheduler::build_http_client()?;
+    let calendar = dresden_waste_bot::scheduler::fetch_calendar(&client, location_id).await?;
+    let today = chrono::Local::now().date_naive();
+    let events: Vec<&PickupEvent> = calendar.events.iter().filter(|e| e.date >= today).collect();
+
+    if json {
+        let output = serde_json::json!({
+            "location_id": location_id,
+            "name": calendar.name,
+            "events": events,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
 
+    println!(
+        "{} ({})",
+        calendar.name.as_deref().unwrap_or("Unnamed location"),
+        location_id
+    );
+    println!("{:<15} {:<7} WASTE TYPES", "DATE", "ALL DAY");
+    for event in &events {
+        let types: Vec<String> = event.waste_types.iter().map(|w| w.label()).collect();
+        println!(
+            "{:<15} {:<7} {}",
+            event.date.format("%a %d.%m.%Y"),
+            if event.all_day { "yes" } else { "no" },
+            types.join(", ")
+        );
+    }
+    println!("{} upcoming pickups", events.len());
     Ok(())
 }
+
+/// Cancels `shutdown` on SIGINT, or SIGTERM on Unix (e.g. `docker stop`).
+async fn shutdown_on_signal(shutdown: CancellationToken) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = sigterm.recv() => {}
+                }
+            }
+            Err(e) => {
+                error!("Failed to install SIGTERM handler: {:?}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+
+    info!("Shutdown signal received, finishing in-flight work...");
+    shutdown.cancel();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_cli_parse() {
+        assert_eq!(Cli::parse(&[]), Ok(Cli::Bot));
+        assert_eq!(
+            Cli::parse(&args(&["dump", "12345"])),
+            Ok(Cli::Dump {
+                location_id: "12345".to_string(),
+                json: false
+            })
+        );
+        assert_eq!(
+            Cli::parse(&args(&["dump", "--json", "12345"])),
+            Ok(Cli::Dump {
+                location_id: "12345".to_string(),
+                json: true
+            })
+        );
+        assert!(Cli::parse(&args(&["dump"])).is_err());
+        assert!(Cli::parse(&args(&["dump", "1", "2"])).is_err());
+        assert!(Cli::parse(&args(&["dump", "1", "--csv"])).is_err());
+        assert!(Cli::parse(&args(&["serve"])).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use crate::store;
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use chrono::{Duration, Local, NaiveDateTime, Utc};
+use sqlx::SqlitePool;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+static NOTIFICATIONS_SENT: AtomicU64 = AtomicU64::new(0);
+static NOTIFICATIONS_FAILED: AtomicU64 = AtomicU64::new(0);
+/// Unix timestamp of the last iCal run with at least one successful location, 0 if none yet.
+static LAST_ICAL_UPDATE: AtomicI64 = AtomicI64::new(0);
+
+pub fn notification_sent() {
+    NOTIFICATIONS_SENT.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn notification_failed() {
+    NOTIFICATIONS_FAILED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn ical_updated() {
+    LAST_ICAL_UPDATE.store(Local::now().timestamp(), Ordering::Relaxed);
+}
+
+/// Days a feed refresh may be overdue before `/healthz` reports the bot as unhealthy.
+const FEED_STALE_GRACE_DAYS: i64 = 2;
+
+/// Serves `/metrics` in the Prometheus text format and the `/healthz` check until
+/// `shutdown` is cancelled.
+pub async fn serve(pool: SqlitePool, port: u16, shutdown: CancellationToken) {
+    let app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .route("/healthz", get(healthz_handler))
+        .with_state(pool);
+
+    let listener = match tokio::net::TcpListener::bind(("0.0.0.0", port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind metrics server to port {}: {:?}", port, e);
+            return;
+        }
+    };
+    info!("Metrics server listening on port {}", port);
+
+    if let Err(e) = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+    {
+        error!("Metrics server failed: {:?}", e);
+    }
+}
+
+async fn metrics_handler(State(pool): State<SqlitePool>) -> impl IntoResponse {
+    let today = Local::now().date_naive();
+    let counts = async {
+        let users = store::count_users(&pool).await?;
+        let sent_today = store::count_notifications_on(&pool, today).await?;
+        anyhow::Ok((users, sent_today))
+    };
+
+    match counts.await {
+        Ok((users, sent_today)) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            render(users, sent_today),
+        ),
+        Err(e) => {
+            error!("Failed to collect metrics: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                String::from("# failed to collect metrics\n"),
+            )
+        }
+    }
+}
+
+/// 200 while the database answers and the feeds were refreshed on schedule, else 503
+/// with the reason, so an orchestrator can restart a wedged bot.
+async fn healthz_handler(State(pool): State<SqlitePool>) -> impl IntoResponse {
+    match check_health(&pool).await {
+        Ok(()) => (StatusCode::OK, String::from("ok\n")),
+        Err(problem) => {
+            warn!("Health check failed: {}", problem);
+            (StatusCode::SERVICE_UNAVAILABLE, format!("{}\n", problem))
+        }
+    }
+}
+
+async fn check_health(pool: &SqlitePool) -> Result<(), String> {
+    store::ping(pool)
+        .await
+        .map_err(|e| format!("database not responding: {}", e))?;
+    let locations = store::get_active_location_ids(pool)
+        .await
+        .map_err(|e| format!("database query failed: {}", e))?;
+    if locations.is_empty() {
+        // Nothing to refresh yet
+        return Ok(());
+    }
+    let last_update = store::last_feed_update(pool)
+        .await
+        .map_err(|e| format!("database query failed: {}", e))?;
+    let max_age = Duration::days(
+        crate::scheduler::ical_config().update_interval_days + FEED_STALE_GRACE_DAYS,
+    );
+    check_feed_age(last_update, Utc::now().naive_utc(), max_age)
+}
+
+/// Whether the last feed refresh is recent enough.
+fn check_feed_age(
+    last_update: Option<NaiveDateTime>,
+    now: NaiveDateTime,
+    max_age: Duration,
+) -> Result<(), String> {
+    match last_update {
+        Some(last) if now - last <= max_age => Ok(()),
+        Some(last) => Err(format!("last feed update was at {} UTC", last)),
+        None => Err(String::from("feeds were never updated")),
+    }
+}
+
+fn render(users: i64, sent_today: i64) -> String {
+    let metrics: [(&str, &str, &str, i64); 5] = [
+        (
+            "waste_bot_users",
+            "gauge",
+            "Number of registered users.",
+            users,
+        ),
+        (
+            "waste_bot_notifications_sent_today",
+            "gauge",
+            "Pickup reminders delivered today.",
+            sent_today,
+        ),
+        (
+            "waste_bot_notifications_sent_total",
+            "counter",
+            "Notification messages sent since startup.",
+            NOTIFICATIONS_SENT.load(Ordering::Relaxed) as i64,
+        ),
+        (
+            "waste_bot_notifications_failed_total",
+            "counter",
+            "Notification messages that failed since startup.",
+            NOTIFICATIONS_FAILED.load(Ordering::Relaxed) as i64,
+        ),
+        (
+            "waste_bot_last_ical_update_timestamp_seconds",
+            "gauge",
+            "Unix time of the last successful iCal update, 0 if none since startup.",
+            LAST_ICAL_UPDATE.load(Ordering::Relaxed),
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, kind, help, value) in metrics {
+        // Writing to a String cannot fail.
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_prometheus_text() {
+        let text = render(3, 7);
+        assert!(text.contains("# TYPE waste_bot_users gauge\nwaste_bot_users 3\n"));
+        assert!(text.contains("waste_bot_notifications_sent_today 7\n"));
+        assert!(text.contains("# TYPE waste_bot_notifications_sent_total counter\n"));
+        assert!(text.contains("waste_bot_last_ical_update_timestamp_seconds "));
+        assert!(text.ends_with('\n'));
+    }
+
+    #[test]
+    fn test_check_feed_age() {
+        let now =
+            NaiveDateTime::parse_from_str("2024-06-30 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let max_age = Duration::days(30);
+        assert!(check_feed_age(Some(now - Duration::days(29)), now, max_age).is_ok());
+        assert!(check_feed_age(Some(now - max_age), now, max_age).is_ok());
+        assert!(check_feed_age(Some(now - Duration::days(31)), now, max_age).is_err());
+        assert!(check_feed_age(None, now, max_age).is_err());
+    }
+}
@@ -0,0 +1,297 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use teloxide::prelude::*;
+use teloxide::types::InlineKeyboardMarkup;
+use teloxide::RequestError;
+use tokio::sync::Mutex;
+use tokio::time::{interval, sleep_until, Duration, Instant, Interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+/// Telegram allows roughly 30 messages per second across all chats; stay below that.
+const DEFAULT_MESSAGES_PER_SECOND: u64 = 25;
+
+/// How long one batch (a notification slot, a broadcast) may spend waiting on RetryAfter.
+pub const MAX_BATCH_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Result of a successful send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Sent on the first attempt.
+    Sent,
+    /// Telegram asked us to back off (RetryAfter) and the retry succeeded.
+    Deferred,
+}
+
+/// Sends bot messages through a shared rate limiter.
+///
+/// All outgoing bulk messages (notifications, broadcasts) should go through one
+/// `Notifier` so the pacing applies globally rather than per batch.
+pub struct Notifier {
+    bot: Bot,
+    gate: Mutex<Interval>,
+    /// Set when Telegram asks us to back off; every send waits until it has passed.
+    paused_until: Mutex<Option<Instant>>,
+    dry_run: bool,
+}
+
+impl Notifier {
+    pub fn new(bot: Bot) -> Self {
+        Self::with_rate(bot, DEFAULT_MESSAGES_PER_SECOND)
+    }
+
+    pub fn with_rate(bot: Bot, messages_per_second: u64) -> Self {
+        let mut gate = interval(Duration::from_millis(1000 / messages_per_second.max(1)));
+        // After an idle period, don't burst to catch up on missed ticks.
+        gate.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Notifier {
+            bot,
+            gate: Mutex::new(gate),
+            paused_until: Mutex::new(None),
+            dry_run: false,
+        }
+    }
+
+    /// In dry-run mode, `send` only logs the message and reports it as sent.
+    pub fn dry_run(mut self, enabled: bool) -> Self {
+        self.dry_run = enabled;
+        self
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Waits for a free slot, then sends `text` to `chat_id`.
+    ///
+    /// If Telegram answers with RetryAfter, pauses all sends for the requested time and
+    /// retries once.
+    pub async fn send(&self, chat_id: ChatId, text: String) -> Result<Delivery, RequestError> {
+        self.send_with(chat_id, text, None).await
+    }
+
+    /// Like `send`, with an optional inline keyboard under the message.
+    pub async fn send_with(
+        &self,
+        chat_id: ChatId,
+        text: String,
+        keyboard: Option<InlineKeyboardMarkup>,
+    ) -> Result<Delivery, RequestError> {
+        if self.dry_run {
+            info!("[dry run] Message to {}:\n{}", chat_id, text);
+            return Ok(Delivery::Sent);
+        }
+        let request = || {
+            let request = self.bot.send_message(chat_id, text.clone());
+            match &keyboard {
+                Some(keyboard) => request.reply_markup(keyboard.clone()),
+                None => request,
+            }
+        };
+        self.wait_turn().await;
+        match request().await {
+            Ok(_) => Ok(Delivery::Sent),
+            Err(RequestError::RetryAfter(retry_after)) => {
+                warn!(
+                    "Rate limited by Telegram, retrying message to {} in {:?}",
+                    chat_id,
+                    retry_after.duration()
+                );
+                self.pause(retry_after.duration()).await;
+                self.wait_turn().await;
+                request().await?;
+                Ok(Delivery::Deferred)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Like `send`, but keeps backing off while Telegram answers with RetryAfter, as long
+    /// as `budget` allows. Meant for bulk sends, where one budget is shared by the batch.
+    pub async fn send_within(
+        &self,
+        chat_id: ChatId,
+        text: String,
+        keyboard: Option<InlineKeyboardMarkup>,
+        budget: &BackoffBudget,
+    ) -> Result<Delivery, RequestError> {
+        let mut delivery = Delivery::Sent;
+        loop {
+            match self
+                .send_with(chat_id, text.clone(), keyboard.clone())
+                .await
+            {
+                Err(RequestError::RetryAfter(retry_after))
+                    if budget.try_take(retry_after.duration()) =>
+                {
+                    warn!(
+                        "Still rate limited, pausing all sends for {:?}",
+                        retry_after.duration()
+                    );
+                    self.pause(retry_after.duration()).await;
+                    delivery = Delivery::Deferred;
+                }
+                Ok(Delivery::Sent) => return Ok(delivery),
+                result => return result,
+            }
+        }
+    }
+
+    /// Holds back every send until `duration` from now.
+    async fn pause(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut paused_until = self.paused_until.lock().await;
+        if paused_until.is_none_or(|current| current < until) {
+            *paused_until = Some(until);
+        }
+    }
+
+    async fn wait_turn(&self) {
+        let paused_until = *self.paused_until.lock().await;
+        if let Some(until) = paused_until {
+            sleep_until(until).await;
+        }
+        self.gate.lock().await.tick().await;
+    }
+}
+
+/// Total time a batch of sends may spend backing off, so a pathological stream of
+/// RetryAfter answers can't stall it indefinitely.
+pub struct BackoffBudget {
+    remaining_ms: AtomicU64,
+}
+
+impl BackoffBudget {
+    pub fn new(total: Duration) -> Self {
+        BackoffBudget {
+            remaining_ms: AtomicU64::new(total.as_millis() as u64),
+        }
+    }
+
+    /// Deducts `wait` and returns true if enough budget was left for it.
+    pub fn try_take(&self, wait: Duration) -> bool {
+        let wait = wait.as_millis() as u64;
+        self.remaining_ms
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+                remaining.checked_sub(wait)
+            })
+            .is_ok()
+    }
+}
+
+/// Longest text Telegram accepts in one message. Telegram counts UTF-16 code units, so
+/// most emoji take up two.
+pub const MAX_MESSAGE_CHARS: usize = 4096;
+
+/// Length of `text` the way Telegram counts it, in UTF-16 code units.
+fn telegram_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Splits `text` into messages of at most `max_units` UTF-16 code units, breaking between
+/// lines. Only a single line that is too long on its own gets cut in the middle.
+pub fn split_message(text: &str, max_units: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_units = 0;
+    for line in text.split('\n') {
+        let mut line = line;
+        let mut line_units = telegram_len(line);
+        // Room for the line plus the newline joining it to the chunk
+        if current_units > 0 && current_units + 1 + line_units > max_units {
+            chunks.push(std::mem::take(&mut current));
+            current_units = 0;
+        }
+        while line_units > max_units {
+            // Cut after the last character that still fits, but always after at least one
+            let mut units = 0;
+            let cut = line
+                .char_indices()
+                .find(|(i, c)| {
+                    units += c.len_utf16();
+                    units > max_units && *i > 0
+                })
+                .map_or(line.len(), |(i, _)| i);
+            chunks.push(line[..cut].to_string());
+            line = &line[cut..];
+            line_units = telegram_len(line);
+        }
+        if current_units > 0 {
+            current.push('\n');
+            current_units += 1;
+        }
+        current.push_str(line);
+        current_units += line_units;
+    }
+    if !current.is_empty() || chunks.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Whether the error means the chat will never accept messages again, in which case
+/// the user's data should be removed.
+pub fn is_unreachable(error: &RequestError) -> bool {
+    matches!(
+        error,
+        RequestError::Api(teloxide::ApiError::BotBlocked | teloxide::ApiError::UserDeactivated)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_budget() {
+        let budget = BackoffBudget::new(Duration::from_secs(60));
+        assert!(budget.try_take(Duration::from_secs(40)));
+        // Not enough left, and a refused wait doesn't use anything up
+        assert!(!budget.try_take(Duration::from_secs(30)));
+        assert!(budget.try_take(Duration::from_secs(20)));
+        assert!(!budget.try_take(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_split_message() {
+        let lines: Vec<String> = (0..500)
+            .map(|i| {
+                format!(
+                    "• Mon, {:02}.06. at Location {}: 🟤 Bio, ⚫ Rest",
+                    i % 30,
+                    i
+                )
+            })
+            .collect();
+        let text = lines.join("\n");
+        assert!(text.chars().count() > MAX_MESSAGE_CHARS);
+
+        let chunks = split_message(&text, MAX_MESSAGE_CHARS);
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(chunk.chars().count() <= MAX_MESSAGE_CHARS);
+        }
+        // Every line survives whole and in order
+        let rejoined: Vec<&str> = chunks.iter().flat_map(|c| c.split('\n')).collect();
+        assert_eq!(rejoined, lines);
+
+        assert_eq!(split_message("short", MAX_MESSAGE_CHARS), vec!["short"]);
+        // A line too long on its own is cut at the limit
+        assert_eq!(split_message("äbcdefg\nh", 3), vec!["äbc", "def", "g\nh"]);
+    }
+
+    #[test]
+    fn test_split_message_counts_utf16() {
+        // Each line is 8 characters but 12 UTF-16 units, as Telegram counts them
+        let line = "🟤🔵🗓📍 Bio";
+        assert_eq!(telegram_len(line), 12);
+        let text = vec![line; 1000].join("\n");
+        let chunks = split_message(&text, MAX_MESSAGE_CHARS);
+        for chunk in &chunks {
+            assert!(telegram_len(chunk) <= MAX_MESSAGE_CHARS);
+        }
+        assert_eq!(chunks.join("\n"), text);
+
+        // Emoji are never split in half when a line is cut
+        assert_eq!(split_message("🟤🟤🟤", 3), vec!["🟤", "🟤", "🟤"]);
+        assert_eq!(split_message("🟤a🟤", 1), vec!["🟤", "a", "🟤"]);
+    }
+}
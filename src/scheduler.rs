@@ -1,21 +1,112 @@
-use crate::store;
-use crate::waste::parse_ical;
-use anyhow::Result;
-use chrono::{Datelike, Duration, Local, Timelike};
+use crate::config;
+use crate::i18n::{self, t, tf, Key};
+use crate::metrics;
+use crate::notifier::{self, BackoffBudget, Delivery, Notifier, MAX_MESSAGE_CHARS};
+use crate::store::{self, NotificationTask, NotifyMode, NotifySlot, UpcomingEvent};
+use crate::waste::{find_pickup_gaps, looks_like_html, parse_ical, Calendar, WasteType};
+use anyhow::{bail, Result};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, Timelike};
 use futures::stream::StreamExt;
-use log::{error, info};
-use sqlx::{Row, SqlitePool};
-use std::sync::Arc;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+use teloxide::RequestError;
+use thiserror::Error;
 use tokio_cron_scheduler::{Job, JobScheduler};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{debug, error, info, info_span, instrument, warn, Instrument};
 
 // Constants
-// const ICAL_UPDATE_INTERVAL_DAYS: i64 = 28; // Every 4 weeks
+/// Days between iCal refreshes (`ICAL_UPDATE_INTERVAL_DAYS`). Every 4 weeks.
+const DEFAULT_ICAL_UPDATE_INTERVAL_DAYS: i64 = 28;
+/// How far ahead each iCal fetch looks (`ICAL_WINDOW_DAYS`). About 3 months.
+const DEFAULT_ICAL_WINDOW_DAYS: i64 = 90;
+/// How many hours back `run_catchup` looks for missed notification slots.
+const DEFAULT_CATCHUP_WINDOW_HOURS: u32 = 4;
+/// How many locations `update_all_icals` fetches in parallel (`ICAL_FETCH_CONCURRENCY`).
+const DEFAULT_ICAL_FETCH_CONCURRENCY: usize = 5;
+/// Extra attempts for a failing location fetch before it counts as failed.
+const ICAL_FETCH_RETRIES: u32 = 3;
+const ICAL_RETRY_BASE_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+/// Latest the scheduled refresh starts after 04:00, in minutes (`ICAL_JITTER_MINUTES`).
+/// 0 starts it right away.
+const DEFAULT_ICAL_JITTER_MINUTES: u64 = 120;
+/// Longest random pause before each location's fetch, on top of the fixed one after it.
+const ICAL_FETCH_JITTER: std::time::Duration = std::time::Duration::from_secs(2);
 
-pub async fn run_scheduler(bot: Bot, pool: SqlitePool) {
+/// Feed URL with `{location}`, `{start}` and `{end}` placeholders (`ICAL_URL_TEMPLATE`).
+/// Dates are filled in as `dd.mm.YYYY`.
+const DEFAULT_ICAL_URL_TEMPLATE: &str =
+    "https://stadtplan.dresden.de/project/cardo3Apps/IDU_DDStadtplan/abfall/ical.ashx\
+     ?STANDORT={location}&DATUM_VON={start}&DATUM_BIS={end}";
+
+/// iCal refresh settings, read from the environment once.
+#[derive(Debug)]
+pub struct IcalConfig {
+    pub update_interval_days: i64,
+    pub window_days: i64,
+    /// Upper bound of the random delay before a scheduled refresh.
+    pub jitter: std::time::Duration,
+    pub url_template: String,
+    /// Tried when the main URL doesn't know a location (`ICAL_FALLBACK_URL_TEMPLATE`).
+    pub fallback_url_template: Option<String>,
+}
+
+pub fn ical_config() -> &'static IcalConfig {
+    static CONFIG: OnceLock<IcalConfig> = OnceLock::new();
+    CONFIG.get_or_init(|| IcalConfig {
+        update_interval_days: config::positive_from_env(
+            "ICAL_UPDATE_INTERVAL_DAYS",
+            DEFAULT_ICAL_UPDATE_INTERVAL_DAYS,
+        ),
+        window_days: config::positive_from_env("ICAL_WINDOW_DAYS", DEFAULT_ICAL_WINDOW_DAYS),
+        jitter: std::time::Duration::from_secs(
+            60 * config::non_negative_from_env("ICAL_JITTER_MINUTES", DEFAULT_ICAL_JITTER_MINUTES),
+        ),
+        url_template: ical_url_template(),
+        fallback_url_template: std::env::var("ICAL_FALLBACK_URL_TEMPLATE")
+            .ok()
+            .map(|template| template.trim().to_string())
+            .filter(|template| template.contains("{location}")),
+    })
+}
+
+fn ical_url_template() -> String {
+    match std::env::var("ICAL_URL_TEMPLATE") {
+        Ok(template) if template.contains("{location}") => template.trim().to_string(),
+        Ok(template) => {
+            warn!(
+                "ICAL_URL_TEMPLATE {:?} has no {{location}} placeholder; using the default.",
+                template
+            );
+            DEFAULT_ICAL_URL_TEMPLATE.to_string()
+        }
+        Err(_) => DEFAULT_ICAL_URL_TEMPLATE.to_string(),
+    }
+}
+
+/// Fills in a feed URL template for one location and date range.
+fn ical_url(template: &str, location: &str, start: NaiveDate, end: NaiveDate) -> String {
+    template
+        .replace("{location}", location)
+        .replace("{start}", &start.format("%d.%m.%Y").to_string())
+        .replace("{end}", &end.format("%d.%m.%Y").to_string())
+}
+
+/// Runs the notification and iCal jobs until `shutdown` is cancelled.
+///
+/// On shutdown no new job runs are started, and runs already in progress
+/// (e.g. an iCal upsert) are awaited before returning.
+pub async fn run_scheduler(notifier: Arc<Notifier>, pool: SqlitePool, shutdown: CancellationToken) {
     let pool = Arc::new(pool);
+    // Every job run is tracked so shutdown can wait for in-flight work.
+    let tracker = TaskTracker::new();
     // Handle error instead of unwrap
-    let sched = match JobScheduler::new().await {
+    let mut sched = match JobScheduler::new().await {
         Ok(s) => s,
         Err(e) => {
             error!("Failed to create JobScheduler: {:?}", e);
@@ -24,216 +115,1591 @@ pub async fn run_scheduler(bot: Bot, pool: SqlitePool) {
     };
 
     // Spawn Notification Task
-    // Schedule: Every hour at minute 0: "0 0 * * * *"
+    // Schedule: Every minute at second 0: "0 * * * * *"
     // This cron expression might depend on the crate's parser.
     // tokio-cron-scheduler uses `cron` crate.
     // sec, min, hour, day of month, month, day of week, year (optional)
-    let bot_clone = bot.clone();
+    let notifier_clone = notifier.clone();
     let pool_clone = pool.clone();
+    let tracker_clone = tracker.clone();
+    // Slots missed while the bot was offline, sent by `run_catchup` once the jobs are set up
+    let catchup_at = Local::now().naive_local();
+    let window = config::positive_from_env("CATCHUP_WINDOW_HOURS", DEFAULT_CATCHUP_WINDOW_HOURS);
+    let catchup = catchup_slots(catchup_at.hour(), window);
+    // When each slot was last dispatched, so a slot fires once even if ticks run late.
+    // Starts with the catch-up slots so the first tick doesn't send the current hour again.
+    let fired = Arc::new(Mutex::new(fired_after_catchup(&catchup, catchup_at)));
 
-    // Notifications run every hour
-    let notification_job = Job::new_async("0 0 * * * *", move |_uuid, _l| {
-        let bot = bot_clone.clone();
+    // Notifications are checked every minute, so any stored notify_time gets its turn
+    let notification_job = Job::new_async("0 * * * * *", move |_uuid, _l| {
+        let notifier = notifier_clone.clone();
         let pool = pool_clone.clone();
-        Box::pin(async move {
-            let now = Local::now();
-            let hour = now.hour();
-            let time_str = format!("{:02}:00", hour);
-            if let Err(e) = dispatch_notifications(&bot, &pool, &time_str).await {
-                error!("Error dispatching {} notifications: {:?}", time_str, e);
+        let tracker = tracker_clone.clone();
+        let fired = fired.clone();
+        Box::pin(tracker.track_future(async move {
+            let now = Local::now().naive_local();
+            let times = match store::get_notify_times(&pool).await {
+                Ok(times) => times,
+                Err(e) => {
+                    error!("Error loading notify times: {:?}", e);
+                    Vec::new()
+                }
+            };
+            // Hourly, tracked in `fired` like the slots so a late tick still retries
+            let retry_slot = NotifySlot::on_the_hour(now.hour());
+            let last_retry = fired.lock().unwrap().get(RETRY_FIRED_KEY).copied();
+            if should_fire(retry_slot, now, last_retry) {
+                fired
+                    .lock()
+                    .unwrap()
+                    .insert(RETRY_FIRED_KEY.to_string(), now);
+                if let Err(e) = retry_failed_notifications(&notifier, &pool).await {
+                    error!("Error retrying failed notifications: {:?}", e);
+                }
             }
-        })
-    }).expect("Failed to create notification job");
+            let due = should_dispatch(now, &times, &fired.lock().unwrap());
+            for time_str in due {
+                fired.lock().unwrap().insert(time_str.clone(), now);
+                if let Err(e) = dispatch_notifications(&notifier, &pool, &time_str).await {
+                    error!("Error dispatching {} notifications: {:?}", time_str, e);
+                }
+            }
+            fired
+                .lock()
+                .unwrap()
+                .retain(|_, at| now - *at < Duration::days(1));
+        }))
+    })
+    .expect("Failed to create notification job");
+
+    sched
+        .add(notification_job)
+        .await
+        .expect("Failed to add notification job");
+
+    // Snoozed reminders are checked every minute
+    let notifier_clone = notifier.clone();
+    let pool_clone_snooze = pool.clone();
+    let tracker_clone = tracker.clone();
+    let snooze_job = Job::new_async("0 * * * * *", move |_uuid, _l| {
+        let notifier = notifier_clone.clone();
+        let pool = pool_clone_snooze.clone();
+        let tracker = tracker_clone.clone();
+        Box::pin(tracker.track_future(async move {
+            if let Err(e) = send_due_snoozes(&notifier, &pool).await {
+                error!("Error sending snoozed reminders: {:?}", e);
+            }
+        }))
+    })
+    .expect("Failed to create snooze job");
+
+    sched
+        .add(snooze_job)
+        .await
+        .expect("Failed to add snooze job");
+
+    // Daily maintenance at 3 AM, before the iCal refresh
+    let pool_clone_prune = pool.clone();
+    let tracker_clone = tracker.clone();
+    let prune_job = Job::new_async("0 0 3 * * *", move |_uuid, _l| {
+        let pool = pool_clone_prune.clone();
+        let tracker = tracker_clone.clone();
+        Box::pin(tracker.track_future(async move {
+            match store::prune_old_events(&pool).await {
+                Ok(removed) => info!("Pruned {} old pickup events.", removed),
+                Err(e) => error!("Error pruning pickup events: {:?}", e),
+            }
+        }))
+    })
+    .expect("Failed to create prune job");
+
+    sched.add(prune_job).await.expect("Failed to add prune job");
 
-    sched.add(notification_job).await.expect("Failed to add notification job");
+    // Weekly digest for users who chose it over per-pickup reminders
+    let (digest_weekday, digest_hour) = config::digest_schedule();
+    info!("Weekly digest: {} at {:02}:00", digest_weekday, digest_hour);
+    let notifier_clone = notifier.clone();
+    let pool_clone_digest = pool.clone();
+    let tracker_clone = tracker.clone();
+    // DIGEST_HOUR is local time; plain cron jobs run on UTC
+    let digest_cron = format!("0 0 {} * * {}", digest_hour, digest_weekday);
+    let digest_job = Job::new_async_tz(digest_cron.as_str(), Local, move |_uuid, _l| {
+        let notifier = notifier_clone.clone();
+        let pool = pool_clone_digest.clone();
+        let tracker = tracker_clone.clone();
+        Box::pin(tracker.track_future(async move {
+            let today = Local::now().date_naive();
+            if let Err(e) = dispatch_digests(&notifier, &pool, today).await {
+                error!("Error dispatching weekly digests: {:?}", e);
+            }
+            if let Err(e) = dispatch_quiet_week_notices(&notifier, &pool, today).await {
+                error!("Error sending quiet week notices: {:?}", e);
+            }
+        }))
+    })
+    .expect("Failed to create digest job");
+
+    sched
+        .add(digest_job)
+        .await
+        .expect("Failed to add digest job");
 
     // Spawn iCal Update Task
-    // Run once a month on the first Saturday at 4 AM.
-    // Cron: "0 0 4 * * Sat" (Every Saturday at 4 AM)
-    // Check inside: if day of month <= 7.
+    // Runs daily at 4 AM and refreshes once `update_interval_days` have passed
+    // since the last successful run, after a random delay so the upstream API doesn't
+    // see every instance at the same minute.
+    let ical = ical_config();
+    info!(
+        "iCal update interval: {} days, fetch window: {} days, start jitter up to {} min",
+        ical.update_interval_days,
+        ical.window_days,
+        ical.jitter.as_secs() / 60
+    );
+    let last_ical_update = Arc::new(Mutex::new(None::<NaiveDate>));
+
+    let notifier_clone = notifier.clone();
     let pool_clone_ical = pool.clone();
-    let ical_job = Job::new_async("0 0 4 * * Sat", move |_uuid, _l| {
+    let last_ical_update_job = last_ical_update.clone();
+    let tracker_clone = tracker.clone();
+    let shutdown_clone = shutdown.clone();
+    let ical_job = Job::new_async("0 0 4 * * *", move |_uuid, _l| {
+        let notifier = notifier_clone.clone();
         let pool = pool_clone_ical.clone();
-        Box::pin(async move {
-            let now = Local::now();
-            if now.day() > 7 {
+        let last_update = last_ical_update_job.clone();
+        let tracker = tracker_clone.clone();
+        let shutdown = shutdown_clone.clone();
+        Box::pin(tracker.track_future(async move {
+            let today = Local::now().date_naive();
+            let due = match *last_update.lock().unwrap() {
+                Some(last) => (today - last).num_days() >= ical_config().update_interval_days,
+                None => true,
+            };
+            if !due {
                 return;
             }
-            if let Err(e) = update_all_icals(&pool).await {
-                error!("Error updating iCals: {:?}", e);
+            let delay = jitter(ical_config().jitter);
+            info!("iCal update due, starting in {} min", delay.as_secs() / 60);
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = shutdown.cancelled() => return,
             }
-        })
-    }).expect("Failed to create iCal job");
+            match update_all_icals(&notifier, &pool).await {
+                Ok(_) => *last_update.lock().unwrap() = Some(today),
+                Err(e) => error!("Error updating iCals: {:?}", e),
+            }
+        }))
+    })
+    .expect("Failed to create iCal job");
 
     sched.add(ical_job).await.expect("Failed to add iCal job");
 
     // Run iCal update immediately on startup (asynchronously)
+    let notifier_clone = notifier.clone();
     let pool_clone_startup = pool.clone();
-    tokio::spawn(async move {
-         if let Err(e) = update_all_icals(&pool_clone_startup).await {
-            error!("Error performing startup iCal update: {:?}", e);
+    tracker.spawn(async move {
+        match update_all_icals(&notifier_clone, &pool_clone_startup).await {
+            Ok(_) => *last_ical_update.lock().unwrap() = Some(Local::now().date_naive()),
+            Err(e) => error!("Error performing startup iCal update: {:?}", e),
         }
     });
 
+    // Deliver anything missed while the bot was offline
+    tracker.spawn(run_catchup(notifier.clone(), (*pool).clone(), catchup));
+
     if let Err(e) = sched.start().await {
         error!("Error starting scheduler: {:?}", e);
     }
 
-    // Keep the scheduler running. The main loop in main.rs keeps the process alive,
-    // but run_scheduler was previously spawned and expected to run forever.
-    // Since sched.start() runs in background, we need to wait here or let the task finish
-    // but the scheduler lives in `sched`.
-    // Actually, `sched` will be dropped if we exit this function unless we keep it alive.
-    // But `JobScheduler` spawns tasks.
-    // However, the `sched` struct itself might need to be held?
-    // Looking at docs: "The scheduler must be kept alive".
+    // The scheduler must be kept alive, so park here until shutdown is requested.
+    shutdown.cancelled().await;
+    info!("Scheduler stopping...");
 
-    // So we will just park here.
-    if let Err(e) = tokio::signal::ctrl_c().await {
-        error!("Error waiting for ctrl_c: {:?}", e);
+    if let Err(e) = sched.shutdown().await {
+        error!("Error shutting down scheduler: {:?}", e);
     }
-    info!("Scheduler stopping...");
+
+    // Let in-flight notification and iCal runs finish before the pool is closed.
+    tracker.close();
+    tracker.wait().await;
+    info!("Scheduler stopped.");
 }
 
-async fn dispatch_notifications(bot: &Bot, pool: &SqlitePool, time: &str) -> Result<()> {
+/// Sends notifications for slots that were missed while the bot was offline.
+///
+/// `slots` are today's hourly slots within the catch-up window (`CATCHUP_WINDOW_HOURS`,
+/// default 4), see `catchup_slots`. Anything already delivered is skipped via
+/// `notified_log`, so this is safe to run on every startup.
+pub async fn run_catchup(notifier: Arc<Notifier>, pool: SqlitePool, slots: Vec<String>) {
+    info!("Running notification catch-up for slots: {:?}", slots);
+
+    for slot in slots {
+        if let Err(e) = dispatch_notifications(&notifier, &pool, &slot).await {
+            error!("Error dispatching catch-up for {}: {:?}", slot, e);
+        }
+    }
+}
+
+/// How many minutes late a tick may run and still dispatch the slot it was meant for.
+const LATE_TICK_GRACE_MINUTES: i64 = 5;
+
+/// Entry in the notification job's `fired` map for the hourly retry of failed
+/// notifications. Not a time, so it can't clash with a slot.
+const RETRY_FIRED_KEY: &str = "retry";
+
+/// Whether `slot` is due at `now`: it came up today no more than
+/// `LATE_TICK_GRACE_MINUTES` ago, and wasn't dispatched since (`last_sent`).
+pub fn should_fire(slot: NotifySlot, now: NaiveDateTime, last_sent: Option<NaiveDateTime>) -> bool {
+    let due = now.date().and_time(slot.time());
+    let late = now - due;
+    late >= Duration::zero()
+        && late < Duration::minutes(LATE_TICK_GRACE_MINUTES)
+        && last_sent.is_none_or(|sent| sent < due)
+}
+
+/// The notification slots to dispatch at `now`: every one of the stored `times` that is
+/// due, and the current hourly slot, which custom lead times count from. `fired` holds
+/// when each slot was last dispatched.
+///
+/// Times are compared as clock times, so a hand-edited "7:30" still fires at 07:30; the
+/// slot is returned as stored because the query matches it literally.
+fn should_dispatch(
+    now: NaiveDateTime,
+    times: &[String],
+    fired: &HashMap<String, NaiveDateTime>,
+) -> Vec<String> {
+    let is_due = |time: &String, slot| should_fire(slot, now, fired.get(time).copied());
+    let mut due: Vec<String> = times
+        .iter()
+        .filter(|time| {
+            time.parse::<NotifySlot>()
+                .is_ok_and(|slot| is_due(time, slot))
+        })
+        .cloned()
+        .collect();
+    let hourly = NotifySlot::on_the_hour(now.hour());
+    if !due.contains(&hourly.to_db()) && is_due(&hourly.to_db(), hourly) {
+        due.push(hourly.to_db());
+    }
+    due
+}
+
+/// The notification job's initial `fired` map: every catch-up slot counts as dispatched
+/// at `at`, so a tick within the grace window doesn't send it a second time.
+fn fired_after_catchup(slots: &[String], at: NaiveDateTime) -> HashMap<String, NaiveDateTime> {
+    slots.iter().map(|slot| (slot.clone(), at)).collect()
+}
+
+/// Returns today's hourly slots from `window` hours ago up to and including `hour`.
+/// Slots never wrap past midnight, since yesterday's date-based lookups no longer apply.
+fn catchup_slots(hour: u32, window: u32) -> Vec<String> {
+    (hour.saturating_sub(window)..=hour)
+        .map(|h| NotifySlot::on_the_hour(h).to_db())
+        .collect()
+}
+
+#[instrument(skip(notifier, pool))]
+async fn dispatch_notifications(notifier: &Notifier, pool: &SqlitePool, time: &str) -> Result<()> {
     info!("Dispatching notifications for time: {}", time);
     let today = Local::now().date_naive();
     let tomorrow = today + Duration::days(1);
 
-    let today_str = today.format("%Y-%m-%d").to_string();
-    let tomorrow_str = tomorrow.format("%Y-%m-%d").to_string();
+    let tasks = store::get_users_to_notify(pool, time, today, tomorrow).await?;
+    let counts = deliver(notifier, pool, tasks).await;
+
+    info!(
+        "Notifications for {}: {} sent, {} deferred by rate limiting, {} failed.",
+        time, counts.sent, counts.deferred, counts.failed
+    );
+    Ok(())
+}
+
+/// Reads the slot argument of /testnotify: an hour ("18") or a time ("7:30"), returned
+/// as the `HH:MM` slot `get_users_to_notify` matches.
+pub fn parse_slot(arg: &str) -> Option<String> {
+    arg.parse::<NotifySlot>().ok().map(NotifySlot::to_db)
+}
 
-    let tasks = store::get_users_to_notify(pool, time, &today_str, &tomorrow_str).await?;
+/// Runs the notification pipeline for `time` today like `dispatch_notifications`, but
+/// sends every message to `admin` with its intended recipient noted. Nothing is logged
+/// as delivered and no snooze button is attached, so real users are unaffected.
+/// Returns how many messages were redirected.
+#[instrument(skip(notifier, pool, lang))]
+pub async fn test_dispatch(
+    notifier: &Notifier,
+    pool: &SqlitePool,
+    time: &str,
+    admin: ChatId,
+    lang: i18n::Lang,
+) -> Result<usize> {
+    let today = Local::now().date_naive();
+    let tomorrow = today + Duration::days(1);
 
+    let tasks = store::get_users_to_notify(pool, time, today, tomorrow).await?;
+    let groups = group_by_chat(tasks);
+    warn!(
+        "TEST RUN of slot {}: redirecting {} notifications to the admin chat {}",
+        time,
+        groups.len(),
+        admin
+    );
+
+    let budget = BackoffBudget::new(notifier::MAX_BATCH_BACKOFF);
+    let mut sent = 0;
+    for (chat, tasks) in groups {
+        let text = format!(
+            "{}\n\n{}",
+            tf(Key::TestNotifyRecipient, lang, &[&chat]),
+            format_notification(&tasks)
+        );
+        match notifier.send_within(admin, text, None, &budget).await {
+            Ok(_) => sent += 1,
+            Err(e) => error!("Failed to send test notification for {}: {:?}", chat, e),
+        }
+    }
+    Ok(sent)
+}
+
+/// Sends again what failed for a transient reason on an earlier tick.
+#[instrument(skip(notifier, pool))]
+async fn retry_failed_notifications(notifier: &Notifier, pool: &SqlitePool) -> Result<()> {
+    let tasks = store::get_failed_notifications(pool, Local::now().date_naive()).await?;
+    if tasks.is_empty() {
+        return Ok(());
+    }
+    let counts = deliver(notifier, pool, tasks).await;
+
+    info!(
+        "Retried notifications: {} sent, {} deferred by rate limiting, {} failed.",
+        counts.sent, counts.deferred, counts.failed
+    );
+    Ok(())
+}
+
+/// How many of a batch's messages went out.
+#[derive(Debug, Default)]
+struct DeliveryCounts {
+    sent: usize,
+    deferred: usize,
+    failed: usize,
+}
+
+/// Sends one message per chat for `tasks` and settles each result.
+async fn deliver(
+    notifier: &Notifier,
+    pool: &SqlitePool,
+    tasks: Vec<NotificationTask>,
+) -> DeliveryCounts {
     // Optimization: Send notifications in parallel with a concurrency limit.
     // This prevents one slow request from blocking others and speeds up the overall process.
-    // Telegram broadcasting limit is ~30 messages/second.
-    // A concurrency of 15 is a safe heuristic: even with fast network (200ms RTT),
-    // 15 req / 0.2s = 75 req/s (burst). But sustained average with processing overhead should be safer.
-    // To be strictly safe without a complex rate limiter, we keep this conservative.
-    futures::stream::iter(tasks)
-        .for_each_concurrent(15, |task| async move {
-            let chat_id = ChatId(task.chat_id);
+    // Telegram broadcasting limit is ~30 messages/second; the Notifier paces the actual
+    // sends, so concurrency only hides network latency.
+    let sent = AtomicUsize::new(0);
+    let deferred = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+    let (sent_ref, deferred_ref, failed_ref) = (&sent, &deferred, &failed);
+    let budget = &BackoffBudget::new(notifier::MAX_BATCH_BACKOFF);
+    // Snoozing only makes sense while the snooze time is still ahead
+    let snooze_offered = Local::now().hour() < SNOOZE_HOUR;
+
+    futures::stream::iter(group_by_chat(tasks))
+        .for_each_concurrent(15, |(chat, tasks)| {
+            async move {
+                let message = format_notification(&tasks);
+                let keyboard = snooze_offered.then(|| snooze_keyboard(&tasks)).flatten();
 
+                match notifier
+                    .send_within(ChatId(chat), message, keyboard, budget)
+                    .await
+                {
+                    // Nothing was delivered, so leave the log untouched and the run repeatable
+                    Ok(_) if notifier.is_dry_run() => {
+                        sent_ref.fetch_add(1, Ordering::Relaxed);
+                    }
+                    result => match settle_delivery(pool, chat, &tasks, result).await {
+                        Settled::Delivered(delivery) => {
+                            metrics::notification_sent();
+                            match delivery {
+                                Delivery::Sent => sent_ref.fetch_add(1, Ordering::Relaxed),
+                                Delivery::Deferred => deferred_ref.fetch_add(1, Ordering::Relaxed),
+                            };
+                        }
+                        Settled::Retrying | Settled::GaveUp | Settled::Removed => {
+                            failed_ref.fetch_add(1, Ordering::Relaxed);
+                            metrics::notification_failed();
+                        }
+                    },
+                }
+            }
+            .instrument(info_span!("notify", chat_id = chat))
+        })
+        .await;
+
+    DeliveryCounts {
+        sent: sent.into_inner(),
+        deferred: deferred.into_inner(),
+        failed: failed.into_inner(),
+    }
+}
+
+/// Sends that fail this often for transient reasons are dropped.
+pub const MAX_SEND_ATTEMPTS: i64 = 3;
+
+/// What became of one chat's notification.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Settled {
+    Delivered(Delivery),
+    /// Failed, queued for the next tick.
+    Retrying,
+    /// Failed `MAX_SEND_ATTEMPTS` times and was dropped.
+    GaveUp,
+    /// The chat blocked the bot or is gone, so the user was deleted.
+    Removed,
+}
+
+/// Records the result of sending `tasks` to `chat`: delivered tasks are logged, transient
+/// failures queued for a retry, and users who can't be reached anymore deleted.
+pub(crate) async fn settle_delivery(
+    pool: &SqlitePool,
+    chat: i64,
+    tasks: &[NotificationTask],
+    result: Result<Delivery, RequestError>,
+) -> Settled {
+    match result {
+        Ok(delivery) => {
+            for task in tasks {
+                let recorded = async {
+                    store::record_notification(
+                        pool,
+                        task.chat_id,
+                        &task.location_id,
+                        &task.waste_type,
+                        task.event_date,
+                        &task.slot,
+                    )
+                    .await?;
+                    store::clear_failed_notification(pool, task).await
+                };
+                if let Err(e) = recorded.await {
+                    error!("Failed to record notification for {}: {:?}", chat, e);
+                }
+            }
+            Settled::Delivered(delivery)
+        }
+        Err(e) if notifier::is_unreachable(&e) => {
+            info!("User {} blocked bot or is deactivated. Removing...", chat);
+            let _ = store::delete_user(pool, chat).await;
+            Settled::Removed
+        }
+        Err(e) => {
+            error!("Failed to send notification to {}: {:?}", chat, e);
+            let mut settled = Settled::Retrying;
+            for task in tasks {
+                let queued = async {
+                    let attempts =
+                        store::record_failed_notification(pool, task, &e.to_string()).await?;
+                    if attempts >= MAX_SEND_ATTEMPTS {
+                        store::clear_failed_notification(pool, task).await?;
+                        return anyhow::Ok(true);
+                    }
+                    Ok(false)
+                };
+                match queued.await {
+                    Ok(true) => settled = Settled::GaveUp,
+                    Ok(false) => {}
+                    Err(e) => error!("Failed to queue notification for {}: {:?}", chat, e),
+                }
+            }
+            if settled == Settled::GaveUp {
+                warn!(
+                    "Giving up on notification to {} after {} attempts",
+                    chat, MAX_SEND_ATTEMPTS
+                );
+            }
+            settled
+        }
+    }
+}
+
+/// Sends the weekly digest to every user in digest mode: their pickups of the next
+/// seven days in one message. Users without pickups in that week get nothing.
+#[instrument(skip(notifier, pool))]
+async fn dispatch_digests(notifier: &Notifier, pool: &SqlitePool, today: NaiveDate) -> Result<()> {
+    let events = store::get_events_in_range(
+        pool,
+        NotifyMode::WeeklyDigest,
+        today + Duration::days(1),
+        today + Duration::days(7),
+    )
+    .await?;
+
+    let mut digests: Vec<(i64, Vec<UpcomingEvent>)> = Vec::new();
+    for event in events {
+        match digests.iter_mut().find(|(chat, _)| *chat == event.chat_id) {
+            Some((_, group)) => group.push(event),
+            None => digests.push((event.chat_id, vec![event])),
+        }
+    }
+
+    let sent = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+    let (sent_ref, failed_ref) = (&sent, &failed);
+    let budget = &BackoffBudget::new(notifier::MAX_BATCH_BACKOFF);
+
+    futures::stream::iter(digests)
+        .for_each_concurrent(15, |(chat, events)| {
+            async move {
+                let mut result = Ok(Delivery::Sent);
+                // A busy week at several locations can outgrow a single message
+                for chunk in notifier::split_message(&format_digest(&events), MAX_MESSAGE_CHARS) {
+                    result = notifier
+                        .send_within(ChatId(chat), chunk, None, budget)
+                        .await;
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                match result {
+                    Ok(_) => {
+                        sent_ref.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        failed_ref.fetch_add(1, Ordering::Relaxed);
+                        error!("Failed to send weekly digest to {}: {:?}", chat, e);
+                        if notifier::is_unreachable(&e) {
+                            info!("User {} blocked bot or is deactivated. Removing...", chat);
+                            let _ = store::delete_user(pool, chat).await;
+                        }
+                    }
+                }
+            }
+            .instrument(info_span!("digest", chat_id = chat))
+        })
+        .await;
+
+    info!(
+        "Weekly digests: {} sent, {} failed.",
+        sent.into_inner(),
+        failed.into_inner()
+    );
+    Ok(())
+}
+
+/// Tells users who opted in when the week the digest covers has no pickups for them.
+#[instrument(skip(notifier, pool))]
+async fn dispatch_quiet_week_notices(
+    notifier: &Notifier,
+    pool: &SqlitePool,
+    today: NaiveDate,
+) -> Result<()> {
+    let users =
+        store::get_quiet_week_users(pool, today + Duration::days(1), today + Duration::days(7))
+            .await?;
+    let budget = &BackoffBudget::new(notifier::MAX_BATCH_BACKOFF);
+    let mut sent = 0;
+    for (chat, lang) in users {
+        let text = t(Key::QuietWeek, lang).to_string();
+        match notifier.send_within(ChatId(chat), text, None, budget).await {
+            Ok(_) => sent += 1,
+            Err(e) => {
+                error!("Failed to send quiet week notice to {}: {:?}", chat, e);
+                if notifier::is_unreachable(&e) {
+                    let _ = store::delete_user(pool, chat).await;
+                }
+            }
+        }
+    }
+    info!("Quiet week notices: {} sent.", sent);
+    Ok(())
+}
+
+/// Renders one chat's week, with a line per day and location, e.g.
+/// "• Mon, 10.06. at Home: 🟤 Bio, ⚫ Rest".
+fn format_digest(events: &[UpcomingEvent]) -> String {
+    let lang = events
+        .first()
+        .map(|event| event.language)
+        .unwrap_or_default();
+    let mut lines: Vec<(&UpcomingEvent, Vec<String>)> = Vec::new();
+    for event in events {
+        let waste: WasteType = event
+            .waste_type
+            .parse()
+            .expect("WasteType parsing is infallible");
+        match lines
+            .iter_mut()
+            .find(|(first, _)| first.location_id == event.location_id && first.date == event.date)
+        {
+            Some((_, labels)) => labels.push(waste.label()),
+            None => lines.push((event, vec![waste.label()])),
+        }
+    }
+
+    let mut message = t(Key::DigestHeader, lang).to_string();
+    for (event, labels) in lines {
+        let loc_label = event
+            .location_alias
+            .as_deref()
+            .unwrap_or(&event.location_id);
+        message.push('\n');
+        message.push_str(&tf(
+            Key::DigestLine,
+            lang,
+            &[&lang.format_day(event.date), &loc_label, &labels.join(", ")],
+        ));
+    }
+    message
+}
+
+/// Outcome of an `update_all_icals` run.
+#[derive(Debug, Default)]
+pub struct IcalUpdateSummary {
+    pub succeeded: usize,
+    /// Locations that still failed after all retries.
+    pub failed: Vec<String>,
+}
+
+/// Runs `op`, retrying up to `retries` more times on error. The wait before each retry
+/// doubles, starting at `base_delay` (1s, 2s, 4s for the iCal fetch).
+async fn retry_with_backoff<T, F, Fut>(
+    retries: u32,
+    base_delay: std::time::Duration,
+    mut op: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T>>,
+{
+    let mut delay = base_delay;
+    let mut attempt = 0;
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                warn!(
+                    "Attempt {} failed: {:?}; retrying in {}s",
+                    attempt,
+                    e,
+                    delay.as_secs_f32()
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A random duration between zero and `max`, to spread requests to the upstream API.
+fn jitter(max: std::time::Duration) -> std::time::Duration {
+    use std::hash::{BuildHasher, Hasher};
+    // Every RandomState is seeded differently, which is random enough for spreading load.
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    max.mul_f64(random as f64 / u64::MAX as f64)
+}
+
+/// How far ahead /preview looks for the chat's next reminder.
+pub const PREVIEW_HOURS: i64 = 48;
+
+/// Finds the next hourly slot after `now` in which `chat_id` would get a reminder and
+/// renders it exactly as `dispatch_notifications` would.
+pub async fn preview_notification(
+    pool: &SqlitePool,
+    chat_id: i64,
+    now: NaiveDateTime,
+) -> Result<Option<(NaiveDateTime, String)>> {
+    let first_slot = now
+        .date()
+        .and_hms_opt(now.hour(), 0, 0)
+        .expect("valid hour");
+    for hours in 1..=PREVIEW_HOURS {
+        let slot = first_slot + Duration::hours(hours);
+        let today = slot.date();
+        let time = slot.format("%H:00").to_string();
+        let tomorrow = today + Duration::days(1);
+        let tasks: Vec<_> = store::get_users_to_notify(pool, &time, today, tomorrow)
+            .await?
+            .into_iter()
+            .filter(|task| task.chat_id == chat_id)
+            .collect();
+        if !tasks.is_empty() {
+            return Ok(Some((slot, format_notification(&tasks))));
+        }
+    }
+    Ok(None)
+}
+
+/// Groups notification tasks by chat so each user gets a single message per slot.
+/// Chats keep the order of their first task.
+fn group_by_chat(tasks: Vec<NotificationTask>) -> Vec<(i64, Vec<NotificationTask>)> {
+    let mut groups: Vec<(i64, Vec<NotificationTask>)> = Vec::new();
+    for task in tasks {
+        match groups.iter_mut().find(|(chat, _)| *chat == task.chat_id) {
+            Some((_, group)) => group.push(task),
+            None => groups.push((task.chat_id, vec![task])),
+        }
+    }
+    groups
+}
+
+/// Hour at which a snoozed reminder is sent again.
+pub const SNOOZE_HOUR: u32 = 16;
+
+/// Telegram rejects callback data longer than this many bytes.
+const MAX_CALLBACK_DATA: usize = 64;
+
+/// The "remind me again" button for a notification, covering its earliest pickup date.
+/// Waste types that don't fit into the callback data are left out; if not even the first
+/// one fits, the reminder goes out without the button.
+fn snooze_keyboard(tasks: &[NotificationTask]) -> Option<InlineKeyboardMarkup> {
+    let first = tasks.iter().min_by_key(|task| task.event_date)?;
+    let mut types: Vec<&str> = Vec::new();
+    for task in tasks
+        .iter()
+        .filter(|task| task.event_date == first.event_date)
+    {
+        if !types.contains(&task.waste_type.as_str()) {
+            types.push(&task.waste_type);
+        }
+    }
+    let mut data = format!("snooze:{}:{}", first.event_date, types.join(","));
+    while data.len() > MAX_CALLBACK_DATA && types.len() > 1 {
+        types.pop();
+        data = format!("snooze:{}:{}", first.event_date, types.join(","));
+    }
+    if data.len() > MAX_CALLBACK_DATA {
+        return None;
+    }
+    Some(InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback(
+            tf(Key::SnoozeButton, first.language, &[&SNOOZE_HOUR]),
+            data,
+        ),
+    ]]))
+}
+
+/// Sends the snoozed reminders that are due and forgets them.
+async fn send_due_snoozes(notifier: &Notifier, pool: &SqlitePool) -> Result<()> {
+    let now = Local::now().naive_local();
+    let today = now.date();
+    for snooze in store::take_due_snoozes(pool, now).await? {
+        // Left over from before a restart, and the pickup is already over
+        if snooze.event_date < today {
+            continue;
+        }
+        let day = if snooze.event_date == today {
+            Key::Today
+        } else {
+            Key::Tomorrow
+        };
+        let labels: Vec<String> = snooze
+            .waste_types
+            .iter()
+            .map(|name| {
+                let waste: WasteType = name.parse().expect("WasteType parsing is infallible");
+                waste.label()
+            })
+            .collect();
+        let text = tf(
+            Key::SnoozeReminder,
+            snooze.language,
+            &[
+                &t(day, snooze.language),
+                &snooze.language.format_day(snooze.event_date),
+                &labels.join(", "),
+            ],
+        );
+        if let Err(e) = notifier.send(ChatId(snooze.chat_id), text).await {
+            error!(
+                "Failed to send snoozed reminder to {}: {:?}",
+                snooze.chat_id, e
+            );
+            if notifier::is_unreachable(&e) {
+                let _ = store::delete_user(pool, snooze.chat_id).await;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Renders one chat's tasks as a message with one line per location, e.g.
+/// "📅 Tomorrow (Fri, 07.06.) at Home: 🟤 Bio, ⚫ Rest collection.", or in the user's
+/// own template if they set one.
+///
+/// Each type is listed once per line, even if several tasks name it: a main reminder with
+/// a custom lead time and an extra one from notify_times can come due on the same tick,
+/// and each brings its own task for the same pickup.
+fn format_notification(tasks: &[NotificationTask]) -> String {
+    let mut lines: Vec<(&NotificationTask, Vec<String>)> = Vec::new();
+    for task in tasks {
+        let waste: WasteType = task
+            .waste_type
+            .parse()
+            .expect("WasteType parsing is infallible");
+        let label = waste.label();
+        match lines.iter_mut().find(|(first, _)| {
+            first.location_id == task.location_id && first.event_date == task.event_date
+        }) {
+            Some((_, labels)) if labels.contains(&label) => {}
+            Some((_, labels)) => labels.push(label),
+            None => lines.push((task, vec![label])),
+        }
+    }
+
+    lines
+        .into_iter()
+        .map(|(task, labels)| {
             // Determine prefix based on notify_offset
             // offset 1 = Day Before ("Tomorrow")
             // offset 0 = Same Day ("Today")
             let prefix = if task.notify_offset == 1 {
-                "Tomorrow"
+                Key::Tomorrow
             } else {
-                "Today"
+                Key::Today
             };
+            let loc_label = task.location_alias.as_deref().unwrap_or(&task.location_id);
+            if let Some(template) = &task.message_template {
+                return i18n::fill_template(
+                    template,
+                    &[
+                        ("type", &labels.join(", ")),
+                        ("date", &task.language.format_day(task.event_date)),
+                        ("when", t(prefix, task.language)),
+                        ("location", loc_label),
+                    ],
+                );
+            }
+            tf(
+                Key::NotificationLine,
+                task.language,
+                &[
+                    &t(prefix, task.language),
+                    &task.language.format_day(task.event_date),
+                    &loc_label,
+                    &labels.join(", "),
+                ],
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
-            let loc_label = task
-                .location_alias
-                .as_deref()
-                .unwrap_or(&task.location_id);
+async fn update_all_icals(notifier: &Notifier, pool: &SqlitePool) -> Result<IcalUpdateSummary> {
+    info!("Starting iCal update...");
 
-            let message = format!(
-                "📅 {} at {}: {} collection.",
-                prefix, loc_label, task.waste_type
-            );
+    let locations = store::get_active_location_ids(pool).await?;
 
-            if let Err(e) = bot.send_message(chat_id, message).await {
-                error!("Failed to send notification to {}: {:?}", task.chat_id, e);
-                // Handle block/deactivated
-                if let teloxide::RequestError::Api(
-                    teloxide::ApiError::BotBlocked | teloxide::ApiError::UserDeactivated,
-                ) = &e
-                {
-                    info!(
-                        "User {} blocked bot or is deactivated. Removing...",
-                        task.chat_id
-                    );
-                    // We should delete all user data? Or just the specific subscription?
-                    // Probably delete user entirely if they blocked the bot.
-                    let _ = store::delete_user(pool, task.chat_id).await;
-                }
+    let client = build_http_client()?;
+
+    let concurrency =
+        config::positive_from_env("ICAL_FETCH_CONCURRENCY", DEFAULT_ICAL_FETCH_CONCURRENCY);
+
+    // Fetch a handful of locations at once; each slot still pauses between requests
+    // so the upstream API sees a bounded request rate.
+    let results: Vec<(String, bool)> = futures::stream::iter(locations)
+        .map(|loc_id| {
+            let client = &client;
+            let span = info_span!("ical_update", location_id = %loc_id);
+            async move {
+                tokio::time::sleep(jitter(ICAL_FETCH_JITTER)).await;
+                let fetch = retry_with_backoff(ICAL_FETCH_RETRIES, ICAL_RETRY_BASE_DELAY, || {
+                    update_location_ical(pool, client, &loc_id)
+                });
+                let ok = match fetch.await {
+                    Ok(count) => {
+                        info!("Loaded {} events for location {}", count, loc_id);
+                        if let Err(e) = check_upcoming_events(notifier, pool, &loc_id).await {
+                            error!("Failed to check upcoming events for {}: {:?}", loc_id, e);
+                        }
+                        true
+                    }
+                    Err(e) => {
+                        error!("Failed to update iCal for {}: {:?}", loc_id, e);
+                        false
+                    }
+                };
+
+                // Sleep a bit to be nice to the API
+                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+                (loc_id, ok)
             }
+            .instrument(span)
         })
+        .buffer_unordered(concurrency)
+        .collect()
         .await;
 
-    Ok(())
+    let mut summary = IcalUpdateSummary::default();
+    for (loc_id, ok) in results {
+        if ok {
+            summary.succeeded += 1;
+        } else {
+            summary.failed.push(loc_id);
+        }
+    }
+    if summary.succeeded > 0 {
+        metrics::ical_updated();
+    }
+
+    info!(
+        "iCal update finished: {} succeeded, {} failed.",
+        summary.succeeded,
+        summary.failed.len()
+    );
+    if !summary.failed.is_empty() {
+        warn!(
+            "Locations without fresh events: {}",
+            summary.failed.join(", ")
+        );
+    }
+    // Every fetch failing points at the endpoint rather than single locations
+    if summary.succeeded == 0 && !summary.failed.is_empty() {
+        if let Some(admin) = config::admin_chat_id() {
+            let lang = store::get_language(pool, admin.0).await.unwrap_or_default();
+            let text = tf(Key::IcalOutage, lang, &[&summary.failed.len()]);
+            if let Err(e) = notifier.send(admin, text).await {
+                error!("Failed to alert the admin about the iCal outage: {:?}", e);
+            }
+        }
+    }
+    // Judge locations only on fresh data, so an outage doesn't flag them all
+    if summary.succeeded > 0 {
+        match check_stale_locations(notifier, pool).await {
+            Ok(flagged) => info!("Stale location check: {} newly flagged.", flagged),
+            Err(e) => error!("Error checking for stale locations: {:?}", e),
+        }
+    }
+    Ok(summary)
 }
 
-async fn update_all_icals(pool: &SqlitePool) -> Result<()> {
-    info!("Starting iCal update...");
+/// Tells users once when their location's feed has no upcoming pickups (usually a
+/// wrong Standort-ID), and re-arms the warning as soon as events show up again.
+/// With `EMPTY_FEED_REMINDER_DAYS` set, the warning repeats while the feed stays empty.
+async fn check_upcoming_events(notifier: &Notifier, pool: &SqlitePool, loc_id: &str) -> Result<()> {
+    let today = Local::now().date_naive();
+    if store::count_upcoming_events(pool, loc_id, today).await? > 0 {
+        store::reset_empty_feed_warning(pool, loc_id).await?;
+        store::clear_location_stale(pool, loc_id).await?;
+        return Ok(());
+    }
 
-    // Get all unique location_ids from user_locations
-    // We need to join with user_locations now because location_id is there
-    let rows = sqlx::query("SELECT DISTINCT location_id FROM user_locations")
-            .fetch_all(pool)
-            .await?;
+    let remind_after = config::empty_feed_reminder_days();
+    let users = store::take_empty_feed_warnings(pool, loc_id, remind_after).await?;
+    let unsent = warn_location_users(notifier, pool, loc_id, users, Key::NoUpcomingPickups).await;
+    for chat_id in unsent {
+        store::release_empty_feed_warning(pool, loc_id, chat_id).await?;
+    }
+    Ok(())
+}
+
+/// Flags every location that was fetched but has no upcoming pickups, which usually
+/// means the city retired its ID, and tells its users once. Locations with pickups
+/// again lose the flag. If a warning couldn't be sent, the flag is taken back so the
+/// next check tries again. Returns how many locations were newly flagged.
+async fn check_stale_locations(notifier: &Notifier, pool: &SqlitePool) -> Result<usize> {
+    let today = Local::now().date_naive();
+    let mut flagged = 0;
+    for loc_id in store::get_active_location_ids(pool).await? {
+        // Never fetched successfully: nothing to judge yet
+        if store::get_last_update(pool, &loc_id).await?.is_none() {
+            continue;
+        }
+        if store::count_upcoming_events(pool, &loc_id, today).await? > 0 {
+            store::clear_location_stale(pool, &loc_id).await?;
+            continue;
+        }
+        if !store::mark_location_stale(pool, &loc_id).await? {
+            continue;
+        }
+        warn!(
+            "Location {} has no upcoming pickups, flagged as stale",
+            loc_id
+        );
+        flagged += 1;
+        let users = store::get_location_users(pool, &loc_id).await?;
+        let unsent = warn_location_users(notifier, pool, &loc_id, users, Key::LocationStale).await;
+        if !unsent.is_empty() {
+            store::clear_location_stale(pool, &loc_id).await?;
+        }
+    }
+    Ok(flagged)
+}
 
-    let mut locations = Vec::new();
-    for row in rows {
-        locations.push(row.try_get::<String, _>("location_id")?);
+/// Sends the warning `key` about `loc_id` to each of `users`. Users who can't be
+/// reached anymore are deleted. Returns the chats whose warning failed for another
+/// reason and is worth trying again.
+async fn warn_location_users(
+    notifier: &Notifier,
+    pool: &SqlitePool,
+    loc_id: &str,
+    users: Vec<store::LocationUser>,
+    key: Key,
+) -> Vec<i64> {
+    let mut unsent = Vec::new();
+    for user in users {
+        let label = user.alias.as_deref().unwrap_or(loc_id);
+        let text = tf(key, user.language, &[&label]);
+        let send = notifier.send(ChatId(user.chat_id), text);
+        match send
+            .instrument(info_span!("notify", chat_id = user.chat_id))
+            .await
+        {
+            Ok(_) => metrics::notification_sent(),
+            Err(e) => {
+                metrics::notification_failed();
+                error!(
+                    "Failed to warn {} about location {}: {:?}",
+                    user.chat_id, loc_id, e
+                );
+                if notifier::is_unreachable(&e) {
+                    let _ = store::delete_user(pool, user.chat_id).await;
+                } else {
+                    unsent.push(user.chat_id);
+                }
+            }
+        }
     }
+    unsent
+}
 
+pub fn build_http_client() -> Result<reqwest::Client> {
     // Sentinel: Added timeout to prevent hanging if the external API is unresponsive.
     let client = reqwest::Client::builder()
         .timeout(std::time::Duration::from_secs(30))
         .build()?;
+    Ok(client)
+}
 
+/// Why a feed URL answered successfully but without a usable calendar. Each variant
+/// keeps the rejected body for the raw feed cache.
+#[derive(Error, Debug)]
+pub enum FeedError {
+    /// A web page instead of iCal, which the city's endpoint sends for unknown location
+    /// IDs. Points at a wrong ID rather than an outage.
+    #[error("Feed returned an HTML page, the location ID is probably invalid")]
+    HtmlPage(String),
+    #[error("Invalid iCal response")]
+    NotIcal(String),
+}
+
+impl FeedError {
+    /// The response body that was rejected.
+    pub fn body(&self) -> &str {
+        match self {
+            FeedError::HtmlPage(body) | FeedError::NotIcal(body) => body,
+        }
+    }
+}
+
+/// Downloads a location's feed from the first URL template that has it. A 404 or a
+/// body that isn't iCal moves on to the next template; other failures end the attempt.
+async fn fetch_feed(
+    client: &reqwest::Client,
+    templates: &[&str],
+    loc_id: &str,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<String> {
+    let mut last_error = None;
+    for (i, template) in templates.iter().enumerate() {
+        let endpoint = if i == 0 { "primary" } else { "fallback" };
+        let resp = client
+            .get(ical_url(template, loc_id, start, end))
+            .send()
+            .await?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            warn!("{} feed URL has no location {} (404)", endpoint, loc_id);
+            last_error = Some(anyhow::anyhow!("Status {}", resp.status()));
+            continue;
+        }
+        if !resp.status().is_success() {
+            bail!("Status {}", resp.status());
+        }
+
+        let text = resp.text().await?;
+        // Validate content type or content
+        if looks_like_html(&text) {
+            warn!(
+                "{} feed URL returned an HTML page for location {}",
+                endpoint, loc_id
+            );
+            last_error = Some(FeedError::HtmlPage(text).into());
+            continue;
+        }
+        if !text.contains("BEGIN:VCALENDAR") {
+            warn!(
+                "{} feed URL returned no iCal data for location {}",
+                endpoint, loc_id
+            );
+            last_error = Some(FeedError::NotIcal(text).into());
+            continue;
+        }
+        info!("Fetched location {} from the {} feed URL", loc_id, endpoint);
+        return Ok(text);
+    }
+    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No feed URL configured")))
+}
+
+/// Fetches and parses a location's feed for the configured window, without storing it.
+pub async fn fetch_calendar(client: &reqwest::Client, loc_id: &str) -> Result<Calendar> {
+    let text = fetch_calendar_text(client, loc_id).await?;
+    Ok(parse_ical(&text)?)
+}
+
+/// Downloads a location's raw feed for the configured window.
+async fn fetch_calendar_text(client: &reqwest::Client, loc_id: &str) -> Result<String> {
     let now = Local::now().date_naive();
     // Start date: today
-    // End date: today + 3 months
-    let start_date = now.format("%d.%m.%Y").to_string(); // Check API format!
-    let end_date = (now + Duration::days(90)).format("%d.%m.%Y").to_string();
+    // End date: today + window (3 months by default)
+    let config = ical_config();
+    let mut templates = vec![config.url_template.as_str()];
+    templates.extend(config.fallback_url_template.as_deref());
+    let end = now + Duration::days(config.window_days);
+    fetch_feed(client, &templates, loc_id, now, end).await
+}
 
-    for loc_id in locations {
-        info!("Updating iCal for location: {}", loc_id);
+/// Keeps `body` as the location's last fetched feed if `CACHE_RAW_ICAL` is set.
+async fn cache_raw_feed(pool: &SqlitePool, loc_id: &str, body: &str) {
+    if !config::cache_raw_ical() {
+        return;
+    }
+    if let Err(e) = store::save_raw_ical(pool, loc_id, body).await {
+        warn!("Failed to cache the raw feed of {}: {:?}", loc_id, e);
+    }
+}
 
-        let params = [
-            ("STANDORT", loc_id.as_str()),
-            ("DATUM_VON", start_date.as_str()),
-            ("DATUM_BIS", end_date.as_str()),
-        ];
+/// Fetches, parses and stores the iCal feed for a single location.
+/// Returns the number of parsed pickup events.
+#[instrument(skip_all, fields(location_id = %loc_id))]
+pub async fn update_location_ical(
+    pool: &SqlitePool,
+    client: &reqwest::Client,
+    loc_id: &str,
+) -> Result<usize> {
+    info!("Updating iCal for location: {}", loc_id);
 
-        let url =
-            "https://stadtplan.dresden.de/project/cardo3Apps/IDU_DDStadtplan/abfall/ical.ashx";
-
-        match client.get(url).query(&params).send().await {
-            Ok(resp) => {
-                if resp.status().is_success() {
-                    match resp.text().await {
-                        Ok(text) => {
-                            // Validate content type or content
-                            if !text.contains("BEGIN:VCALENDAR") {
-                                error!("Invalid iCal response for location {}", loc_id);
-                                continue;
-                            }
-
-                            match parse_ical(&text) {
-                                Ok(events) => {
-                                    if let Err(e) =
-                                        store::upsert_events(pool, &loc_id, &events).await
-                                    {
-                                        error!("Failed to upsert events for {}: {:?}", loc_id, e);
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("Failed to parse iCal for {}: {:?}", loc_id, e);
-                                }
-                            }
-                        }
-                        Err(e) => error!("Failed to read response body for {}: {:?}", loc_id, e),
-                    }
-                } else {
-                    error!(
-                        "Failed to fetch iCal for {}: Status {}",
-                        loc_id,
-                        resp.status()
-                    );
-                }
+    // Cached before parsing, and also when rejected, so a bad response can be looked at
+    let text = match fetch_calendar_text(client, loc_id).await {
+        Ok(text) => text,
+        Err(e) => {
+            if let Some(rejected) = e.downcast_ref::<FeedError>() {
+                cache_raw_feed(pool, loc_id, rejected.body()).await;
             }
-            Err(e) => error!("Failed to connect for {}: {:?}", loc_id, e),
+            return Err(e);
+        }
+    };
+    cache_raw_feed(pool, loc_id, &text).await;
+    let calendar = parse_ical(&text)?;
+    store::upsert_events(pool, loc_id, &calendar.events).await?;
+    store::mark_location_updated(pool, loc_id).await?;
+
+    // Only logged for now: a pickup missing from a regular rhythm usually means a broken
+    // feed rather than a real change. At debug level, since every refresh finds the same
+    // gaps again.
+    let mut dates_by_type: std::collections::HashMap<&WasteType, Vec<NaiveDate>> =
+        std::collections::HashMap::new();
+    for event in &calendar.events {
+        for waste_type in &event.waste_types {
+            dates_by_type
+                .entry(waste_type)
+                .or_default()
+                .push(event.date);
+        }
+    }
+    for (waste_type, dates) in &dates_by_type {
+        for gap in find_pickup_gaps(dates) {
+            debug!(
+                "No {} pickup between {} and {}, usually every {} days",
+                waste_type.as_str(),
+                gap.after,
+                gap.before,
+                gap.interval_days
+            );
         }
+    }
 
-        // Sleep a bit to be nice to the API
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    if let Some(name) = calendar.name {
+        // The name comes from upstream; keep it short and printable before storing.
+        let name: String = name.chars().filter(|c| !c.is_control()).take(100).collect();
+        store::update_location_name(pool, loc_id, &name).await?;
     }
 
-    info!("iCal update finished.");
-    Ok(())
+    Ok(calendar.events.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(chat_id: i64, location_id: &str, waste_type: &str) -> NotificationTask {
+        NotificationTask {
+            chat_id,
+            waste_type: waste_type.to_string(),
+            location_alias: Some("Home".to_string()),
+            location_id: location_id.to_string(),
+            notify_offset: 0,
+            event_date: NaiveDate::from_ymd_opt(2024, 6, 7).unwrap(),
+            language: crate::i18n::Lang::En,
+            message_template: None,
+            slot: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_same_day_pickups_coalesce() {
+        let tasks = vec![
+            task(1, "LOC1", "Bio"),
+            task(1, "LOC1", "Rest"),
+            task(1, "LOC1", "Papier"),
+        ];
+
+        let groups = group_by_chat(tasks);
+        assert_eq!(
+            groups.len(),
+            1,
+            "three subscriptions should produce one send"
+        );
+        assert_eq!(
+            format_notification(&groups[0].1),
+            "📅 Today (Fri, 07.06.) at Home: 🟤 Bio, ⚫ Rest, 🔵 Papier collection."
+        );
+    }
+
+    #[test]
+    fn test_duplicate_types_listed_once() {
+        // The main reminder and an extra one from notify_times land on the same tick, so
+        // every pickup comes in once per slot
+        let extra = |waste| NotificationTask {
+            slot: "18:00/1".to_string(),
+            ..task(1, "LOC1", waste)
+        };
+        let tasks = vec![
+            task(1, "LOC1", "Bio"),
+            task(1, "LOC1", "Rest"),
+            extra("Bio"),
+            extra("Rest"),
+        ];
+
+        let groups = group_by_chat(tasks);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(
+            format_notification(&groups[0].1),
+            "📅 Today (Fri, 07.06.) at Home: 🟤 Bio, ⚫ Rest collection."
+        );
+    }
+
+    #[test]
+    fn test_format_notification_with_template() {
+        let tasks: Vec<_> = ["Bio", "Rest"]
+            .into_iter()
+            .map(|waste| NotificationTask {
+                message_template: Some("{when} ({date}): {type} at {location}. Bins out!".into()),
+                ..task(1, "LOC1", waste)
+            })
+            .collect();
+        assert_eq!(
+            format_notification(&tasks),
+            "Today (Fri, 07.06.): 🟤 Bio, ⚫ Rest at Home. Bins out!"
+        );
+    }
+
+    #[test]
+    fn test_group_by_chat_keeps_chats_and_locations_apart() {
+        let mut office = task(1, "LOC2", "Gelb");
+        office.location_alias = Some("Office".to_string());
+        let tasks = vec![task(1, "LOC1", "Bio"), task(2, "LOC1", "Bio"), office];
+
+        let groups = group_by_chat(tasks);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].0, 1);
+        assert_eq!(
+            format_notification(&groups[0].1),
+            "📅 Today (Fri, 07.06.) at Home: 🟤 Bio collection.\n\
+             📅 Today (Fri, 07.06.) at Office: 🟡 Gelb collection."
+        );
+        assert_eq!(groups[1].0, 2);
+        assert_eq!(groups[1].1.len(), 1);
+    }
+
+    #[test]
+    fn test_snooze_keyboard() {
+        let mut tomorrow = task(1, "LOC2", "Gelb");
+        tomorrow.event_date = NaiveDate::from_ymd_opt(2024, 6, 8).unwrap();
+        let tasks = vec![tomorrow, task(1, "LOC1", "Bio"), task(1, "LOC2", "Bio")];
+
+        let keyboard = snooze_keyboard(&tasks).unwrap();
+        let button = &keyboard.inline_keyboard[0][0];
+        assert_eq!(button.text, "⏰ Remind me again at 16:00");
+        // Earliest date only, each type once
+        assert!(matches!(
+            &button.kind,
+            teloxide::types::InlineKeyboardButtonKind::CallbackData(data)
+                if data == "snooze:2024-06-07:Bio"
+        ));
+
+        // Types that don't fit into Telegram's 64 bytes are dropped
+        let names = [
+            "Sperrmüll",
+            "Schadstoff",
+            "Weihnachtsbaum",
+            "Papier",
+            "Rest",
+            "Bio",
+        ];
+        let tasks: Vec<_> = names.iter().map(|name| task(1, "LOC1", name)).collect();
+        let keyboard = snooze_keyboard(&tasks).unwrap();
+        let teloxide::types::InlineKeyboardButtonKind::CallbackData(data) =
+            &keyboard.inline_keyboard[0][0].kind
+        else {
+            panic!("snooze button should carry callback data");
+        };
+        assert!(data.len() <= MAX_CALLBACK_DATA);
+        assert_eq!(
+            data,
+            "snooze:2024-06-07:Sperrmüll,Schadstoff,Weihnachtsbaum,Papier"
+        );
+
+        // A single name that is too long on its own leaves the reminder without a button
+        let summary = "Gartenabfälle: Abholung über die Straßensammlung im April";
+        assert_eq!(summary.len(), 60);
+        assert!(snooze_keyboard(&[task(1, "LOC1", summary)]).is_none());
+
+        assert!(snooze_keyboard(&[]).is_none());
+    }
+
+    #[test]
+    fn test_format_digest() {
+        let event = |date: u32, location_id: &str, waste_type: &str| UpcomingEvent {
+            chat_id: 1,
+            location_id: location_id.to_string(),
+            location_alias: None,
+            waste_type: waste_type.to_string(),
+            date: NaiveDate::from_ymd_opt(2024, 6, date).unwrap(),
+            language: crate::i18n::Lang::En,
+        };
+        let events = vec![
+            event(10, "LOC1", "Bio"),
+            event(10, "LOC1", "Rest"),
+            event(10, "LOC2", "Gelb"),
+            event(13, "LOC1", "Papier"),
+        ];
+        assert_eq!(
+            format_digest(&events),
+            "🗓 Your pickups in the coming week:\n\
+             • Mon, 10.06. at LOC1: 🟤 Bio, ⚫ Rest\n\
+             • Mon, 10.06. at LOC2: 🟡 Gelb\n\
+             • Thu, 13.06. at LOC1: 🔵 Papier"
+        );
+    }
+
+    #[test]
+    fn test_ical_url() {
+        let start = NaiveDate::from_ymd_opt(2024, 6, 7).unwrap();
+        let end = NaiveDate::from_ymd_opt(2024, 9, 5).unwrap();
+        assert_eq!(
+            ical_url(DEFAULT_ICAL_URL_TEMPLATE, "12345", start, end),
+            "https://stadtplan.dresden.de/project/cardo3Apps/IDU_DDStadtplan/abfall/ical.ashx\
+             ?STANDORT=12345&DATUM_VON=07.06.2024&DATUM_BIS=05.09.2024"
+        );
+        assert_eq!(
+            ical_url("https://example.org/{location}.ics", "12345", start, end),
+            "https://example.org/12345.ics"
+        );
+    }
+
+    /// Serves a feed under /primary and /fallback: `bio` only exists on the primary URL,
+    /// `old` only on the fallback, and the primary answers `html` with a web page.
+    async fn feed_server() -> String {
+        use axum::http::StatusCode;
+        use axum::routing::get;
+
+        const FEED: &str = "BEGIN:VCALENDAR\nBEGIN:VEVENT\nDTSTART:20240607\nSUMMARY:Bio\n\
+                            END:VEVENT\nEND:VCALENDAR";
+        let app = axum::Router::new()
+            .route("/primary/bio", get(|| async { FEED }))
+            .route(
+                "/primary/html",
+                get(|| async { "<!DOCTYPE html>\n<html>Standort unbekannt</html>" }),
+            )
+            .route("/fallback/old", get(|| async { FEED }))
+            .route("/fallback/html", get(|| async { FEED }))
+            .fallback(|| async { StatusCode::NOT_FOUND });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_feed_fallback() {
+        let base = feed_server().await;
+        let primary = format!("{}/primary/{{location}}", base);
+        let fallback = format!("{}/fallback/{{location}}", base);
+        let client = build_http_client().unwrap();
+        let day = NaiveDate::from_ymd_opt(2024, 6, 7).unwrap();
+        let both = [primary.as_str(), fallback.as_str()];
+
+        // Found on the primary URL
+        let text = fetch_feed(&client, &both, "bio", day, day).await.unwrap();
+        assert!(text.contains("SUMMARY:Bio"));
+        // 404 on the primary URL
+        assert!(fetch_feed(&client, &both, "old", day, day).await.is_ok());
+        // Not iCal on the primary URL
+        assert!(fetch_feed(&client, &both, "html", day, day).await.is_ok());
+        // Without the fallback, the HTML page is rejected as such rather than parsed
+        let err = fetch_feed(&client, &[primary.as_str()], "html", day, day)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err.downcast_ref(), Some(FeedError::HtmlPage(_))),
+            "{:?}",
+            err
+        );
+        let rejected: &FeedError = err.downcast_ref().unwrap();
+        assert!(looks_like_html(rejected.body()));
+        // Without a fallback, or where neither has it, the location fails
+        assert!(fetch_feed(&client, &[primary.as_str()], "old", day, day)
+            .await
+            .is_err());
+        assert!(fetch_feed(&client, &both, "nowhere", day, day)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_slot() {
+        assert_eq!(parse_slot("18").as_deref(), Some("18:00"));
+        assert_eq!(parse_slot(" 7 ").as_deref(), Some("07:00"));
+        assert_eq!(parse_slot("7:30").as_deref(), Some("07:30"));
+        assert_eq!(parse_slot("06:00").as_deref(), Some("06:00"));
+        assert_eq!(parse_slot("24"), None);
+        assert_eq!(parse_slot("18:75"), None);
+        assert_eq!(parse_slot(""), None);
+    }
+
+    #[test]
+    fn test_should_dispatch() {
+        let at = |h, m| {
+            NaiveDate::from_ymd_opt(2024, 6, 7)
+                .unwrap()
+                .and_hms_opt(h, m, 0)
+                .unwrap()
+        };
+        let times: Vec<String> = ["06:00", "07:30", "7:45", "18:00", "garbage"]
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        let none = HashMap::new();
+
+        assert_eq!(should_dispatch(at(6, 0), &times, &none), vec!["06:00"]);
+        assert_eq!(should_dispatch(at(7, 30), &times, &none), vec!["07:30"]);
+        // Hand-edited times without the leading zero still fire
+        assert_eq!(should_dispatch(at(7, 45), &times, &none), vec!["7:45"]);
+        // The full hour is always dispatched for custom lead times
+        assert_eq!(should_dispatch(at(9, 0), &times, &none), vec!["09:00"]);
+        assert_eq!(should_dispatch(at(9, 0), &[], &none), vec!["09:00"]);
+
+        // A late tick still catches the slot, but only if it hasn't fired yet
+        assert_eq!(should_dispatch(at(9, 1), &times, &none), vec!["09:00"]);
+        let fired = HashMap::from([("09:00".to_string(), at(9, 0))]);
+        assert!(should_dispatch(at(9, 1), &times, &fired).is_empty());
+        assert!(should_dispatch(at(9, 30), &times, &none).is_empty());
+    }
+
+    #[test]
+    fn test_catchup_slots_not_redispatched() {
+        let at = |h, m| {
+            NaiveDate::from_ymd_opt(2024, 6, 7)
+                .unwrap()
+                .and_hms_opt(h, m, 0)
+                .unwrap()
+        };
+        let times = vec!["18:00".to_string()];
+        // Started at 18:01: catch-up already sent 15:00..=18:00
+        let fired = fired_after_catchup(&catchup_slots(18, 3), at(18, 1));
+
+        assert_eq!(fired.len(), 4);
+        assert!(should_dispatch(at(18, 1), &times, &fired).is_empty());
+        assert!(should_dispatch(at(18, 2), &times, &fired).is_empty());
+        // The next slots still fire as usual
+        assert_eq!(should_dispatch(at(19, 0), &times, &fired), vec!["19:00"]);
+    }
+
+    #[test]
+    fn test_should_fire() {
+        let at = |h, m| {
+            NaiveDate::from_ymd_opt(2024, 6, 7)
+                .unwrap()
+                .and_hms_opt(h, m, 0)
+                .unwrap()
+        };
+        let slot = NotifySlot::on_the_hour(18);
+
+        // On time
+        assert!(should_fire(slot, at(18, 0), None));
+        // Two minutes late
+        assert!(should_fire(slot, at(18, 2), None));
+        // Already sent for this slot today
+        assert!(!should_fire(slot, at(18, 2), Some(at(18, 0))));
+        // Sent yesterday doesn't count
+        assert!(should_fire(
+            slot,
+            at(18, 2),
+            Some(at(18, 0) - Duration::days(1))
+        ));
+        // Too early, and too late
+        assert!(!should_fire(slot, at(17, 59), None));
+        assert!(!should_fire(
+            slot,
+            at(18, LATE_TICK_GRACE_MINUTES as u32),
+            None
+        ));
+    }
+
+    #[test]
+    fn test_jitter() {
+        let max = std::time::Duration::from_secs(7200);
+        let delays: Vec<_> = (0..20).map(|_| jitter(max)).collect();
+        assert!(delays.iter().all(|delay| *delay <= max));
+        // Not the same value every time
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+        assert_eq!(jitter(std::time::Duration::ZERO), std::time::Duration::ZERO);
+    }
+
+    #[test]
+    fn test_catchup_slots() {
+        assert_eq!(catchup_slots(6, 0), vec!["06:00"]);
+        assert_eq!(catchup_slots(8, 2), vec!["06:00", "07:00", "08:00"]);
+        // Never reaches back into yesterday
+        assert_eq!(catchup_slots(1, 4), vec!["00:00", "01:00"]);
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_backoff() {
+        let delay = std::time::Duration::from_millis(1);
+        let calls = AtomicUsize::new(0);
+
+        // Succeeds on the third attempt
+        let result = retry_with_backoff(3, delay, || async {
+            if calls.fetch_add(1, Ordering::Relaxed) < 2 {
+                bail!("transient")
+            }
+            Ok(42)
+        })
+        .await;
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+
+        // Gives up after the initial attempt plus three retries
+        calls.store(0, Ordering::Relaxed);
+        let result: Result<()> = retry_with_backoff(3, delay, || async {
+            calls.fetch_add(1, Ordering::Relaxed);
+            bail!("down")
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::Relaxed), 4);
+    }
 }
@@ -1,16 +1,44 @@
-use crate::waste::PickupEvent;
-use anyhow::Result;
+use crate::i18n::Lang;
+use crate::waste::{PickupEvent, WasteType};
+use anyhow::{bail, Result};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
 use sqlx::{sqlite::Sqlite, QueryBuilder, Row, SqlitePool};
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use tracing::warn;
 
 // User Operations
 pub async fn create_user(pool: &SqlitePool, chat_id: i64) -> Result<()> {
-    sqlx::query("INSERT INTO users (id) VALUES (?) ON CONFLICT(id) DO NOTHING")
+    sqlx::query("INSERT INTO users (id, language) VALUES (?, ?) ON CONFLICT(id) DO NOTHING")
         .bind(chat_id)
+        .bind(Lang::default().code())
         .execute(pool)
         .await?;
     Ok(())
 }
 
+/// Runs a trivial query, to check the database still answers.
+pub async fn ping(pool: &SqlitePool) -> Result<()> {
+    sqlx::query("SELECT 1").execute(pool).await?;
+    Ok(())
+}
+
+pub async fn count_users(pool: &SqlitePool) -> Result<i64> {
+    let count = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+        .fetch_one(pool)
+        .await?;
+    Ok(count)
+}
+
+pub async fn get_all_chat_ids(pool: &SqlitePool) -> Result<Vec<i64>> {
+    let ids = sqlx::query_scalar("SELECT id FROM users ORDER BY id")
+        .fetch_all(pool)
+        .await?;
+    Ok(ids)
+}
+
 pub async fn delete_user(pool: &SqlitePool, chat_id: i64) -> Result<()> {
     sqlx::query("DELETE FROM users WHERE id = ?")
         .bind(chat_id)
@@ -19,20 +47,421 @@ pub async fn delete_user(pool: &SqlitePool, chat_id: i64) -> Result<()> {
     Ok(())
 }
 
+/// Pauses notifications up to and including `until`; `None` unmutes.
+pub async fn set_mute_until(
+    pool: &SqlitePool,
+    chat_id: i64,
+    until: Option<NaiveDate>,
+) -> Result<()> {
+    create_user(pool, chat_id).await?;
+    sqlx::query("UPDATE users SET mute_until = ? WHERE id = ?")
+        .bind(until)
+        .bind(chat_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Language for the chat's messages; unknown chats get the default.
+pub async fn get_language(pool: &SqlitePool, chat_id: i64) -> Result<Lang> {
+    let code: Option<String> = sqlx::query_scalar("SELECT language FROM users WHERE id = ?")
+        .bind(chat_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(code.map(|c| Lang::from_code(&c)).unwrap_or_default())
+}
+
+pub async fn set_language(pool: &SqlitePool, chat_id: i64, lang: Lang) -> Result<()> {
+    create_user(pool, chat_id).await?;
+    sqlx::query("UPDATE users SET language = ? WHERE id = ?")
+        .bind(lang.code())
+        .bind(chat_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// How a user gets reminders, stored as its code in `users.notify_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NotifyMode {
+    /// A reminder before each pickup.
+    #[default]
+    PerEvent,
+    /// One summary of the coming week, see `config::digest_schedule`.
+    WeeklyDigest,
+}
+
+impl NotifyMode {
+    pub fn code(self) -> &'static str {
+        match self {
+            NotifyMode::PerEvent => "per_event",
+            NotifyMode::WeeklyDigest => "weekly_digest",
+        }
+    }
+
+    /// Unknown codes fall back to per-event reminders.
+    pub fn from_code(code: &str) -> Self {
+        match code {
+            "weekly_digest" => NotifyMode::WeeklyDigest,
+            _ => NotifyMode::PerEvent,
+        }
+    }
+
+    pub fn toggled(self) -> Self {
+        match self {
+            NotifyMode::PerEvent => NotifyMode::WeeklyDigest,
+            NotifyMode::WeeklyDigest => NotifyMode::PerEvent,
+        }
+    }
+}
+
+pub async fn get_notify_mode(pool: &SqlitePool, chat_id: i64) -> Result<NotifyMode> {
+    let code: Option<String> = sqlx::query_scalar("SELECT notify_mode FROM users WHERE id = ?")
+        .bind(chat_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(code.map(|c| NotifyMode::from_code(&c)).unwrap_or_default())
+}
+
+pub async fn set_notify_mode(pool: &SqlitePool, chat_id: i64, mode: NotifyMode) -> Result<()> {
+    create_user(pool, chat_id).await?;
+    sqlx::query("UPDATE users SET notify_mode = ? WHERE id = ?")
+        .bind(mode.code())
+        .bind(chat_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// The chat-wide settings stored in `users`. Per-location settings are in `UserLocation`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct User {
+    pub id: i64,
+    pub language: Lang,
+    /// Vacation mode, see `set_mute_until`. May lie in the past.
+    pub mute_until: Option<NaiveDate>,
+    pub notify_mode: NotifyMode,
+    /// The user's own reminder line, see `i18n::fill_template`.
+    pub message_template: Option<String>,
+    /// Whether to say so when the coming week has no pickups, see `get_quiet_week_users`.
+    pub quiet_week_notice: bool,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+impl User {
+    /// Settings of a chat that has nothing stored yet.
+    pub fn new(id: i64) -> Self {
+        User {
+            id,
+            language: Lang::default(),
+            mute_until: None,
+            notify_mode: NotifyMode::default(),
+            message_template: None,
+            quiet_week_notice: false,
+            created_at: None,
+        }
+    }
+
+    /// The mute date if it hasn't expired by `today`.
+    pub fn active_mute(&self, today: NaiveDate) -> Option<NaiveDate> {
+        self.mute_until.filter(|until| *until >= today)
+    }
+}
+
+pub async fn get_user(pool: &SqlitePool, chat_id: i64) -> Result<Option<User>> {
+    let row = sqlx::query(
+        "SELECT id, language, mute_until, notify_mode, message_template, quiet_week_notice,
+                created_at
+         FROM users WHERE id = ?",
+    )
+    .bind(chat_id)
+    .fetch_optional(pool)
+    .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    Ok(Some(User {
+        id: row.try_get("id")?,
+        language: Lang::from_code(row.try_get("language")?),
+        mute_until: row.try_get("mute_until")?,
+        notify_mode: NotifyMode::from_code(row.try_get("notify_mode")?),
+        message_template: row.try_get("message_template")?,
+        quiet_week_notice: row.try_get("quiet_week_notice")?,
+        created_at: row.try_get("created_at")?,
+    }))
+}
+
+pub async fn set_quiet_week_notice(pool: &SqlitePool, chat_id: i64, enabled: bool) -> Result<()> {
+    create_user(pool, chat_id).await?;
+    sqlx::query("UPDATE users SET quiet_week_notice = ? WHERE id = ?")
+        .bind(enabled)
+        .bind(chat_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Sets the user's own reminder template; `None` goes back to the default text.
+/// The template must have passed `i18n::check_template`.
+pub async fn set_message_template(
+    pool: &SqlitePool,
+    chat_id: i64,
+    template: Option<&str>,
+) -> Result<()> {
+    create_user(pool, chat_id).await?;
+    sqlx::query("UPDATE users SET message_template = ? WHERE id = ?")
+        .bind(template)
+        .bind(chat_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Everything stored about one user, for /export. /import reads the same format.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserExport {
+    /// Ignored on import; data always goes to the importing chat.
+    #[serde(default)]
+    pub chat_id: i64,
+    pub created_at: Option<NaiveDateTime>,
+    pub language: String,
+    pub mute_until: Option<NaiveDate>,
+    /// Missing in exports from before the weekly digest.
+    #[serde(default = "default_notify_mode")]
+    pub notify_mode: String,
+    /// Missing in exports from before custom reminder texts.
+    #[serde(default)]
+    pub message_template: Option<String>,
+    /// Missing in exports from before the quiet-week notice.
+    #[serde(default)]
+    pub quiet_week_notice: bool,
+    pub locations: Vec<LocationExport>,
+    /// Ignored on import.
+    #[serde(default)]
+    pub notifications_sent: Vec<NotificationExport>,
+}
+
+fn default_notify_mode() -> String {
+    NotifyMode::PerEvent.code().to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LocationExport {
+    pub location_id: String,
+    pub alias: Option<String>,
+    pub notify_time: String,
+    /// 1 = Day Before, 0 = Same Day
+    pub notify_offset: i64,
+    pub notify_offset_hours: Option<i64>,
+    pub subscriptions: Vec<String>,
+    /// Missing in exports from before extra reminders.
+    #[serde(default)]
+    pub extra_notify_times: Vec<NotifyTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotificationExport {
+    pub location_id: String,
+    pub waste_type: String,
+    pub date: NaiveDate,
+    pub sent_at: Option<NaiveDateTime>,
+}
+
+/// Collects the user's data, or `None` if the chat isn't known.
+pub async fn export_user(pool: &SqlitePool, chat_id: i64) -> Result<Option<UserExport>> {
+    let Some(user) = sqlx::query(
+        "SELECT created_at, language, mute_until, notify_mode, message_template,
+                    quiet_week_notice
+             FROM users WHERE id = ?",
+    )
+    .bind(chat_id)
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let mut locations = Vec::new();
+    for loc in get_user_locations(pool, chat_id).await? {
+        locations.push(LocationExport {
+            subscriptions: get_subscriptions(pool, loc.id).await?,
+            extra_notify_times: get_location_notify_times(pool, loc.id).await?,
+            location_id: loc.location_id,
+            alias: loc.alias,
+            notify_time: loc.notify_time,
+            notify_offset: loc.notify_offset,
+            notify_offset_hours: loc.notify_offset_hours,
+        });
+    }
+
+    let rows = sqlx::query(
+        "SELECT location_id, waste_type, date, sent_at FROM notified_log
+         WHERE chat_id = ? ORDER BY date, location_id, waste_type",
+    )
+    .bind(chat_id)
+    .fetch_all(pool)
+    .await?;
+    let mut notifications_sent = Vec::new();
+    for row in rows {
+        notifications_sent.push(NotificationExport {
+            location_id: row.try_get("location_id")?,
+            waste_type: row.try_get("waste_type")?,
+            date: row.try_get("date")?,
+            sent_at: row.try_get("sent_at")?,
+        });
+    }
+
+    Ok(Some(UserExport {
+        chat_id,
+        created_at: user.try_get("created_at")?,
+        language: user.try_get("language")?,
+        mute_until: user.try_get("mute_until")?,
+        notify_mode: user.try_get("notify_mode")?,
+        message_template: user.try_get("message_template")?,
+        quiet_week_notice: user.try_get("quiet_week_notice")?,
+        locations,
+        notifications_sent,
+    }))
+}
+
+/// Checks an /import payload before anything is written.
+fn validate_import(data: &UserExport) -> Result<()> {
+    if !matches!(data.language.as_str(), "de" | "en") {
+        bail!("unknown language {:?}", data.language);
+    }
+    if !matches!(data.notify_mode.as_str(), "per_event" | "weekly_digest") {
+        bail!("unknown notify_mode {:?}", data.notify_mode);
+    }
+    if let Some(template) = &data.message_template {
+        if let Err(e) = crate::i18n::check_template(template) {
+            bail!("invalid message_template: {}", e);
+        }
+    }
+    for loc in &data.locations {
+        if !crate::waste::is_valid_location_id(&loc.location_id) {
+            bail!("invalid location ID {:?}", loc.location_id);
+        }
+        if let Some(alias) = &loc.alias {
+            if alias.len() > 50 || alias.chars().any(|c| c.is_control()) {
+                bail!("invalid name {:?} for location {}", alias, loc.location_id);
+            }
+        }
+        if !is_valid_notify_time(&loc.notify_time) {
+            bail!("invalid notify_time {:?}", loc.notify_time);
+        }
+        if !matches!(loc.notify_offset, 0 | 1) {
+            bail!("notify_offset must be 0 or 1, got {}", loc.notify_offset);
+        }
+        if let Some(hours) = loc.notify_offset_hours {
+            if !(0..=MAX_LEAD_HOURS).contains(&hours) {
+                bail!(
+                    "notify_offset_hours must be between 0 and {}",
+                    MAX_LEAD_HOURS
+                );
+            }
+        }
+        for extra in &loc.extra_notify_times {
+            if !is_valid_notify_time(&extra.notify_time) || !matches!(extra.notify_offset, 0 | 1) {
+                bail!("invalid extra reminder {:?}", extra);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the chat's settings with an /import payload. The payload's own chat_id and
+/// notification log are ignored. Either everything is applied or nothing is.
+pub async fn import_user(pool: &SqlitePool, chat_id: i64, data: &UserExport) -> Result<()> {
+    validate_import(data)?;
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("INSERT INTO users (id) VALUES (?) ON CONFLICT(id) DO NOTHING")
+        .bind(chat_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "UPDATE users SET language = ?, mute_until = ?, notify_mode = ?, message_template = ?,
+                          quiet_week_notice = ?
+         WHERE id = ?",
+    )
+    .bind(&data.language)
+    .bind(data.mute_until)
+    .bind(&data.notify_mode)
+    .bind(&data.message_template)
+    .bind(data.quiet_week_notice)
+    .bind(chat_id)
+    .execute(&mut *tx)
+    .await?;
+    // Subscriptions go with their locations (ON DELETE CASCADE)
+    sqlx::query("DELETE FROM user_locations WHERE user_id = ?")
+        .bind(chat_id)
+        .execute(&mut *tx)
+        .await?;
+
+    for loc in &data.locations {
+        sqlx::query("INSERT INTO locations (location_id) VALUES (?) ON CONFLICT DO NOTHING")
+            .bind(&loc.location_id)
+            .execute(&mut *tx)
+            .await?;
+        let user_location_id: i64 = sqlx::query_scalar(
+            "INSERT INTO user_locations
+                 (user_id, location_id, alias, notify_time, notify_offset, notify_offset_hours)
+             VALUES (?, ?, ?, ?, ?, ?)
+             RETURNING id",
+        )
+        .bind(chat_id)
+        .bind(&loc.location_id)
+        .bind(&loc.alias)
+        .bind(&loc.notify_time)
+        .bind(loc.notify_offset)
+        .bind(loc.notify_offset_hours)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        for waste_type in &loc.subscriptions {
+            sqlx::query(
+                "INSERT INTO subscriptions (user_location_id, waste_type) VALUES (?, ?)
+                 ON CONFLICT DO NOTHING",
+            )
+            .bind(user_location_id)
+            .bind(canonical_waste_type(waste_type)?)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for extra in &loc.extra_notify_times {
+            sqlx::query(
+                "INSERT INTO notify_times (user_location_id, notify_time, notify_offset)
+                 VALUES (?, ?, ?)
+                 ON CONFLICT DO NOTHING",
+            )
+            .bind(user_location_id)
+            .bind(&extra.notify_time)
+            .bind(extra.notify_offset)
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
 pub async fn add_user_location(
     pool: &SqlitePool,
     chat_id: i64,
     location_id: &str,
     alias: Option<&str>,
 ) -> Result<i64> {
-    // Ensure user exists first
+    // Ensure user and location exist first
     create_user(pool, chat_id).await?;
+    add_location(pool, location_id).await?;
 
     // notify_offset default to 1 (Day Before) as per schema, but here we can be explicit or rely on default.
     // relying on DB default.
     let row = sqlx::query(
         "INSERT INTO user_locations (user_id, location_id, alias) VALUES (?, ?, ?)
-         ON CONFLICT(user_id, location_id) DO UPDATE SET alias = excluded.alias
+         ON CONFLICT(user_id, location_id)
+         DO UPDATE SET alias = COALESCE(excluded.alias, user_locations.alias)
          RETURNING id",
     )
     .bind(chat_id)
@@ -46,17 +475,82 @@ pub async fn add_user_location(
     Ok(id)
 }
 
+/// Adds a location with the default subscriptions. If the user already has it, only the
+/// alias changes (a missing alias keeps the old one) and their own subscription choices
+/// are kept.
+pub async fn add_user_location_with_defaults(
+    pool: &SqlitePool,
+    chat_id: i64,
+    location_id: &str,
+    alias: Option<&str>,
+) -> Result<i64> {
+    let existing = get_user_locations(pool, chat_id)
+        .await?
+        .iter()
+        .any(|loc| loc.location_id == location_id);
+    let user_location_id = add_user_location(pool, chat_id, location_id, alias).await?;
+    if !existing {
+        for waste in WasteType::default_subscriptions() {
+            add_subscription(pool, user_location_id, waste.as_str()).await?;
+        }
+    }
+    Ok(user_location_id)
+}
+
+/// Hour at which pickups are assumed to start; lead times count back from it.
+pub const PICKUP_HOUR: i64 = 6;
+
+/// Largest lead time, so a reminder never goes out earlier than the day before.
+pub const MAX_LEAD_HOURS: i64 = PICKUP_HOUR + 24;
+
+/// Reminder time and day offset ("HH:00", 1 = Day Before) for a lead time in hours.
+/// Lead times outside `0..=MAX_LEAD_HOURS` are clamped to that range.
+pub fn lead_time_slot(hours: i64) -> (String, i64) {
+    let hour = PICKUP_HOUR - hours.clamp(0, MAX_LEAD_HOURS);
+    if hour >= 0 {
+        (NotifySlot::on_the_hour(hour as u32).to_db(), 0)
+    } else {
+        (NotifySlot::on_the_hour((hour + 24) as u32).to_db(), 1)
+    }
+}
+
 pub struct UserLocation {
     pub id: i64,
     pub location_id: String,
     pub notify_time: String,
     pub notify_offset: i64,
+    /// Custom lead time; overrides `notify_time`/`notify_offset` when set.
+    pub notify_offset_hours: Option<i64>,
     pub alias: Option<String>,
+    /// Human-readable name from the iCal feed, if one has been fetched.
+    pub location_name: Option<String>,
+}
+
+impl UserLocation {
+    /// "Musterstraße 1 (ID 12345)", or just the ID when no name is known.
+    pub fn describe(&self) -> String {
+        match &self.location_name {
+            Some(name) => format!("{} (ID {})", name, self.location_id),
+            None => self.location_id.clone(),
+        }
+    }
+
+    /// When reminders actually go out, taking a custom lead time into account.
+    pub fn effective_slot(&self) -> (String, i64) {
+        match self.notify_offset_hours {
+            Some(hours) => lead_time_slot(hours),
+            None => (self.notify_time.clone(), self.notify_offset),
+        }
+    }
 }
 
 pub async fn get_user_locations(pool: &SqlitePool, chat_id: i64) -> Result<Vec<UserLocation>> {
     let rows = sqlx::query(
-        "SELECT id, location_id, notify_time, notify_offset, alias FROM user_locations WHERE user_id = ?",
+        "SELECT ul.id, ul.location_id, ul.notify_time, ul.notify_offset, ul.notify_offset_hours,
+                ul.alias, l.name
+         FROM user_locations ul
+         LEFT JOIN locations l ON l.location_id = ul.location_id
+         WHERE ul.user_id = ?",
     )
     .bind(chat_id)
     .fetch_all(pool)
@@ -69,7 +563,9 @@ pub async fn get_user_locations(pool: &SqlitePool, chat_id: i64) -> Result<Vec<U
             location_id: row.try_get("location_id")?,
             notify_time: row.try_get("notify_time")?,
             notify_offset: row.try_get("notify_offset")?,
+            notify_offset_hours: row.try_get("notify_offset_hours")?,
             alias: row.try_get("alias")?,
+            location_name: row.try_get("name")?,
         });
     }
     Ok(locations)
@@ -93,14 +589,111 @@ pub async fn delete_user_location(
     Ok(result.rows_affected() > 0)
 }
 
+/// A reminder time of day. Stored as `HH:MM` text in user_locations and notify_times,
+/// which is also the slot `get_users_to_notify` matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NotifySlot {
+    hour: u32,
+    minute: u32,
+}
+
+impl NotifySlot {
+    /// The schema's default reminder time, also used when a stored one can't be read.
+    pub const DEFAULT: NotifySlot = NotifySlot::new(18, 0);
+
+    pub const fn new(hour: u32, minute: u32) -> Self {
+        assert!(hour < 24 && minute < 60, "not a time of day");
+        NotifySlot { hour, minute }
+    }
+
+    pub const fn on_the_hour(hour: u32) -> Self {
+        NotifySlot::new(hour, 0)
+    }
+
+    pub fn hour(self) -> u32 {
+        self.hour
+    }
+
+    pub fn minute(self) -> u32 {
+        self.minute
+    }
+
+    pub fn time(self) -> NaiveTime {
+        NaiveTime::from_hms_opt(self.hour, self.minute, 0).expect("checked on construction")
+    }
+
+    /// Reads a stored time. Only the exact `HH:MM` form the database CHECK allows is
+    /// accepted; user input goes through `FromStr` instead.
+    pub fn from_db(text: &str) -> Option<Self> {
+        let (hour, minute) = text.split_once(':')?;
+        let two_digits = |s: &str| s.len() == 2 && s.bytes().all(|b| b.is_ascii_digit());
+        if !two_digits(hour) || !two_digits(minute) {
+            return None;
+        }
+        let (hour, minute) = (hour.parse().ok()?, minute.parse().ok()?);
+        (hour < 24 && minute < 60).then_some(NotifySlot { hour, minute })
+    }
+
+    pub fn to_db(self) -> String {
+        self.to_string()
+    }
+
+    /// The next value of the settings time toggle: the following full hour, wrapping
+    /// around at midnight.
+    pub fn next_hour(self) -> Self {
+        NotifySlot::on_the_hour((self.hour + 1) % 24)
+    }
+}
+
+impl fmt::Display for NotifySlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+/// Reads a time typed by a person: an hour ("18") or a clock time ("7:30", "06:00").
+impl FromStr for NotifySlot {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (hour, minute) = s.split_once(':').unwrap_or((s, "00"));
+        let digits = |s: &str, max_len| {
+            (1..=max_len).contains(&s.len()) && s.bytes().all(|b| b.is_ascii_digit())
+        };
+        if digits(hour, 2) && minute.len() == 2 && digits(minute, 2) {
+            let (hour, minute) = (hour.parse()?, minute.parse()?);
+            if hour < 24 && minute < 60 {
+                return Ok(NotifySlot { hour, minute });
+            }
+        }
+        bail!("invalid time {:?}, expected HH:MM", s)
+    }
+}
+
+impl From<NaiveTime> for NotifySlot {
+    fn from(time: NaiveTime) -> Self {
+        NotifySlot::new(time.hour(), time.minute())
+    }
+}
+
+/// Whether `time` is a reminder time the database accepts: `HH:MM` from 00:00 to 23:59.
+pub fn is_valid_notify_time(time: &str) -> bool {
+    NotifySlot::from_db(time).is_some()
+}
+
 pub async fn update_notify_time(
     pool: &SqlitePool,
     chat_id: i64,
     location_alias_or_id: &str,
     time: &str,
 ) -> Result<bool> {
+    if !is_valid_notify_time(time) {
+        bail!("invalid notify time {:?}, expected HH:MM", time);
+    }
     let result = sqlx::query(
-        "UPDATE user_locations SET notify_time = ? WHERE user_id = ? AND (alias = ? OR location_id = ?)",
+        "UPDATE user_locations SET notify_time = ?, notify_offset_hours = NULL
+         WHERE user_id = ? AND (alias = ? OR location_id = ?)",
     )
     .bind(time)
     .bind(chat_id)
@@ -118,7 +711,8 @@ pub async fn update_notify_offset(
     offset: i64,
 ) -> Result<bool> {
     let result = sqlx::query(
-        "UPDATE user_locations SET notify_offset = ? WHERE user_id = ? AND (alias = ? OR location_id = ?)",
+        "UPDATE user_locations SET notify_offset = ?, notify_offset_hours = NULL
+         WHERE user_id = ? AND (alias = ? OR location_id = ?)",
     )
     .bind(offset)
     .bind(chat_id)
@@ -129,44 +723,424 @@ pub async fn update_notify_offset(
     Ok(result.rows_affected() > 0)
 }
 
-// Subscription Operations
-pub async fn add_subscription(
+/// Sets a custom lead time in hours, or clears it with `None`. The time and day presets
+/// (`update_notify_time`, `update_notify_offset`) clear it as well.
+pub async fn update_notify_offset_hours(
     pool: &SqlitePool,
-    user_location_id: i64,
-    waste_type: &str,
-) -> Result<()> {
-    sqlx::query(
-        "INSERT INTO subscriptions (user_location_id, waste_type) VALUES (?, ?) ON CONFLICT DO NOTHING",
+    chat_id: i64,
+    location_alias_or_id: &str,
+    hours: Option<i64>,
+) -> Result<bool> {
+    let result = sqlx::query(
+        "UPDATE user_locations SET notify_offset_hours = ?
+         WHERE user_id = ? AND (alias = ? OR location_id = ?)",
     )
-    .bind(user_location_id)
-    .bind(waste_type)
+    .bind(hours)
+    .bind(chat_id)
+    .bind(location_alias_or_id)
+    .bind(location_alias_or_id)
     .execute(pool)
     .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// Location Operations
+/// Makes sure `location_id` has its shared row in `locations`.
+pub async fn add_location(pool: &SqlitePool, location_id: &str) -> Result<()> {
+    sqlx::query("INSERT INTO locations (location_id) VALUES (?) ON CONFLICT DO NOTHING")
+        .bind(location_id)
+        .execute(pool)
+        .await?;
     Ok(())
 }
 
-pub async fn remove_subscription(
-    pool: &SqlitePool,
-    user_location_id: i64,
-    waste_type: &str,
-) -> Result<()> {
+pub async fn update_location_name(pool: &SqlitePool, location_id: &str, name: &str) -> Result<()> {
     sqlx::query(
-        "DELETE FROM subscriptions WHERE user_location_id = ? AND waste_type = ?",
+        "INSERT INTO locations (location_id, name) VALUES (?, ?)
+         ON CONFLICT(location_id) DO UPDATE SET name = excluded.name",
     )
-    .bind(user_location_id)
-    .bind(waste_type)
+    .bind(location_id)
+    .bind(name)
     .execute(pool)
     .await?;
     Ok(())
 }
 
-pub async fn get_subscriptions(pool: &SqlitePool, user_location_id: i64) -> Result<Vec<String>> {
-    let rows = sqlx::query(
-        "SELECT waste_type FROM subscriptions WHERE user_location_id = ?",
+/// Records a successful feed refresh for `location_id` at the current time.
+pub async fn mark_location_updated(pool: &SqlitePool, location_id: &str) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO locations (location_id, last_updated) VALUES (?, CURRENT_TIMESTAMP)
+         ON CONFLICT(location_id) DO UPDATE SET last_updated = excluded.last_updated",
     )
-    .bind(user_location_id)
-    .fetch_all(pool)
-    .await?;
+    .bind(location_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Time of the last successful feed refresh (UTC), if any.
+pub async fn get_last_update(
+    pool: &SqlitePool,
+    location_id: &str,
+) -> Result<Option<NaiveDateTime>> {
+    let last_updated: Option<Option<NaiveDateTime>> =
+        sqlx::query_scalar("SELECT last_updated FROM locations WHERE location_id = ?")
+            .bind(location_id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(last_updated.flatten())
+}
+
+/// Longest feed body kept in the raw iCal cache, in bytes. A year of pickups is far below.
+pub const MAX_RAW_ICAL_BYTES: usize = 256 * 1024;
+
+/// Stores the feed body last fetched for `location_id`, replacing the previous one.
+/// Bodies over `MAX_RAW_ICAL_BYTES` are cut at a character boundary.
+pub async fn save_raw_ical(pool: &SqlitePool, location_id: &str, body: &str) -> Result<()> {
+    let mut end = body.len().min(MAX_RAW_ICAL_BYTES);
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    sqlx::query(
+        "INSERT INTO raw_ical_cache (location_id, body, fetched_at)
+         VALUES (?, ?, CURRENT_TIMESTAMP)
+         ON CONFLICT(location_id) DO UPDATE SET
+             body = excluded.body,
+             fetched_at = excluded.fetched_at",
+    )
+    .bind(location_id)
+    .bind(&body[..end])
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// The cached feed body for `location_id` and when it was fetched (UTC), if any.
+pub async fn get_raw_ical(
+    pool: &SqlitePool,
+    location_id: &str,
+) -> Result<Option<(String, NaiveDateTime)>> {
+    let cached =
+        sqlx::query_as("SELECT body, fetched_at FROM raw_ical_cache WHERE location_id = ?")
+            .bind(location_id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(cached)
+}
+
+/// Most recent successful feed refresh (UTC) of any configured location, if any.
+pub async fn last_feed_update(pool: &SqlitePool) -> Result<Option<NaiveDateTime>> {
+    let last_updated = sqlx::query_scalar(
+        "SELECT MAX(last_updated) FROM locations l
+         WHERE EXISTS (SELECT 1 FROM user_locations ul WHERE ul.location_id = l.location_id)",
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(last_updated)
+}
+
+/// Every distinct `notify_time` in use by locations without a custom lead time, and
+/// by extra reminders.
+pub async fn get_notify_times(pool: &SqlitePool) -> Result<Vec<String>> {
+    let times = sqlx::query_scalar(
+        "SELECT notify_time FROM user_locations WHERE notify_offset_hours IS NULL
+         UNION
+         SELECT notify_time FROM notify_times
+         ORDER BY notify_time",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(times)
+}
+
+/// An extra reminder of a user location, on top of its main `notify_time`/`notify_offset`,
+/// e.g. on the morning of the pickup in addition to the evening before.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotifyTime {
+    pub notify_time: String,
+    /// 1 = Day Before, 0 = Same Day
+    pub notify_offset: i64,
+}
+
+/// Adds an extra reminder to a user location. Adding one twice changes nothing.
+pub async fn add_notify_time(
+    pool: &SqlitePool,
+    user_location_id: i64,
+    time: &str,
+    offset: i64,
+) -> Result<()> {
+    if !is_valid_notify_time(time) {
+        bail!("invalid notify time {:?}, expected HH:MM", time);
+    }
+    if !matches!(offset, 0 | 1) {
+        bail!("notify_offset must be 0 or 1, got {}", offset);
+    }
+    sqlx::query(
+        "INSERT INTO notify_times (user_location_id, notify_time, notify_offset) VALUES (?, ?, ?)
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(user_location_id)
+    .bind(time)
+    .bind(offset)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Sets the main reminder of the user location `user_location_id` to `main` and adds
+/// `extra` as another one, both as (time, offset), in one transaction. Like the other
+/// presets, this clears a custom lead time.
+pub async fn set_reminder_pair(
+    pool: &SqlitePool,
+    user_location_id: i64,
+    main: (NotifySlot, i64),
+    extra: (NotifySlot, i64),
+) -> Result<()> {
+    for (_, offset) in [main, extra] {
+        if !matches!(offset, 0 | 1) {
+            bail!("notify_offset must be 0 or 1, got {}", offset);
+        }
+    }
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "UPDATE user_locations SET notify_time = ?, notify_offset = ?, notify_offset_hours = NULL
+         WHERE id = ?",
+    )
+    .bind(main.0.to_db())
+    .bind(main.1)
+    .bind(user_location_id)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        "INSERT INTO notify_times (user_location_id, notify_time, notify_offset) VALUES (?, ?, ?)
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(user_location_id)
+    .bind(extra.0.to_db())
+    .bind(extra.1)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn remove_notify_time(
+    pool: &SqlitePool,
+    user_location_id: i64,
+    time: &str,
+    offset: i64,
+) -> Result<()> {
+    sqlx::query(
+        "DELETE FROM notify_times
+         WHERE user_location_id = ? AND notify_time = ? AND notify_offset = ?",
+    )
+    .bind(user_location_id)
+    .bind(time)
+    .bind(offset)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// The extra reminders of a user location, earliest time first.
+pub async fn get_location_notify_times(
+    pool: &SqlitePool,
+    user_location_id: i64,
+) -> Result<Vec<NotifyTime>> {
+    let rows: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT notify_time, notify_offset FROM notify_times
+         WHERE user_location_id = ?
+         ORDER BY notify_time, notify_offset",
+    )
+    .bind(user_location_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(notify_time, notify_offset)| NotifyTime {
+            notify_time,
+            notify_offset,
+        })
+        .collect())
+}
+
+/// Every Standort-ID at least one user has configured.
+pub async fn get_active_location_ids(pool: &SqlitePool) -> Result<Vec<String>> {
+    let ids = sqlx::query_scalar(
+        "SELECT location_id FROM locations l
+         WHERE EXISTS (SELECT 1 FROM user_locations ul WHERE ul.location_id = l.location_id)
+         ORDER BY location_id",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(ids)
+}
+
+/// Number of users per configured location, most used first.
+pub async fn users_per_location(pool: &SqlitePool) -> Result<Vec<(String, i64)>> {
+    let counts = sqlx::query_as(
+        "SELECT location_id, COUNT(*) AS users FROM user_locations
+         GROUP BY location_id
+         ORDER BY users DESC, location_id",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(counts)
+}
+
+// Subscription Operations
+/// Waste types are matched by string between subscriptions and events, so both are
+/// stored under the canonical `WasteType::as_str` name ("Blaue Tonne" becomes "Papier").
+fn canonical_waste_type(name: &str) -> Result<String> {
+    Ok(name.parse::<WasteType>()?.as_str().to_string())
+}
+
+/// Rows whose waste type `renormalize_waste_types` rewrote, per table.
+#[derive(Debug, Default, PartialEq)]
+pub struct RenormalizeCounts {
+    pub subscriptions: u64,
+    pub events: u64,
+    pub notified: u64,
+    pub retries: u64,
+    pub snoozes: u64,
+}
+
+/// Rewrites stored waste types to their current canonical name, for after the parsing
+/// in `WasteType::from_str` changed. Rows that end up duplicating an existing one are
+/// merged into it. Runs in one transaction.
+pub async fn renormalize_waste_types(pool: &SqlitePool) -> Result<RenormalizeCounts> {
+    let mut tx = pool.begin().await?;
+    let counts = RenormalizeCounts {
+        subscriptions: renormalize_table(&mut tx, "subscriptions").await?,
+        events: renormalize_table(&mut tx, "pickup_events").await?,
+        // So reminders already sent for an event aren't sent again under its new name
+        notified: renormalize_table(&mut tx, "notified_log").await?,
+        // Queued retries and snoozes look their pickups up by waste type as well
+        retries: renormalize_table(&mut tx, "failed_notifications").await?,
+        snoozes: renormalize_snoozes(&mut tx).await?,
+    };
+    tx.commit().await?;
+    Ok(counts)
+}
+
+async fn renormalize_table(conn: &mut sqlx::SqliteConnection, table: &str) -> Result<u64> {
+    let names: Vec<String> =
+        sqlx::query_scalar(&format!("SELECT DISTINCT waste_type FROM {}", table))
+            .fetch_all(&mut *conn)
+            .await?;
+
+    let mut changed = 0;
+    for name in names {
+        let canonical = canonical_waste_type(&name)?;
+        if canonical == name {
+            continue;
+        }
+        // Rows that would collide with an existing canonical one are left behind...
+        let updated = sqlx::query(&format!(
+            "UPDATE OR IGNORE {} SET waste_type = ? WHERE waste_type = ?",
+            table
+        ))
+        .bind(&canonical)
+        .bind(&name)
+        .execute(&mut *conn)
+        .await?;
+        // ...and dropped, since the canonical row already says the same
+        let merged = sqlx::query(&format!("DELETE FROM {} WHERE waste_type = ?", table))
+            .bind(&name)
+            .execute(&mut *conn)
+            .await?;
+        changed += updated.rows_affected() + merged.rows_affected();
+    }
+    Ok(changed)
+}
+
+/// Like `renormalize_table` for snoozes, whose waste types are a comma-separated list.
+/// Names that become the same are kept once.
+async fn renormalize_snoozes(conn: &mut sqlx::SqliteConnection) -> Result<u64> {
+    let rows: Vec<(i64, String)> = sqlx::query_as("SELECT id, waste_types FROM snoozes")
+        .fetch_all(&mut *conn)
+        .await?;
+
+    let mut changed = 0;
+    for (id, waste_types) in rows {
+        let mut canonical: Vec<String> = Vec::new();
+        for name in waste_types.split(',') {
+            let name = canonical_waste_type(name)?;
+            if !canonical.contains(&name) {
+                canonical.push(name);
+            }
+        }
+        let canonical = canonical.join(",");
+        if canonical == waste_types {
+            continue;
+        }
+        sqlx::query("UPDATE snoozes SET waste_types = ? WHERE id = ?")
+            .bind(&canonical)
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        changed += 1;
+    }
+    Ok(changed)
+}
+
+pub async fn add_subscription(
+    pool: &SqlitePool,
+    user_location_id: i64,
+    waste_type: &str,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO subscriptions (user_location_id, waste_type) VALUES (?, ?) ON CONFLICT DO NOTHING",
+    )
+    .bind(user_location_id)
+    .bind(canonical_waste_type(waste_type)?)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn remove_subscription(
+    pool: &SqlitePool,
+    user_location_id: i64,
+    waste_type: &str,
+) -> Result<()> {
+    sqlx::query("DELETE FROM subscriptions WHERE user_location_id = ? AND waste_type = ?")
+        .bind(user_location_id)
+        .bind(canonical_waste_type(waste_type)?)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Subscribes to (or unsubscribes from) every supported waste type at once. Runs in one
+/// transaction so the settings menu never sees a half-applied state.
+pub async fn set_all_subscriptions(
+    pool: &SqlitePool,
+    user_location_id: i64,
+    subscribe: bool,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    for waste_type in WasteType::supported_types() {
+        let query = if subscribe {
+            "INSERT INTO subscriptions (user_location_id, waste_type) VALUES (?, ?) ON CONFLICT DO NOTHING"
+        } else {
+            "DELETE FROM subscriptions WHERE user_location_id = ? AND waste_type = ?"
+        };
+        sqlx::query(query)
+            .bind(user_location_id)
+            .bind(waste_type.as_str())
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn get_subscriptions(pool: &SqlitePool, user_location_id: i64) -> Result<Vec<String>> {
+    let rows = sqlx::query("SELECT waste_type FROM subscriptions WHERE user_location_id = ?")
+        .bind(user_location_id)
+        .fetch_all(pool)
+        .await?;
 
     let mut subscriptions = Vec::new();
     for row in rows {
@@ -176,6 +1150,18 @@ pub async fn get_subscriptions(pool: &SqlitePool, user_location_id: i64) -> Resu
 }
 
 // Event Operations
+/// Rows per statement when writing or deleting pickup events in bulk.
+const EVENT_BATCH_SIZE: usize = 250;
+
+/// Stores `events` as the upcoming pickups of `location_id`. Past events are skipped,
+/// and upcoming rows that are no longer in `events` are deleted.
+///
+/// Events with a UID are matched on it, so a pickup moved to another day keeps its row.
+/// Events without one are matched on their date and written in batches.
+///
+/// A feed without any upcoming event leaves the stored ones alone: that is far more
+/// often an upstream glitch than a location whose pickups were all cancelled, and the
+/// old events age out by themselves.
 pub async fn upsert_events(
     pool: &SqlitePool,
     location_id: &str,
@@ -183,50 +1169,84 @@ pub async fn upsert_events(
 ) -> Result<()> {
     let mut tx = pool.begin().await?;
 
-    let today = chrono::Local::now()
-        .date_naive()
-        .format("%Y-%m-%d")
-        .to_string();
-
-    sqlx::query("DELETE FROM pickup_events WHERE location_id = ? AND date >= ?")
-        .bind(location_id)
-        .bind(&today)
-        .execute(&mut *tx)
-        .await?;
-
-    let mut buffer: Vec<(&str, String, &str)> = Vec::with_capacity(250);
+    let today = chrono::Local::now().date_naive();
 
+    let mut kept = HashSet::new();
+    let mut seen_uids = HashSet::new();
+    let mut without_uid = HashSet::new();
     for event in events {
-        let date_str = event.date.format("%Y-%m-%d").to_string();
-        if date_str < today {
+        if event.date < today {
             continue;
         }
 
         for waste in &event.waste_types {
-            buffer.push((location_id, date_str.clone(), waste.as_str()));
-
-            if buffer.len() >= 250 {
-                let mut query_builder: QueryBuilder<Sqlite> =
-                    QueryBuilder::new("INSERT INTO pickup_events (location_id, date, waste_type) ");
-
-                query_builder.push_values(&buffer, |mut b, (loc, date, waste)| {
-                    b.push_bind(loc).push_bind(date).push_bind(waste);
-                });
-
-                query_builder.build().execute(&mut *tx).await?;
-                buffer.clear();
+            let waste_type = canonical_waste_type(waste.as_str())?;
+            // A UID repeated within the feed can't tell its events apart
+            let uid = event
+                .uid
+                .as_deref()
+                .filter(|uid| seen_uids.insert((uid.to_string(), waste_type.clone())));
+            match uid {
+                Some(uid) => {
+                    let id =
+                        upsert_event_by_uid(&mut tx, location_id, event.date, &waste_type, uid)
+                            .await?;
+                    kept.insert(id);
+                }
+                None => {
+                    without_uid.insert((event.date, waste_type));
+                }
             }
         }
     }
 
-    if !buffer.is_empty() {
+    let without_uid: Vec<(NaiveDate, String)> = without_uid.into_iter().collect();
+    for chunk in without_uid.chunks(EVENT_BATCH_SIZE) {
         let mut query_builder: QueryBuilder<Sqlite> =
             QueryBuilder::new("INSERT INTO pickup_events (location_id, date, waste_type) ");
-
-        query_builder.push_values(&buffer, |mut b, (loc, date, waste)| {
-            b.push_bind(loc).push_bind(date).push_bind(waste);
+        query_builder.push_values(chunk, |mut b, (date, waste_type)| {
+            b.push_bind(location_id)
+                .push_bind(date)
+                .push_bind(waste_type);
         });
+        query_builder.push(
+            " ON CONFLICT(location_id, date, waste_type) DO UPDATE SET date = excluded.date
+             RETURNING id",
+        );
+        let ids: Vec<i64> = query_builder
+            .build_query_scalar()
+            .fetch_all(&mut *tx)
+            .await?;
+        kept.extend(ids);
+    }
 
+    let upcoming: Vec<i64> =
+        sqlx::query_scalar("SELECT id FROM pickup_events WHERE location_id = ? AND date >= ?")
+            .bind(location_id)
+            .bind(today)
+            .fetch_all(&mut *tx)
+            .await?;
+    if kept.is_empty() && !upcoming.is_empty() {
+        warn!(
+            "Feed for {} has no upcoming events; keeping the {} stored ones",
+            location_id,
+            upcoming.len()
+        );
+        tx.commit().await?;
+        return Ok(());
+    }
+    let stale: Vec<i64> = upcoming
+        .into_iter()
+        .filter(|id| !kept.contains(id))
+        .collect();
+    for chunk in stale.chunks(EVENT_BATCH_SIZE) {
+        let mut query_builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new("DELETE FROM pickup_events WHERE id IN (");
+        let mut ids = query_builder.separated(", ");
+        for id in chunk {
+            ids.push_bind(id);
+        }
+        ids.push_unseparated(")");
         query_builder.build().execute(&mut *tx).await?;
     }
 
@@ -234,6 +1254,310 @@ pub async fn upsert_events(
     Ok(())
 }
 
+/// Moves the row of the event `uid` to `date`, or inserts it. Returns the row's id.
+async fn upsert_event_by_uid(
+    conn: &mut sqlx::SqliteConnection,
+    location_id: &str,
+    date: NaiveDate,
+    waste_type: &str,
+    uid: &str,
+) -> Result<i64> {
+    // Whatever else sits on that day for the type is replaced by this event
+    sqlx::query(
+        "DELETE FROM pickup_events
+         WHERE location_id = ? AND date = ? AND waste_type = ? AND uid IS NOT ?",
+    )
+    .bind(location_id)
+    .bind(date)
+    .bind(waste_type)
+    .bind(uid)
+    .execute(&mut *conn)
+    .await?;
+
+    let moved: Option<i64> = sqlx::query_scalar(
+        "UPDATE pickup_events SET date = ?
+         WHERE location_id = ? AND uid = ? AND waste_type = ?
+         RETURNING id",
+    )
+    .bind(date)
+    .bind(location_id)
+    .bind(uid)
+    .bind(waste_type)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(id) = moved {
+        return Ok(id);
+    }
+
+    let id = sqlx::query_scalar(
+        "INSERT INTO pickup_events (location_id, date, waste_type, uid) VALUES (?, ?, ?, ?)
+         RETURNING id",
+    )
+    .bind(location_id)
+    .bind(date)
+    .bind(waste_type)
+    .bind(uid)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(id)
+}
+
+/// Days of past pickups kept around before `prune_old_events` removes them.
+const EVENT_RETENTION_DAYS: i64 = 30;
+
+/// Deletes pickups older than the retention window, locations nobody has configured
+/// anymore with all their events and cached feeds, and queued retries for past pickups.
+/// Returns the number of events removed.
+pub async fn prune_old_events(pool: &SqlitePool) -> Result<u64> {
+    let cutoff = chrono::Local::now().date_naive() - chrono::Duration::days(EVENT_RETENTION_DAYS);
+
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "DELETE FROM locations
+         WHERE location_id NOT IN (SELECT DISTINCT location_id FROM user_locations)",
+    )
+    .execute(&mut *tx)
+    .await?;
+    let result = sqlx::query(
+        "DELETE FROM pickup_events
+         WHERE date < ?
+            OR location_id NOT IN (SELECT location_id FROM locations)",
+    )
+    .bind(cutoff)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        "DELETE FROM raw_ical_cache
+         WHERE location_id NOT IN (SELECT location_id FROM locations)",
+    )
+    .execute(&mut *tx)
+    .await?;
+    // Retries for pickups that are over won't be sent anymore
+    sqlx::query("DELETE FROM failed_notifications WHERE date < ?")
+        .bind(chrono::Local::now().date_naive())
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn count_upcoming_events(
+    pool: &SqlitePool,
+    location_id: &str,
+    from: NaiveDate,
+) -> Result<i64> {
+    let count = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM pickup_events WHERE location_id = ? AND date >= ?",
+    )
+    .bind(location_id)
+    .bind(from)
+    .fetch_one(pool)
+    .await?;
+    Ok(count)
+}
+
+/// The user's next `limit` pickup days from `from` on, across all their locations, with
+/// the subscribed types collected on each day.
+pub async fn get_user_pickups(
+    pool: &SqlitePool,
+    chat_id: i64,
+    from: NaiveDate,
+    limit: usize,
+) -> Result<Vec<(NaiveDate, Vec<String>)>> {
+    let rows: Vec<(NaiveDate, String)> = sqlx::query_as(
+        "SELECT DISTINCT e.date, e.waste_type
+         FROM user_locations ul
+         JOIN subscriptions s ON s.user_location_id = ul.id
+         JOIN pickup_events e ON e.location_id = ul.location_id AND e.waste_type = s.waste_type
+         WHERE ul.user_id = ? AND e.date >= ?
+         ORDER BY e.date, e.waste_type",
+    )
+    .bind(chat_id)
+    .bind(from)
+    .fetch_all(pool)
+    .await?;
+    let mut pickups: Vec<(NaiveDate, Vec<String>)> = Vec::new();
+    for (date, waste_type) in rows {
+        if let Some((last, types)) = pickups.last_mut() {
+            if *last == date {
+                types.push(waste_type);
+                continue;
+            }
+        }
+        if pickups.len() == limit {
+            break;
+        }
+        pickups.push((date, vec![waste_type]));
+    }
+    Ok(pickups)
+}
+
+/// The first pickup on or after `from` of any waste type the user location is subscribed
+/// to, with every subscribed type collected on that day.
+pub async fn get_next_pickup(
+    pool: &SqlitePool,
+    user_location_id: i64,
+    from: NaiveDate,
+) -> Result<Option<(NaiveDate, Vec<String>)>> {
+    let rows: Vec<(NaiveDate, String)> = sqlx::query_as(
+        "WITH subscribed AS (
+             SELECT e.date, e.waste_type
+             FROM user_locations ul
+             JOIN subscriptions s ON s.user_location_id = ul.id
+             JOIN pickup_events e ON e.location_id = ul.location_id
+                                 AND e.waste_type = s.waste_type
+             WHERE ul.id = ? AND e.date >= ?
+         )
+         SELECT date, waste_type FROM subscribed
+         WHERE date = (SELECT MIN(date) FROM subscribed)
+         ORDER BY waste_type",
+    )
+    .bind(user_location_id)
+    .bind(from)
+    .fetch_all(pool)
+    .await?;
+    let Some(&(date, _)) = rows.first() else {
+        return Ok(None);
+    };
+    Ok(Some((
+        date,
+        rows.into_iter().map(|(_, waste_type)| waste_type).collect(),
+    )))
+}
+
+/// A user of a location, with what's needed to write to them about it.
+pub struct LocationUser {
+    pub chat_id: i64,
+    pub alias: Option<String>,
+    pub language: Lang,
+}
+
+/// Returns the users of `location_id` that still need the empty-feed warning and
+/// marks them as warned. With `remind_after_days`, users warned at least that many
+/// days ago are returned again.
+pub async fn take_empty_feed_warnings(
+    pool: &SqlitePool,
+    location_id: &str,
+    remind_after_days: Option<i64>,
+) -> Result<Vec<LocationUser>> {
+    let mut tx = pool.begin().await?;
+
+    // Warnings from before the timestamp existed count as long ago
+    let due = "ul.location_id = ?
+         AND (ul.empty_feed_warned = 0
+              OR (? IS NOT NULL AND (ul.empty_feed_warned_at IS NULL
+                  OR ul.empty_feed_warned_at <= datetime('now', printf('-%d days', ?)))))";
+
+    let rows = sqlx::query(&format!(
+        "SELECT ul.user_id, ul.alias, u.language
+         FROM user_locations ul
+         JOIN users u ON u.id = ul.user_id
+         WHERE {}",
+        due
+    ))
+    .bind(location_id)
+    .bind(remind_after_days)
+    .bind(remind_after_days)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    sqlx::query(&format!(
+        "UPDATE user_locations AS ul
+         SET empty_feed_warned = 1, empty_feed_warned_at = CURRENT_TIMESTAMP
+         WHERE {}",
+        due
+    ))
+    .bind(location_id)
+    .bind(remind_after_days)
+    .bind(remind_after_days)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    let mut users = Vec::new();
+    for row in rows {
+        users.push(LocationUser {
+            chat_id: row.try_get("user_id")?,
+            alias: row.try_get("alias")?,
+            language: Lang::from_code(row.try_get("language")?),
+        });
+    }
+    Ok(users)
+}
+
+/// Takes back the empty-feed warning of one user whose message couldn't be sent, so
+/// the next check tries again.
+pub async fn release_empty_feed_warning(
+    pool: &SqlitePool,
+    location_id: &str,
+    chat_id: i64,
+) -> Result<()> {
+    sqlx::query(
+        "UPDATE user_locations SET empty_feed_warned = 0
+         WHERE location_id = ? AND user_id = ?",
+    )
+    .bind(location_id)
+    .bind(chat_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn reset_empty_feed_warning(pool: &SqlitePool, location_id: &str) -> Result<()> {
+    sqlx::query("UPDATE user_locations SET empty_feed_warned = 0 WHERE location_id = ?")
+        .bind(location_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Everyone who has `location_id` configured.
+pub async fn get_location_users(pool: &SqlitePool, location_id: &str) -> Result<Vec<LocationUser>> {
+    let rows = sqlx::query(
+        "SELECT ul.user_id, ul.alias, u.language
+         FROM user_locations ul
+         JOIN users u ON u.id = ul.user_id
+         WHERE ul.location_id = ?
+         ORDER BY ul.user_id",
+    )
+    .bind(location_id)
+    .fetch_all(pool)
+    .await?;
+
+    let mut users = Vec::new();
+    for row in rows {
+        users.push(LocationUser {
+            chat_id: row.try_get("user_id")?,
+            alias: row.try_get("alias")?,
+            language: Lang::from_code(row.try_get("language")?),
+        });
+    }
+    Ok(users)
+}
+
+/// Flags `location_id` as needing attention. Returns true only if it wasn't flagged
+/// already, so its users are told once.
+pub async fn mark_location_stale(pool: &SqlitePool, location_id: &str) -> Result<bool> {
+    let result = sqlx::query(
+        "UPDATE locations SET needs_attention = 1 WHERE location_id = ? AND needs_attention = 0",
+    )
+    .bind(location_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Clears the flag once the location has upcoming pickups again.
+pub async fn clear_location_stale(pool: &SqlitePool, location_id: &str) -> Result<()> {
+    sqlx::query("UPDATE locations SET needs_attention = 0 WHERE location_id = ?")
+        .bind(location_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
 // Query for notifications
 pub struct NotificationTask {
     pub chat_id: i64,
@@ -241,36 +1565,297 @@ pub struct NotificationTask {
     pub location_alias: Option<String>,
     pub location_id: String,
     pub notify_offset: i64,
+    pub event_date: NaiveDate,
+    pub language: Lang,
+    /// The user's own reminder line, if they set one.
+    pub message_template: Option<String>,
+    /// Which of the location's reminders this is: empty for the main one, `HH:MM/offset`
+    /// for an extra one from notify_times. Each is logged separately.
+    pub slot: String,
 }
 
 pub async fn get_users_to_notify(
     pool: &SqlitePool,
     check_time: &str,
-    current_date: &str,
-    next_date: &str,
+    current_date: NaiveDate,
+    next_date: NaiveDate,
 ) -> Result<Vec<NotificationTask>> {
     // Logic:
-    // Query users with matching notify_time.
+    // Every user location has its main reminder plus any extra ones from notify_times
+    // (skipping those that repeat the main one), each with its own slot.
+    // Without a custom lead time, query reminders with matching notify_time
     // AND check events:
     // (notify_offset = 0 AND date = current_date) OR (notify_offset = 1 AND date = next_date)
+    // With one, the reminder is due at PICKUP_HOUR on the event date minus the lead time.
+    // AND skip anything already recorded in notified_log for that slot.
+    // AND skip users whose mute_until hasn't passed yet.
+    // AND skip users who get the weekly digest instead.
+    // notify_offset is reported from the event date, so custom lead times get the right
+    // "today"/"tomorrow" wording too.
 
     let rows = sqlx::query(
         r#"
-        SELECT u.id as chat_id, s.waste_type, ul.alias, ul.location_id, ul.notify_offset
+        WITH reminders AS (
+            SELECT id AS user_location_id, '' AS slot, notify_time, notify_offset,
+                   notify_offset_hours
+            FROM user_locations
+            UNION ALL
+            SELECT nt.user_location_id, nt.notify_time || '/' || nt.notify_offset,
+                   nt.notify_time, nt.notify_offset, NULL
+            FROM notify_times nt
+            JOIN user_locations main ON main.id = nt.user_location_id
+            WHERE NOT (main.notify_offset_hours IS NULL
+                       AND main.notify_time = nt.notify_time
+                       AND main.notify_offset = nt.notify_offset)
+        )
+        SELECT u.id as chat_id, s.waste_type, ul.alias, ul.location_id,
+               CASE WHEN e.date = ? THEN 0 ELSE 1 END as notify_offset,
+               e.date as event_date, u.language, u.message_template, r.slot
         FROM users u
         JOIN user_locations ul ON u.id = ul.user_id
+        JOIN reminders r ON r.user_location_id = ul.id
         JOIN subscriptions s ON ul.id = s.user_location_id
         JOIN pickup_events e ON ul.location_id = e.location_id AND s.waste_type = e.waste_type
-        WHERE ul.notify_time = ?
-          AND (
-               (ul.notify_offset = 0 AND e.date = ?)
-            OR (ul.notify_offset = 1 AND e.date = ?)
+        WHERE (
+               (r.notify_offset_hours IS NULL
+                AND r.notify_time = ?
+                AND (
+                     (r.notify_offset = 0 AND e.date = ?)
+                  OR (r.notify_offset = 1 AND e.date = ?)
+                ))
+            OR (r.notify_offset_hours IS NOT NULL
+                AND e.date IN (?, ?)
+                AND datetime(e.date, printf('%+d hours', ? - r.notify_offset_hours))
+                    = datetime(? || ' ' || ?))
+          )
+          AND (u.mute_until IS NULL OR u.mute_until < ?)
+          AND u.notify_mode = 'per_event'
+          AND NOT EXISTS (
+              SELECT 1 FROM notified_log n
+              WHERE n.chat_id = u.id
+                AND n.location_id = ul.location_id
+                AND n.waste_type = s.waste_type
+                AND n.date = e.date
+                AND n.slot = r.slot
           )
         "#,
     )
+    .bind(current_date)
     .bind(check_time)
     .bind(current_date)
     .bind(next_date)
+    .bind(current_date)
+    .bind(next_date)
+    .bind(PICKUP_HOUR)
+    .bind(current_date)
+    .bind(check_time)
+    .bind(current_date)
+    .fetch_all(pool)
+    .await?;
+
+    let mut tasks = Vec::new();
+    for row in rows {
+        tasks.push(NotificationTask {
+            chat_id: row.try_get("chat_id")?,
+            waste_type: row.try_get("waste_type")?,
+            location_alias: row.try_get("alias")?,
+            location_id: row.try_get("location_id")?,
+            notify_offset: row.try_get("notify_offset")?,
+            event_date: row.try_get("event_date")?,
+            language: Lang::from_code(row.try_get("language")?),
+            message_template: row.try_get("message_template")?,
+            slot: row.try_get("slot")?,
+        });
+    }
+    Ok(tasks)
+}
+
+/// A subscribed pickup, as listed in the weekly digest.
+pub struct UpcomingEvent {
+    pub chat_id: i64,
+    pub location_id: String,
+    pub location_alias: Option<String>,
+    pub waste_type: String,
+    pub date: NaiveDate,
+    pub language: Lang,
+}
+
+/// Users who asked to hear about weeks without pickups and have none of their
+/// subscribed types due from `from` to `to` at any of their locations. Users without
+/// locations, or on vacation, are left out.
+pub async fn get_quiet_week_users(
+    pool: &SqlitePool,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<(i64, Lang)>> {
+    let rows = sqlx::query(
+        "SELECT u.id, u.language
+         FROM users u
+         WHERE u.quiet_week_notice = 1
+           AND (u.mute_until IS NULL OR u.mute_until < ?)
+           AND EXISTS (SELECT 1 FROM user_locations ul WHERE ul.user_id = u.id)
+           AND NOT EXISTS (
+               SELECT 1
+               FROM user_locations ul
+               JOIN subscriptions s ON ul.id = s.user_location_id
+               JOIN pickup_events e
+                 ON ul.location_id = e.location_id AND s.waste_type = e.waste_type
+               WHERE ul.user_id = u.id AND e.date BETWEEN ? AND ?)
+         ORDER BY u.id",
+    )
+    .bind(from)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await?;
+
+    let mut users = Vec::new();
+    for row in rows {
+        users.push((
+            row.try_get("id")?,
+            Lang::from_code(row.try_get("language")?),
+        ));
+    }
+    Ok(users)
+}
+
+/// Subscribed pickups from `from` to `to` (inclusive) for all users in `mode`, ordered by
+/// chat, date and location. Users muted on `from` are left out.
+pub async fn get_events_in_range(
+    pool: &SqlitePool,
+    mode: NotifyMode,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<UpcomingEvent>> {
+    let rows = sqlx::query(
+        "SELECT u.id AS chat_id, ul.location_id, ul.alias, s.waste_type, e.date, u.language
+         FROM users u
+         JOIN user_locations ul ON u.id = ul.user_id
+         JOIN subscriptions s ON ul.id = s.user_location_id
+         JOIN pickup_events e ON ul.location_id = e.location_id AND s.waste_type = e.waste_type
+         WHERE u.notify_mode = ?
+           AND e.date BETWEEN ? AND ?
+           AND (u.mute_until IS NULL OR u.mute_until < ?)
+         ORDER BY u.id, e.date, ul.id, s.waste_type",
+    )
+    .bind(mode.code())
+    .bind(from)
+    .bind(to)
+    .bind(from)
+    .fetch_all(pool)
+    .await?;
+
+    let mut events = Vec::new();
+    for row in rows {
+        events.push(UpcomingEvent {
+            chat_id: row.try_get("chat_id")?,
+            location_id: row.try_get("location_id")?,
+            location_alias: row.try_get("alias")?,
+            waste_type: row.try_get("waste_type")?,
+            date: row.try_get("date")?,
+            language: Lang::from_code(row.try_get("language")?),
+        });
+    }
+    Ok(events)
+}
+
+/// Logs a delivered reminder; `slot` is `NotificationTask::slot`.
+pub async fn record_notification(
+    pool: &SqlitePool,
+    chat_id: i64,
+    location_id: &str,
+    waste_type: &str,
+    date: NaiveDate,
+    slot: &str,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO notified_log (chat_id, location_id, waste_type, date, slot)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(chat_id)
+    .bind(location_id)
+    .bind(waste_type)
+    .bind(date)
+    .bind(slot)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Queues a notification that failed to send for another try and returns how many
+/// attempts have failed so far.
+pub async fn record_failed_notification(
+    pool: &SqlitePool,
+    task: &NotificationTask,
+    error: &str,
+) -> Result<i64> {
+    let attempts = sqlx::query_scalar(
+        "INSERT INTO failed_notifications
+             (chat_id, location_id, waste_type, date, slot, last_error)
+         VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT DO UPDATE SET attempts = attempts + 1, last_error = excluded.last_error,
+                                   failed_at = CURRENT_TIMESTAMP
+         RETURNING attempts",
+    )
+    .bind(task.chat_id)
+    .bind(&task.location_id)
+    .bind(&task.waste_type)
+    .bind(task.event_date)
+    .bind(&task.slot)
+    .bind(error)
+    .fetch_one(pool)
+    .await?;
+    Ok(attempts)
+}
+
+/// Removes a notification from the retry queue, after it was sent or given up on.
+pub async fn clear_failed_notification(pool: &SqlitePool, task: &NotificationTask) -> Result<()> {
+    sqlx::query(
+        "DELETE FROM failed_notifications
+         WHERE chat_id = ? AND location_id = ? AND waste_type = ? AND date = ? AND slot = ?",
+    )
+    .bind(task.chat_id)
+    .bind(&task.location_id)
+    .bind(&task.waste_type)
+    .bind(task.event_date)
+    .bind(&task.slot)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Queued notifications that are still worth sending: the pickup hasn't passed, the
+/// subscription still exists and nothing was delivered in the meantime. Mute and the
+/// weekly digest apply as in `get_users_to_notify`.
+pub async fn get_failed_notifications(
+    pool: &SqlitePool,
+    current_date: NaiveDate,
+) -> Result<Vec<NotificationTask>> {
+    let rows = sqlx::query(
+        "SELECT f.chat_id, f.waste_type, ul.alias, f.location_id,
+                CASE WHEN f.date = ? THEN 0 ELSE 1 END AS notify_offset,
+                f.date AS event_date, u.language, u.message_template, f.slot
+         FROM failed_notifications f
+         JOIN users u ON u.id = f.chat_id
+         JOIN user_locations ul ON ul.user_id = f.chat_id AND ul.location_id = f.location_id
+         JOIN subscriptions s ON s.user_location_id = ul.id AND s.waste_type = f.waste_type
+         WHERE f.date >= ?
+           AND (u.mute_until IS NULL OR u.mute_until < ?)
+           AND u.notify_mode = 'per_event'
+           AND NOT EXISTS (
+               SELECT 1 FROM notified_log n
+               WHERE n.chat_id = f.chat_id
+                 AND n.location_id = f.location_id
+                 AND n.waste_type = f.waste_type
+                 AND n.date = f.date
+                 AND n.slot = f.slot
+           )
+         ORDER BY f.chat_id, ul.id, f.date",
+    )
+    .bind(current_date)
+    .bind(current_date)
+    .bind(current_date)
     .fetch_all(pool)
     .await?;
 
@@ -282,7 +1867,148 @@ pub async fn get_users_to_notify(
             location_alias: row.try_get("alias")?,
             location_id: row.try_get("location_id")?,
             notify_offset: row.try_get("notify_offset")?,
+            event_date: row.try_get("event_date")?,
+            language: Lang::from_code(row.try_get("language")?),
+            message_template: row.try_get("message_template")?,
+            slot: row.try_get("slot")?,
         });
     }
     Ok(tasks)
 }
+
+/// What a chat has received so far, for /mystats.
+#[derive(Debug, PartialEq)]
+pub struct NotificationStats {
+    /// Pickups reminded of, one per location and day.
+    pub reminders: i64,
+    /// Local time of the latest reminder.
+    pub last_sent: Option<NaiveDateTime>,
+}
+
+pub async fn notification_stats(pool: &SqlitePool, chat_id: i64) -> Result<NotificationStats> {
+    let row = sqlx::query(
+        "SELECT COUNT(DISTINCT location_id || '/' || date) AS reminders,
+                datetime(MAX(sent_at), 'localtime') AS last_sent
+         FROM notified_log
+         WHERE chat_id = ?",
+    )
+    .bind(chat_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(NotificationStats {
+        reminders: row.try_get("reminders")?,
+        last_sent: row.try_get("last_sent")?,
+    })
+}
+
+/// Number of notifications logged with a `sent_at` on the given day.
+pub async fn count_notifications_on(pool: &SqlitePool, date: NaiveDate) -> Result<i64> {
+    let count = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM notified_log WHERE date(sent_at, 'localtime') = ?",
+    )
+    .bind(date)
+    .fetch_one(pool)
+    .await?;
+    Ok(count)
+}
+
+// Snoozed reminders
+/// Schedules a one-off re-send of the reminder for `event_date` at `fire_at`. Snoozing
+/// the same reminder again replaces its waste types.
+pub async fn add_snooze(
+    pool: &SqlitePool,
+    chat_id: i64,
+    fire_at: NaiveDateTime,
+    event_date: NaiveDate,
+    waste_types: &[String],
+) -> Result<()> {
+    let waste_types = waste_types
+        .iter()
+        .map(|w| canonical_waste_type(w))
+        .collect::<Result<Vec<_>>>()?
+        .join(",");
+    sqlx::query(
+        "INSERT INTO snoozes (chat_id, fire_at, event_date, waste_types) VALUES (?, ?, ?, ?)
+         ON CONFLICT(chat_id, fire_at, event_date) DO UPDATE SET waste_types = excluded.waste_types",
+    )
+    .bind(chat_id)
+    .bind(fire_at)
+    .bind(event_date)
+    .bind(waste_types)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// A snoozed reminder that is due.
+pub struct Snooze {
+    pub chat_id: i64,
+    pub event_date: NaiveDate,
+    pub waste_types: Vec<String>,
+    pub language: Lang,
+}
+
+/// Returns the snoozes due at `now` and deletes them, so each fires once.
+pub async fn take_due_snoozes(pool: &SqlitePool, now: NaiveDateTime) -> Result<Vec<Snooze>> {
+    let mut tx = pool.begin().await?;
+
+    let rows = sqlx::query(
+        "SELECT s.chat_id, s.event_date, s.waste_types, u.language
+         FROM snoozes s
+         JOIN users u ON u.id = s.chat_id
+         WHERE s.fire_at <= ?
+         ORDER BY s.fire_at",
+    )
+    .bind(now)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM snoozes WHERE fire_at <= ?")
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    let mut snoozes = Vec::new();
+    for row in rows {
+        let waste_types: String = row.try_get("waste_types")?;
+        snoozes.push(Snooze {
+            chat_id: row.try_get("chat_id")?,
+            event_date: row.try_get("event_date")?,
+            waste_types: waste_types.split(',').map(str::to_string).collect(),
+            language: Lang::from_code(row.try_get("language")?),
+        });
+    }
+    Ok(snoozes)
+}
+
+// Dialogue state
+pub async fn get_dialogue_state(pool: &SqlitePool, chat_id: i64) -> Result<Option<String>> {
+    let state = sqlx::query_scalar("SELECT state FROM dialogues WHERE chat_id = ?")
+        .bind(chat_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(state)
+}
+
+pub async fn set_dialogue_state(pool: &SqlitePool, chat_id: i64, state: &str) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO dialogues (chat_id, state) VALUES (?, ?)
+         ON CONFLICT(chat_id) DO UPDATE SET state = excluded.state, updated_at = CURRENT_TIMESTAMP",
+    )
+    .bind(chat_id)
+    .bind(state)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns whether a state was stored.
+pub async fn delete_dialogue_state(pool: &SqlitePool, chat_id: i64) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM dialogues WHERE chat_id = ?")
+        .bind(chat_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
@@ -1,9 +1,13 @@
 use chrono::NaiveDate;
 use ical::parser::ical::component::IcalEvent;
 use ical::IcalParser;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::io::BufReader;
 use std::str::FromStr;
 use thiserror::Error;
+use tracing::warn;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum WasteType {
@@ -12,6 +16,8 @@ pub enum WasteType {
     Paper,
     Yellow,
     ChristmasTree,
+    Bulky,
+    Hazardous,
     Other(String),
 }
 
@@ -23,10 +29,45 @@ impl WasteType {
             WasteType::Paper => "Papier",
             WasteType::Yellow => "Gelb",
             WasteType::ChristmasTree => "Weihnachtsbaum",
+            WasteType::Bulky => "Sperrmüll",
+            WasteType::Hazardous => "Schadstoff",
             WasteType::Other(s) => s.as_str(),
         }
     }
 
+    /// Bin-colored emoji shown next to the German label in messages.
+    pub fn emoji(&self) -> &'static str {
+        match self {
+            WasteType::Bio => "🟤",
+            WasteType::Rest => "⚫",
+            WasteType::Paper => "🔵",
+            WasteType::Yellow => "🟡",
+            WasteType::ChristmasTree => "🎄",
+            WasteType::Bulky => "🛋️",
+            WasteType::Hazardous => "☣️",
+            WasteType::Other(_) => "🗑️",
+        }
+    }
+
+    /// Emoji and label, e.g. "🟤 Bio".
+    pub fn label(&self) -> String {
+        format!("{} {}", self.emoji(), self.as_str())
+    }
+
+    /// One line on what goes into this bin, in German like the labels.
+    pub fn description(&self) -> &'static str {
+        match self {
+            WasteType::Bio => "Küchen- und Gartenabfälle, Obst- und Gemüsereste",
+            WasteType::Rest => "Alles, was nicht verwertbar ist, z. B. Hygieneartikel, Asche",
+            WasteType::Paper => "Papier, Pappe, Kartons, Zeitungen",
+            WasteType::Yellow => "Verpackungen aus Plastik, Metall und Verbundstoffen",
+            WasteType::ChristmasTree => "Abgeschmückte Weihnachtsbäume",
+            WasteType::Bulky => "Möbel und große Gegenstände aus dem Haushalt",
+            WasteType::Hazardous => "Farben, Lacke, Batterien, Chemikalien",
+            WasteType::Other(_) => "Sonstige Abfuhr laut Abfallkalender",
+        }
+    }
+
     pub fn supported_types() -> Vec<WasteType> {
         vec![
             WasteType::Bio,
@@ -34,6 +75,8 @@ impl WasteType {
             WasteType::Paper,
             WasteType::Yellow,
             WasteType::ChristmasTree,
+            WasteType::Bulky,
+            WasteType::Hazardous,
         ]
     }
 
@@ -52,15 +95,90 @@ impl FromStr for WasteType {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let normalized = s.trim();
-        match normalized {
-            "Bio" | "Biotonne" => Ok(WasteType::Bio),
-            "Rest" | "Restmüll" | "Restabfall" => Ok(WasteType::Rest),
-            "Papier" | "Pappe" | "Blaue Tonne" => Ok(WasteType::Paper),
-            "Gelb" | "Gelbe Tonne" | "Gelber Sack" => Ok(WasteType::Yellow),
-            "Weihnachtsbaum" | "Weihnachtsbäume" => Ok(WasteType::ChristmasTree),
-            _ => Ok(WasteType::Other(normalized.to_string())),
+        let folded = fold_name(normalized);
+        let known = known_type(&folded).or_else(|| known_type(&strip_qualifiers(&folded)));
+        Ok(known.unwrap_or_else(|| WasteType::Other(normalized.to_string())))
+    }
+}
+
+/// Serialized as the canonical name from `as_str`, and read back through `from_str`.
+impl Serialize for WasteType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for WasteType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        let Ok(waste_type) = name.parse();
+        Ok(waste_type)
+    }
+}
+
+/// The type a folded name stands for, if it is one of the known spellings.
+fn known_type(folded: &str) -> Option<WasteType> {
+    match folded {
+        "bio" | "biotonne" => Some(WasteType::Bio),
+        "rest" | "restmuell" | "restabfall" => Some(WasteType::Rest),
+        "papier" | "pappe" | "blaue tonne" => Some(WasteType::Paper),
+        "gelb" | "gelbe tonne" | "gelber sack" => Some(WasteType::Yellow),
+        "weihnachtsbaum" | "weihnachtsbaeume" => Some(WasteType::ChristmasTree),
+        "sperrmuell" | "sperrabfall" => Some(WasteType::Bulky),
+        "schadstoff" | "schadstoffe" | "schadstoffmobil" => Some(WasteType::Hazardous),
+        _ => None,
+    }
+}
+
+/// Words some feeds add to a type name without changing the type, in folded form.
+const TYPE_QUALIFIERS: [&str; 4] = ["abholung", "leerung", "taeglich", "woechentlich"];
+
+/// Drops parenthetical remarks and qualifier words from a folded name, e.g.
+/// "biotonne (abholung)" or "restmuell 14-taeglich", leaving the type name itself.
+fn strip_qualifiers(folded: &str) -> String {
+    let mut without_parens = String::with_capacity(folded.len());
+    let mut depth = 0;
+    for c in folded.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            _ if depth == 0 => without_parens.push(c),
+            _ => {}
+        }
+    }
+    without_parens
+        .split_whitespace()
+        .filter(|word| {
+            // A rhythm like "14-taeglich" or "4-woechentlich" counts as its last part
+            let word = match word.split_once('-') {
+                Some((n, rest)) if n.bytes().all(|b| b.is_ascii_digit()) => rest,
+                _ => word,
+            };
+            !TYPE_QUALIFIERS.contains(&word)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Lowercases, transliterates umlauts (ä→ae, ß→ss) and collapses whitespace so
+/// spelling variants like "Restmuell" or "gelbe  Tonne" match the known names.
+fn fold_name(name: &str) -> String {
+    let mut folded = String::with_capacity(name.len());
+    for word in name.split_whitespace() {
+        if !folded.is_empty() {
+            folded.push(' ');
+        }
+        for c in word.chars().flat_map(char::to_lowercase) {
+            match c {
+                'ä' => folded.push_str("ae"),
+                'ö' => folded.push_str("oe"),
+                'ü' => folded.push_str("ue"),
+                'ß' => folded.push_str("ss"),
+                _ => folded.push(c),
+            }
         }
     }
+    folded
 }
 
 impl std::fmt::Display for WasteType {
@@ -69,10 +187,16 @@ impl std::fmt::Display for WasteType {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PickupEvent {
     pub date: NaiveDate,
     pub waste_types: Vec<WasteType>,
+    /// Whether DTSTART was a plain date (`VALUE=DATE`) rather than a date-time. Pickups
+    /// are all-day events and reminders only look at `date`, so a timed event is handled
+    /// like any other; the flag is only reported by the `dump` command.
+    pub all_day: bool,
+    /// The feed's UID, which stays the same when a pickup is moved to another day.
+    pub uid: Option<String>,
 }
 
 #[derive(Error, Debug)]
@@ -91,67 +215,306 @@ pub fn is_valid_location_id(id: &str) -> bool {
     !id.is_empty() && id.len() <= 20 && id.chars().all(|c| c.is_alphanumeric())
 }
 
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum LocationIdError {
+    #[error("No location ID found")]
+    Missing,
+    #[error("Several possible location IDs found")]
+    Ambiguous,
+}
+
+/// Label in front of the location ID, both in pasted text ("Standort-ID: 12345") and as
+/// the query parameter of the city's calendar links ("?STANDORT=12345").
+const LOCATION_ID_LABEL: &str = "standort";
+
+/// Pulls the location ID out of what a user pasted: a bare ID, text like
+/// "Standort-ID: 12345", or a calendar link with a `STANDORT` parameter. Anything else
+/// counts as missing rather than guessing at numbers in it.
+pub fn extract_location_id(text: &str) -> Result<String, LocationIdError> {
+    let text = text.trim();
+    if is_valid_location_id(text) {
+        return Ok(text.to_string());
+    }
+    let mut unique = labelled_values(text)
+        .into_iter()
+        .collect::<HashSet<_>>()
+        .into_iter();
+    match (unique.next(), unique.next()) {
+        (Some(id), None) if is_valid_location_id(id) => Ok(id.to_string()),
+        (Some(_), Some(_)) => Err(LocationIdError::Ambiguous),
+        _ => Err(LocationIdError::Missing),
+    }
+}
+
+/// The values following `Standort`, `Standort-ID` or `Standort ID` and a `:` or `=`,
+/// each up to the next space or URL separator.
+fn labelled_values(text: &str) -> Vec<&str> {
+    // ASCII lowercasing keeps byte offsets, so they index into `text` as well
+    let lower = text.to_ascii_lowercase();
+    lower
+        .match_indices(LOCATION_ID_LABEL)
+        .filter_map(|(start, label)| {
+            let rest = &text[start + label.len()..];
+            let rest = ["-id", "_id", " id"]
+                .iter()
+                .find_map(|suffix| {
+                    let head = rest.get(..suffix.len())?;
+                    head.eq_ignore_ascii_case(suffix)
+                        .then(|| &rest[suffix.len()..])
+                })
+                .unwrap_or(rest);
+            let rest = rest.trim_start().strip_prefix([':', '='])?.trim_start();
+            let end = rest
+                .find(|c: char| c.is_whitespace() || matches!(c, '&' | '#' | ',' | ';'))
+                .unwrap_or(rest.len());
+            Some(&rest[..end])
+        })
+        .collect()
+}
+
+/// Whether a feed response is a web page rather than iCal. Some endpoints answer an
+/// unknown location ID with an HTML error page and status 200.
+pub fn looks_like_html(body: &str) -> bool {
+    let start = body.trim_start_matches('\u{feff}').trim_start();
+    let start = start.get(..9).unwrap_or(start).to_ascii_lowercase();
+    start.starts_with("<!doctype") || start.starts_with("<html")
+}
+
+/// Words that join two waste types in a summary, e.g. "Bio und Rest".
+const TYPE_CONJUNCTIONS: [&str; 3] = ["und", "sowie", "&"];
+
+/// Splits a summary into its waste type names: on commas and semicolons, and around
+/// the conjunctions in `TYPE_CONJUNCTIONS`. Whitespace within a name is collapsed.
+fn split_type_names(summary: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    for part in summary.split([',', ';']) {
+        let mut words: Vec<&str> = Vec::new();
+        for word in part.split_whitespace() {
+            if TYPE_CONJUNCTIONS
+                .iter()
+                .any(|c| word.eq_ignore_ascii_case(c))
+            {
+                names.push(words.join(" "));
+                words.clear();
+            } else {
+                words.push(word);
+            }
+        }
+        names.push(words.join(" "));
+    }
+    names.retain(|name| !name.is_empty());
+    names
+}
+
 pub fn normalize_waste_types(summary: &str) -> Vec<WasteType> {
-    summary
-        .split(',')
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
+    // Feeds occasionally repeat a type within one summary; keep the first occurrence only.
+    let mut seen = HashSet::new();
+    split_type_names(summary)
+        .iter()
         .map(|s| s.parse().expect("WasteType parsing is infallible"))
+        .filter(|w: &WasteType| seen.insert(w.clone()))
+        .collect()
+}
+
+/// Fewest intervals between pickups from which a rhythm is trusted.
+const MIN_RHYTHM_INTERVALS: usize = 3;
+
+/// A stretch without pickups of one waste type that is clearly longer than its rhythm,
+/// e.g. a missing date in a feed that otherwise has one every two weeks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PickupGap {
+    /// Last pickup before the gap.
+    pub after: NaiveDate,
+    /// First pickup after it.
+    pub before: NaiveDate,
+    /// The usual number of days between pickups.
+    pub interval_days: i64,
+}
+
+/// The usual number of days between the given pickups: the most common interval (the
+/// shorter one on a tie), or `None` if there are too few pickups or no interval repeats.
+pub fn typical_interval(dates: &[NaiveDate]) -> Option<i64> {
+    let mut dates = dates.to_vec();
+    dates.sort();
+    dates.dedup();
+    let intervals: Vec<i64> = dates.windows(2).map(|w| (w[1] - w[0]).num_days()).collect();
+    if intervals.len() < MIN_RHYTHM_INTERVALS {
+        return None;
+    }
+
+    let mut counts: Vec<(i64, usize)> = Vec::new();
+    for interval in intervals {
+        match counts.iter_mut().find(|(days, _)| *days == interval) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((interval, 1)),
+        }
+    }
+    counts
+        .into_iter()
+        .filter(|(_, count)| *count >= 2)
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+        .map(|(days, _)| days)
+}
+
+/// Finds gaps in the pickups of one waste type at one location. A gap is an interval
+/// more than half again as long as the typical one, so pickups moved by a few days
+/// around holidays don't count.
+pub fn find_pickup_gaps(dates: &[NaiveDate]) -> Vec<PickupGap> {
+    let Some(interval_days) = typical_interval(dates) else {
+        return Vec::new();
+    };
+    let mut dates = dates.to_vec();
+    dates.sort();
+    dates.dedup();
+    dates
+        .windows(2)
+        .filter(|w| (w[1] - w[0]).num_days() > interval_days + interval_days / 2)
+        .map(|w| PickupGap {
+            after: w[0],
+            before: w[1],
+            interval_days,
+        })
         .collect()
 }
 
-pub fn parse_ical(content: &str) -> Result<Vec<PickupEvent>, ParseError> {
+/// A parsed iCal feed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calendar {
+    /// Human-readable name of the location, from `X-WR-CALNAME` or the first event's `LOCATION`.
+    pub name: Option<String>,
+    pub events: Vec<PickupEvent>,
+}
+
+/// Turns CRLF and lone CR line endings into LF. The live feed uses CRLF, and proxies
+/// sometimes leave a mix of all three, which the parser doesn't cope with.
+fn normalize_line_endings(content: &str) -> Cow<'_, str> {
+    if !content.contains('\r') {
+        return Cow::Borrowed(content);
+    }
+    Cow::Owned(content.replace("\r\n", "\n").replace('\r', "\n"))
+}
+
+/// Parses an iCal feed into pickup events.
+///
+/// Feeds may wrap their events in several VCALENDAR blocks; events from every block are
+/// collected in feed order, and the name comes from the first block that has one. Only
+/// DTSTART is read, so all-day events without DTEND (or DURATION) parse like any other.
+pub fn parse_ical(content: &str) -> Result<Calendar, ParseError> {
+    let content = normalize_line_endings(content);
     let buf = BufReader::new(content.as_bytes());
     let parser = IcalParser::new(buf);
 
+    let mut name = None;
     let mut events = Vec::new();
 
     for line in parser {
         let mut calendar = line?;
 
-        // Optimization: consume events instead of iterating with reference
-        for event in std::mem::take(&mut calendar.events) {
-            let (date, summary) = extract_event_data(event)?;
-            let waste_types = normalize_waste_types(&summary);
-
-            events.push(PickupEvent { date, waste_types });
+        if name.is_none() {
+            name = find_property(&calendar.properties, "X-WR-CALNAME").or_else(|| {
+                calendar
+                    .events
+                    .first()
+                    .and_then(|e| find_property(&e.properties, "LOCATION"))
+            });
         }
+
+        // Optimization: consume events instead of iterating with reference.
+        // A malformed event is skipped so it doesn't cost us the rest of the feed.
+        let parsed = std::mem::take(&mut calendar.events)
+            .into_iter()
+            .map(extract_event_data)
+            .filter_map(|result| {
+                result
+                    .map_err(|e| warn!("Skipping malformed calendar event: {}", e))
+                    .ok()
+            });
+        events.extend(parsed);
     }
 
-    Ok(events)
+    Ok(Calendar { name, events })
+}
+
+/// Returns the trimmed, non-empty value of the first property called `name`.
+fn find_property(properties: &[ical::property::Property], name: &str) -> Option<String> {
+    properties
+        .iter()
+        .find(|p| p.name.eq_ignore_ascii_case(name))
+        .and_then(|p| p.value.as_deref())
+        .map(unescape_text)
+        .filter(|v| !v.is_empty())
 }
 
-fn extract_event_data(event: IcalEvent) -> Result<(NaiveDate, String), ParseError> {
+/// Undoes iCal TEXT escaping (`\,`, `\;`, `\\`, `\n`) and trims the result.
+fn unescape_text(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') | Some('N') => out.push(' '),
+                Some(other) => out.push(other),
+                None => {}
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out.trim().to_string()
+}
+
+/// Reads the event's date, waste types and UID.
+fn extract_event_data(event: IcalEvent) -> Result<PickupEvent, ParseError> {
     let mut date = None;
+    let mut all_day = true;
     let mut summary = None;
+    let mut description = None;
+    let mut uid = None;
 
     // Optimization: consume properties to move strings instead of cloning
     for prop in event.properties {
-        match prop.name.as_str() {
-            "DTSTART" => {
-                if let Some(val) = prop.value {
-                    // Handle YYYYMMDD
-                    // Sometimes it might be longer or have timezone, but usually for city waste it's YYYYMMDD
-                    // val is owned, but we need to split it.
-                    let val_clean = val.split('T').next().unwrap_or(&val);
-                    date = Some(
-                        NaiveDate::parse_from_str(val_clean, "%Y%m%d")
-                            .map_err(|_| ParseError::InvalidDate(val.clone()))?,
-                    );
-                }
-            }
-            "SUMMARY" => {
-                // Move the value instead of cloning
-                summary = prop.value;
+        // Parameters normally end up in `prop.params`, but strip anything after `;`
+        // in case a feed's property line was not split by the parser.
+        let name = prop.name.split(';').next().unwrap_or_default();
+        if name.eq_ignore_ascii_case("DTSTART") {
+            if let Some(val) = prop.value {
+                date = Some(parse_dtstart(&val)?);
+                all_day = !val.contains('T');
             }
-            _ => {}
+        } else if name.eq_ignore_ascii_case("SUMMARY") {
+            // Move the value instead of cloning
+            summary = prop.value;
+        } else if name.eq_ignore_ascii_case("DESCRIPTION") {
+            description = prop.value;
+        } else if name.eq_ignore_ascii_case("UID") {
+            uid = prop
+                .value
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty());
         }
     }
 
-    Ok((
-        date.ok_or(ParseError::MissingDate)?,
-        summary.ok_or(ParseError::MissingSummary)?,
-    ))
+    // Some feeds only name the waste type in DESCRIPTION
+    let summary = summary.or_else(|| description.map(|d| unescape_text(&d)));
+
+    Ok(PickupEvent {
+        date: date.ok_or(ParseError::MissingDate)?,
+        waste_types: normalize_waste_types(&summary.ok_or(ParseError::MissingSummary)?),
+        all_day,
+        uid,
+    })
+}
+
+/// Parses the date part of a DTSTART value.
+///
+/// Accepts plain dates (`20231027`, `VALUE=DATE`) as well as local, UTC and
+/// TZID-qualified date-times (`20231027T060000`, `20231027T060000Z`). The time
+/// component is discarded since pickups are tracked per day.
+fn parse_dtstart(value: &str) -> Result<NaiveDate, ParseError> {
+    let value = value.trim();
+    let date_part = value.split('T').next().unwrap_or(value);
+    NaiveDate::parse_from_str(date_part, "%Y%m%d")
+        .map_err(|_| ParseError::InvalidDate(value.to_string()))
 }
 
 #[cfg(test)]
@@ -173,6 +536,55 @@ mod tests {
         assert!(!is_valid_location_id("a".repeat(21).as_str())); // Too long
     }
 
+    #[test]
+    fn test_extract_location_id() {
+        let found = |text| extract_location_id(text).ok();
+        assert_eq!(found(" 12345\n").as_deref(), Some("12345"));
+        assert_eq!(found("LOC123").as_deref(), Some("LOC123"));
+        assert_eq!(found("Standort-ID: 12345").as_deref(), Some("12345"));
+        assert_eq!(
+            found(
+                "https://stadtplan.dresden.de/project/cardo3Apps/IDU_DDStadtplan/abfall/\
+                 ical.ashx?STANDORT=12345&DATUM_VON=07.06.2024&DATUM_BIS=05.09.2024"
+            )
+            .as_deref(),
+            Some("12345")
+        );
+        // Labelled IDs are taken as they are, letters included
+        assert_eq!(found("Standort-ID: LOC123").as_deref(), Some("LOC123"));
+        assert_eq!(
+            found("standort id = 12345, Hauptstraße").as_deref(),
+            Some("12345")
+        );
+        // The same ID twice is still one candidate
+        assert_eq!(
+            found("Standort: 12345 / ?standort=12345").as_deref(),
+            Some("12345")
+        );
+
+        assert_eq!(
+            extract_location_id("Standort: 12345, Standort: 67890"),
+            Err(LocationIdError::Ambiguous)
+        );
+        // Numbers in unlabelled text aren't guessed at
+        assert_eq!(
+            extract_location_id("Hauptstraße 12"),
+            Err(LocationIdError::Missing)
+        );
+        assert_eq!(
+            extract_location_id("12345 or 67890"),
+            Err(LocationIdError::Missing)
+        );
+        assert_eq!(
+            extract_location_id("my street"),
+            Err(LocationIdError::Missing)
+        );
+        assert_eq!(
+            extract_location_id("?standort=LOC-1"),
+            Err(LocationIdError::Missing)
+        );
+    }
+
     #[test]
     fn test_normalize_waste_types() {
         let input = "Bio, Rest";
@@ -204,6 +616,103 @@ mod tests {
         assert_eq!(output, vec![WasteType::Bio, WasteType::Rest]);
     }
 
+    #[test]
+    fn test_waste_type_qualifiers() {
+        let parse = |s: &str| s.parse::<WasteType>().unwrap();
+        assert_eq!(parse("Biotonne (Abholung)"), WasteType::Bio);
+        assert_eq!(parse("Restmüll 14-täglich"), WasteType::Rest);
+        assert_eq!(parse("Papier 4-wöchentlich"), WasteType::Paper);
+        assert_eq!(parse("Gelbe Tonne (2 Behälter) Leerung"), WasteType::Yellow);
+        assert_eq!(parse("Sperrmüll (nur auf Anmeldung"), WasteType::Bulky);
+        // Unknown names still keep their full text
+        assert_eq!(
+            parse("Grünschnitt (Abholung)"),
+            WasteType::Other("Grünschnitt (Abholung)".to_string())
+        );
+        assert_eq!(parse("Abholung"), WasteType::Other("Abholung".to_string()));
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let grass = WasteType::Other("Grünschnitt".to_string());
+        assert_eq!(
+            serde_json::to_string(&WasteType::Paper).unwrap(),
+            r#""Papier""#
+        );
+        assert_eq!(serde_json::to_string(&grass).unwrap(), r#""Grünschnitt""#);
+
+        let event = PickupEvent {
+            date: NaiveDate::from_ymd_opt(2024, 6, 7).unwrap(),
+            waste_types: vec![WasteType::Bio, WasteType::ChristmasTree, grass],
+            all_day: true,
+            uid: Some("bio-1@stadtplan.dresden.de".to_string()),
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(serde_json::from_str::<PickupEvent>(&json).unwrap(), event);
+    }
+
+    #[test]
+    fn test_normalize_waste_types_separators() {
+        let expected = vec![WasteType::Bio, WasteType::Rest];
+        assert_eq!(normalize_waste_types("Bio; Rest"), expected);
+        assert_eq!(normalize_waste_types("Bio und Rest"), expected);
+        assert_eq!(normalize_waste_types("Bio UND Rest;"), expected);
+        assert_eq!(normalize_waste_types("Bio & Rest"), expected);
+        assert_eq!(
+            normalize_waste_types("Gelbe Tonne sowie Blaue Tonne, Bio"),
+            vec![WasteType::Yellow, WasteType::Paper, WasteType::Bio]
+        );
+        // Only whole words count as conjunctions
+        assert_eq!(
+            normalize_waste_types("Grundstücksreinigung"),
+            vec![WasteType::Other("Grundstücksreinigung".to_string())]
+        );
+        assert!(normalize_waste_types("und").is_empty());
+    }
+
+    #[test]
+    fn test_bulky_and_hazardous_types() {
+        let output = normalize_waste_types("Sperrmüll, Schadstoffmobil");
+        assert_eq!(output, vec![WasteType::Bulky, WasteType::Hazardous]);
+
+        // as_str round-trips through from_str
+        for waste in WasteType::supported_types() {
+            assert_eq!(waste.as_str().parse::<WasteType>().unwrap(), waste);
+        }
+
+        // Infrequent types are opt-in
+        let defaults = WasteType::default_subscriptions();
+        assert!(!defaults.contains(&WasteType::Bulky));
+        assert!(!defaults.contains(&WasteType::Hazardous));
+    }
+
+    #[test]
+    fn test_waste_type_label() {
+        assert_eq!(WasteType::Bio.label(), "🟤 Bio");
+        assert_eq!(WasteType::Paper.label(), "🔵 Papier");
+        assert_eq!(WasteType::Other("Laub".to_string()).label(), "🗑️ Laub");
+    }
+
+    #[test]
+    fn test_waste_type_description() {
+        assert!(WasteType::Yellow.description().contains("Plastik"));
+        for waste in WasteType::supported_types() {
+            assert!(!waste.description().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_normalize_waste_types_dedupes() {
+        let input = "Bio, Rest, Bio";
+        let output = normalize_waste_types(input);
+        assert_eq!(output, vec![WasteType::Bio, WasteType::Rest]);
+
+        // Aliases of the same type collapse as well
+        let input = "Biotonne, Bio, Gelber Sack, Gelb";
+        let output = normalize_waste_types(input);
+        assert_eq!(output, vec![WasteType::Bio, WasteType::Yellow]);
+    }
+
     #[test]
     fn test_parse_ical() {
         let ical_content = "BEGIN:VCALENDAR
@@ -217,7 +726,9 @@ SUMMARY:Gelb
 END:VEVENT
 END:VCALENDAR";
 
-        let events = parse_ical(ical_content).unwrap();
+        let calendar = parse_ical(ical_content).unwrap();
+        assert_eq!(calendar.name, None);
+        let events = calendar.events;
         assert_eq!(events.len(), 2);
         assert_eq!(
             events[0].date,
@@ -230,4 +741,348 @@ END:VCALENDAR";
         );
         assert_eq!(events[1].waste_types, vec![WasteType::Yellow]);
     }
+
+    #[test]
+    fn test_parse_ical_multiple_calendars() {
+        let ical_content = "BEGIN:VCALENDAR
+BEGIN:VEVENT
+DTSTART;VALUE=DATE:20231027
+DTEND;VALUE=DATE:20231028
+SUMMARY:Bio
+END:VEVENT
+END:VCALENDAR
+BEGIN:VCALENDAR
+X-WR-CALNAME:Musterstraße 1
+BEGIN:VEVENT
+DTSTART;VALUE=DATE:20231030
+SUMMARY:Papier
+END:VEVENT
+END:VCALENDAR
+BEGIN:VCALENDAR
+BEGIN:VEVENT
+DTSTART:20231102
+SUMMARY:Gelb
+END:VEVENT
+END:VCALENDAR";
+
+        let calendar = parse_ical(ical_content).unwrap();
+        assert_eq!(calendar.name.as_deref(), Some("Musterstraße 1"));
+        let found: Vec<_> = calendar
+            .events
+            .iter()
+            .map(|e| (e.date.format("%Y%m%d").to_string(), e.waste_types.clone()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("20231027".to_string(), vec![WasteType::Bio]),
+                // No DTEND in these two
+                ("20231030".to_string(), vec![WasteType::Paper]),
+                ("20231102".to_string(), vec![WasteType::Yellow]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_ical_datetime_dtstart() {
+        let ical_content = "BEGIN:VCALENDAR
+BEGIN:VEVENT
+DTSTART:20231027T060000Z
+SUMMARY:Bio
+END:VEVENT
+BEGIN:VEVENT
+DTSTART;TZID=Europe/Berlin:20231028T060000
+SUMMARY:Rest
+END:VEVENT
+BEGIN:VEVENT
+DTSTART;VALUE=DATE:20231029
+SUMMARY:Gelb
+END:VEVENT
+END:VCALENDAR";
+
+        let events = parse_ical(ical_content).unwrap().events;
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0].date,
+            NaiveDate::from_ymd_opt(2023, 10, 27).unwrap()
+        );
+        assert_eq!(
+            events[1].date,
+            NaiveDate::from_ymd_opt(2023, 10, 28).unwrap()
+        );
+        assert_eq!(
+            events[2].date,
+            NaiveDate::from_ymd_opt(2023, 10, 29).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_dtstart_variants() {
+        let expected = NaiveDate::from_ymd_opt(2023, 10, 27).unwrap();
+        assert_eq!(parse_dtstart("20231027").unwrap(), expected);
+        assert_eq!(parse_dtstart("20231027T060000").unwrap(), expected);
+        assert_eq!(parse_dtstart("20231027T060000Z").unwrap(), expected);
+        assert_eq!(parse_dtstart(" 20231027 ").unwrap(), expected);
+
+        assert!(matches!(
+            parse_dtstart("2023-10-27"),
+            Err(ParseError::InvalidDate(_))
+        ));
+        assert!(matches!(parse_dtstart(""), Err(ParseError::InvalidDate(_))));
+    }
+
+    fn every(start: (i32, u32, u32), days: i64, count: i64) -> Vec<NaiveDate> {
+        let start = NaiveDate::from_ymd_opt(start.0, start.1, start.2).unwrap();
+        (0..count)
+            .map(|i| start + chrono::Duration::days(i * days))
+            .collect()
+    }
+
+    #[test]
+    fn test_looks_like_html() {
+        assert!(looks_like_html(
+            "<!DOCTYPE html><html><body>Fehler</body></html>"
+        ));
+        assert!(looks_like_html("\u{feff}\r\n  <HTML lang=\"de\">"));
+        assert!(!looks_like_html("BEGIN:VCALENDAR\nEND:VCALENDAR"));
+        assert!(!looks_like_html("<?xml version=\"1.0\"?>"));
+        assert!(!looks_like_html(""));
+    }
+
+    #[test]
+    fn test_typical_interval() {
+        assert_eq!(typical_interval(&every((2024, 1, 2), 14, 6)), Some(14));
+        assert_eq!(typical_interval(&every((2024, 1, 2), 7, 6)), Some(7));
+        // Too few pickups to tell
+        assert_eq!(typical_interval(&every((2024, 1, 2), 14, 3)), None);
+        // No interval repeats
+        let irregular: Vec<_> = [(1, 2), (1, 5), (1, 20), (2, 28)]
+            .iter()
+            .map(|(m, d)| NaiveDate::from_ymd_opt(2024, *m, *d).unwrap())
+            .collect();
+        assert_eq!(typical_interval(&irregular), None);
+    }
+
+    #[test]
+    fn test_find_pickup_gaps() {
+        // Biweekly Bio with the pickup on 13.02. missing from the feed
+        let mut dates = every((2024, 1, 2), 14, 8);
+        let missing = dates.remove(3);
+        assert_eq!(missing, NaiveDate::from_ymd_opt(2024, 2, 13).unwrap());
+        assert_eq!(
+            find_pickup_gaps(&dates),
+            vec![PickupGap {
+                after: NaiveDate::from_ymd_opt(2024, 1, 30).unwrap(),
+                before: NaiveDate::from_ymd_opt(2024, 2, 27).unwrap(),
+                interval_days: 14,
+            }]
+        );
+
+        // A complete feed, in any order and with duplicates, has no gaps
+        let mut dates = every((2024, 1, 2), 14, 8);
+        dates.reverse();
+        dates.push(dates[0]);
+        assert!(find_pickup_gaps(&dates).is_empty());
+
+        // Weekly Rest moved by two days over a holiday isn't a gap
+        let mut dates = every((2024, 12, 3), 7, 6);
+        dates[3] += chrono::Duration::days(2);
+        assert!(find_pickup_gaps(&dates).is_empty());
+
+        // Without a rhythm nothing is flagged
+        assert!(find_pickup_gaps(&every((2024, 1, 2), 14, 2)).is_empty());
+    }
+
+    #[test]
+    fn test_parse_ical_uid() {
+        let ical_content = "BEGIN:VCALENDAR
+BEGIN:VEVENT
+UID:bio-2023-10-27@stadtplan.dresden.de
+DTSTART:20231027
+SUMMARY:Bio
+END:VEVENT
+BEGIN:VEVENT
+UID: 
+DTSTART:20231028
+SUMMARY:Rest
+END:VEVENT
+END:VCALENDAR";
+        let events = parse_ical(ical_content).unwrap().events;
+        assert_eq!(
+            events[0].uid.as_deref(),
+            Some("bio-2023-10-27@stadtplan.dresden.de")
+        );
+        // A blank UID is as good as none
+        assert_eq!(events[1].uid, None);
+    }
+
+    #[test]
+    fn test_parse_ical_line_endings() {
+        let lines = [
+            "BEGIN:VCALENDAR",
+            "X-WR-CALNAME:Dresden",
+            "BEGIN:VEVENT",
+            "UID:bio-1@stadtplan.dresden.de",
+            "DTSTART;VALUE=DATE:20231027",
+            "SUMMARY:Bio",
+            " , Rest",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "DTSTART:20231103",
+            "SUMMARY:Gelbe Tonne",
+            "END:VEVENT",
+            "END:VCALENDAR",
+        ];
+        let expected = parse_ical(&lines.join("\n")).unwrap();
+        assert_eq!(expected.name.as_deref(), Some("Dresden"));
+        assert_eq!(expected.events.len(), 2);
+        // The folded SUMMARY line is joined back up
+        assert_eq!(
+            expected.events[0].waste_types,
+            vec![WasteType::Bio, WasteType::Rest]
+        );
+
+        let mixed = format!(
+            "{}\r\n{}\r{}",
+            lines[..4].join("\r\n"),
+            lines[4],
+            lines[5..].join("\n")
+        );
+        let variants = [
+            lines.join("\r\n"),
+            lines.join("\r\n") + "\r\n",
+            lines.join("\r"),
+            mixed,
+        ];
+        for content in variants {
+            assert_eq!(parse_ical(&content).unwrap(), expected, "{:?}", content);
+        }
+    }
+
+    #[test]
+    fn test_parse_ical_all_day() {
+        let ical_content = "BEGIN:VCALENDAR
+BEGIN:VEVENT
+DTSTART;VALUE=DATE:20231027
+SUMMARY:Bio
+END:VEVENT
+BEGIN:VEVENT
+DTSTART;TZID=Europe/Berlin:20231027T060000
+SUMMARY:Rest
+END:VEVENT
+END:VCALENDAR";
+        let events = parse_ical(ical_content).unwrap().events;
+        assert!(events[0].all_day);
+        assert!(!events[1].all_day);
+        // Both count for the same day
+        assert_eq!(events[0].date, events[1].date);
+    }
+
+    #[test]
+    fn test_parse_ical_location_name() {
+        let ical_content = "BEGIN:VCALENDAR
+X-WR-CALNAME:Musterstraße 1
+BEGIN:VEVENT
+DTSTART:20231027
+SUMMARY:Bio
+LOCATION:Somewhere else
+END:VEVENT
+END:VCALENDAR";
+        let calendar = parse_ical(ical_content).unwrap();
+        assert_eq!(calendar.name.as_deref(), Some("Musterstraße 1"));
+
+        // Falls back to the event location when the calendar has no name
+        let ical_content = "BEGIN:VCALENDAR
+BEGIN:VEVENT
+DTSTART:20231027
+SUMMARY:Bio
+LOCATION:Musterstraße 1\\, Dresden
+END:VEVENT
+END:VCALENDAR";
+        let calendar = parse_ical(ical_content).unwrap();
+        assert_eq!(calendar.name.as_deref(), Some("Musterstraße 1, Dresden"));
+    }
+
+    #[test]
+    fn test_parse_ical_description_fallback() {
+        let ical_content = "BEGIN:VCALENDAR
+BEGIN:VEVENT
+DTSTART:20231027
+DESCRIPTION:Bio\\, Papier
+END:VEVENT
+BEGIN:VEVENT
+DTSTART:20231028
+SUMMARY:Gelb
+DESCRIPTION:Abholung Gelbe Tonne
+END:VEVENT
+END:VCALENDAR";
+        let events = parse_ical(ical_content).unwrap().events;
+        assert_eq!(
+            events[0].waste_types,
+            vec![WasteType::Bio, WasteType::Paper]
+        );
+        // SUMMARY wins when both are present
+        assert_eq!(events[1].waste_types, vec![WasteType::Yellow]);
+
+        let ical_content = "BEGIN:VCALENDAR
+BEGIN:VEVENT
+DTSTART:20231027
+END:VEVENT
+END:VCALENDAR";
+        let mut calendar = IcalParser::new(BufReader::new(ical_content.as_bytes()))
+            .next()
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            extract_event_data(calendar.events.remove(0)),
+            Err(ParseError::MissingSummary)
+        ));
+    }
+
+    #[test]
+    fn test_parse_ical_skips_malformed_events() {
+        let ical_content = "BEGIN:VCALENDAR
+BEGIN:VEVENT
+DTSTART:20231027
+SUMMARY:Bio
+END:VEVENT
+BEGIN:VEVENT
+DTSTART:not-a-date
+SUMMARY:Rest
+END:VEVENT
+BEGIN:VEVENT
+DTSTART:20231028
+SUMMARY:Gelb
+END:VEVENT
+END:VCALENDAR";
+        let events = parse_ical(ical_content).unwrap().events;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].waste_types, vec![WasteType::Bio]);
+        assert_eq!(events[1].waste_types, vec![WasteType::Yellow]);
+    }
+
+    #[test]
+    fn test_waste_type_fuzzy_names() {
+        assert_eq!(WasteType::from_str("restmuell").unwrap(), WasteType::Rest);
+        assert_eq!(WasteType::from_str("Restmüll").unwrap(), WasteType::Rest);
+        assert_eq!(WasteType::from_str("RESTMÜLL").unwrap(), WasteType::Rest);
+        assert_eq!(
+            WasteType::from_str("gelbe tonne").unwrap(),
+            WasteType::Yellow
+        );
+        assert_eq!(
+            WasteType::from_str(" Gelbe   Tonne ").unwrap(),
+            WasteType::Yellow
+        );
+        assert_eq!(WasteType::from_str("Sperrmuell").unwrap(), WasteType::Bulky);
+        assert_eq!(
+            WasteType::from_str("weihnachtsbaeume").unwrap(),
+            WasteType::ChristmasTree
+        );
+        // Unknown names keep their original spelling
+        assert_eq!(
+            WasteType::from_str("Grünschnitt").unwrap(),
+            WasteType::Other("Grünschnitt".to_string())
+        );
+    }
 }